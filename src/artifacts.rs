@@ -1,6 +1,12 @@
 
 pub const USN_JRNL_PATH : &'static str = r"%SYSTEMDRIVE%\$Extend\$UsnJrnl:$J";
 pub const USN_JRNL_MAX_PATH : &'static str = r"%SYSTEMDRIVE%\$Extend\$UsnJrnl:$MAX";
+pub const ALL_DRIVES_MFT_PATH : &str = r"%ALLDRIVES%\$MFT";
+pub const ALL_DRIVES_USN_JRNL_PATH : &str = r"%ALLDRIVES%\$Extend\$UsnJrnl:$J";
+pub const ALL_DRIVES_USN_JRNL_MAX_PATH : &str = r"%ALLDRIVES%\$Extend\$UsnJrnl:$MAX";
+pub const NTFS_DRIVES_MFT_PATH : &str = r"%NTFSDRIVES%\$MFT";
+pub const NTFS_DRIVES_USN_JRNL_PATH : &str = r"%NTFSDRIVES%\$Extend\$UsnJrnl:$J";
+pub const NTFS_DRIVES_USN_JRNL_MAX_PATH : &str = r"%NTFSDRIVES%\$Extend\$UsnJrnl:$MAX";
 
 pub fn get_default_collection_paths() -> Vec<String> {
     let mut vc = Vec::with_capacity(1_000);
@@ -8,7 +14,7 @@ pub fn get_default_collection_paths() -> Vec<String> {
     vc
 }
 
-pub const DEFAULT_COLLECTION_PATHS : [&'static str; 42] = [
+pub const DEFAULT_COLLECTION_PATHS : [&'static str; 53] = [
     r"%SYSTEMDRIVE%\$LogFile",
     r"%SYSTEMDRIVE%\$MFT",
     r"%SYSTEMROOT%\Tasks\**",
@@ -51,4 +57,15 @@ pub const DEFAULT_COLLECTION_PATHS : [&'static str; 42] = [
     r"%USERHOME%\AppData\Local\ConnectedDevicesPlatform\**",
     r"%USERHOME%\AppData\Local\Google\Chrome\User Data\Default\History\**",
     r"%USERHOME%\AppData\Local\Microsoft\Edge\User Data\Default\History\**",
+    r"%PREVIOUSROOT%\System32\config\SAM",
+    r"%PREVIOUSROOT%\System32\config\SYSTEM",
+    r"%PREVIOUSROOT%\System32\config\SECURITY",
+    r"%PREVIOUSROOT%\System32\config\SOFTWARE",
+    r"%PREVIOUSROOT%\System32\winevt\Logs\**",
+    r"%PREVIOUSROOT%\Prefetch\**",
+    r"%PREVIOUSROOT%\Appcompat\Programs\**",
+    r"%PREVIOUSROOT%\inf\setupapi.dev.log",
+    r"%PREVIOUSUSERHOME%\NTUser.DAT",
+    r"%PREVIOUSUSERHOME%\AppData\Roaming\Microsoft\Windows\Recent\**",
+    r"%PREVIOUSUSERHOME%\AppData\Local\Microsoft\Windows\UsrClass.dat",
 ];
\ No newline at end of file
@@ -0,0 +1,577 @@
+//! Sources the `TriageCollector` reads the files of the examined system from. Every backend
+//! receives Windows paths (`C:\Windows\System32\config\SYSTEM`) so the same collection targets
+//! work in a live Windows system and in a volume mounted elsewhere.
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+#[cfg(windows)]
+use std::sync::Arc;
+
+use forensic_rs::prelude::{ForensicError, ForensicResult, RegistryReader};
+
+use crate::{
+    helpers::{get_drive_and_disk, system_time_to_filetime},
+    mount_info::MountInfo,
+    ntfs::{path_components, NtfsVolume},
+    offline_registry::OfflineRegistry,
+    upcase::UpCase,
+    volume::{OpenedFile, RawDirEntry, RawFileSystem, VolumeReader},
+};
+#[cfg(windows)]
+use crate::{
+    raw_file::RawFile,
+    sys_vars::{drive_type, mounted_devices},
+    volume::{open_live_volume, open_raw_file_system, read_file_system_type, AlignedReader, FileSystemType},
+};
+#[cfg(windows)]
+use frnsc_liveregistry_rs::LiveRegistryReader;
+
+/// Kind of device of a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriveType {
+    Unknown,
+    Removable,
+    Fixed,
+    Network,
+    Optical,
+    RamDisk,
+}
+
+impl DriveType {
+    /// Volumes of the examined machine: not optical or network drives
+    pub fn is_local(&self) -> bool {
+        !matches!(self, DriveType::Network | DriveType::Optical)
+    }
+}
+
+/// Volume mounted in the examined system. Values that cannot be known are empty or 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountedVolume {
+    /// Root of the volume: `C:\`, `D:\`...
+    pub root: String,
+    pub drive_type: DriveType,
+    /// Name of the file system as Windows reports it: `NTFS`, `FAT32`, `exFAT`, `CDFS`...
+    pub file_system: String,
+    pub serial_number: u64,
+    pub label: String,
+    pub cluster_size: u64,
+    pub total_size: u64,
+    pub free_size: u64,
+}
+
+impl MountedVolume {
+    /// Fixed volume with an unknown file system
+    pub fn new(root: &str) -> Self {
+        Self {
+            root: root.to_string(),
+            drive_type: DriveType::Fixed,
+            file_system: String::new(),
+            serial_number: 0,
+            label: String::new(),
+            cluster_size: 0,
+            total_size: 0,
+            free_size: 0,
+        }
+    }
+
+    pub fn with_drive_type(mut self, drive_type: DriveType) -> Self {
+        self.drive_type = drive_type;
+        self
+    }
+
+    pub fn with_file_system(mut self, file_system: &str) -> Self {
+        self.file_system = file_system.to_string();
+        self
+    }
+
+    /// The volume is NTFS, or its file system is unknown and it may be
+    pub fn may_be_ntfs(&self) -> bool {
+        self.file_system.is_empty() || self.file_system.eq_ignore_ascii_case("NTFS")
+    }
+}
+
+/// Volumes of a backend, or the volumes to collect in place of the volumes of the backend
+pub trait VolumeSource: Send + Sync {
+    /// Root of each volume: `C:\`, `D:\`...
+    fn volumes(&self) -> Vec<String>;
+    /// Kind of device of a volume given its root
+    fn drive_type(&self, _volume: &str) -> DriveType {
+        DriveType::Fixed
+    }
+    /// Drive type and file system of each volume. Only the drive type is known by default.
+    fn mounted_volumes(&self) -> Vec<MountedVolume> {
+        self.volumes()
+            .iter()
+            .map(|v| MountedVolume::new(v).with_drive_type(self.drive_type(v)))
+            .collect()
+    }
+}
+
+impl<F: Fn() -> Vec<String> + Send + Sync> VolumeSource for F {
+    fn volumes(&self) -> Vec<String> {
+        self()
+    }
+}
+
+/// Source of the files to collect. Its volumes are the ones that can be collected.
+pub trait CollectionBackend: VolumeSource {
+    /// Opens a file given its Windows path
+    fn open(&self, path: &str) -> ForensicResult<OpenedFile>;
+    /// Lists a directory given its Windows path
+    fn read_dir(&self, path: &str) -> ForensicResult<Vec<RawDirEntry>>;
+    /// Opens the raw content of the volume of `path`, used to parse shadow copies and removable media
+    fn open_volume(&self, _path: &str) -> ForensicResult<Box<dyn VolumeReader>> {
+        Err(ForensicError::missing_str("Raw volumes are not available in this backend"))
+    }
+    /// Registry of the examined system, if the backend can access it
+    fn registry(&self) -> Option<Box<dyn RegistryReader>> {
+        None
+    }
+    /// Table used to compare the names of the volume of `path` ignoring case
+    fn upcase(&self, _path: &str) -> UpCase {
+        UpCase::default()
+    }
+    /// Identifier of the file inside its volume, like the inode number, used to find hard links.
+    /// `None` when the backend cannot tell.
+    fn file_id(&self, _path: &str) -> Option<u64> {
+        None
+    }
+}
+
+/// Collects from the running Windows system. NTFS files are read from their clusters, so locked
+/// files can be copied, and FAT or exFAT volumes are read with the crate parsers. NTFS folders are
+/// listed with their $I30 index, so folders denied by their ACLs are also walked.
+#[cfg(windows)]
+#[derive(Default)]
+pub struct LiveBackend {
+    file_systems: Mutex<BTreeMap<String, Option<FileSystemType>>>,
+    ntfs_volumes: Mutex<BTreeMap<String, Option<LiveNtfsVolume>>>,
+}
+
+#[cfg(windows)]
+type LiveNtfsVolume = Arc<Mutex<NtfsVolume<AlignedReader<std::fs::File>>>>;
+
+#[cfg(windows)]
+impl LiveBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn file_system(&self, disk: &str) -> Option<FileSystemType> {
+        let mut file_systems = self.file_systems.lock().unwrap();
+        *file_systems.entry(disk.to_uppercase()).or_insert_with(|| {
+            let mut volume = open_live_volume(disk).ok()?;
+            read_file_system_type(&mut volume).ok()?
+        })
+    }
+
+    /// Parsed NTFS volume of `disk`, kept to list its folders
+    fn ntfs_volume(&self, disk: &str) -> Option<LiveNtfsVolume> {
+        if self.file_system(disk) != Some(FileSystemType::Ntfs) {
+            return None;
+        }
+        let mut ntfs_volumes = self.ntfs_volumes.lock().unwrap();
+        ntfs_volumes
+            .entry(disk.to_uppercase())
+            .or_insert_with_key(|disk| {
+                let volume = open_live_volume(disk).and_then(NtfsVolume::new).ok()?;
+                Some(Arc::new(Mutex::new(volume)))
+            })
+            .clone()
+    }
+}
+
+#[cfg(windows)]
+impl VolumeSource for LiveBackend {
+    fn volumes(&self) -> Vec<String> {
+        mounted_devices().into_iter().map(|v| v.root).collect()
+    }
+
+    fn drive_type(&self, volume: &str) -> DriveType {
+        drive_type(volume)
+    }
+
+    fn mounted_volumes(&self) -> Vec<MountedVolume> {
+        mounted_devices()
+    }
+}
+
+#[cfg(windows)]
+impl CollectionBackend for LiveBackend {
+    fn open(&self, path: &str) -> ForensicResult<OpenedFile> {
+        let (_, disk) = get_drive_and_disk(path)?;
+        match self.file_system(&disk) {
+            Some(FileSystemType::Fat) | Some(FileSystemType::ExFat) => {
+                open_raw_file_system(open_live_volume(path)?)?.into_file(path)
+            }
+            _ => {
+                let file = RawFile::open(path)?;
+                Ok(OpenedFile {
+                    size: file.file_size,
+                    reader: Box::new(file),
+                })
+            }
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+        let (_, disk) = get_drive_and_disk(path)?;
+        if let Some(volume) = self.ntfs_volume(&disk) {
+            match volume.lock().unwrap().list_dir(path) {
+                Ok(entries) => return Ok(entries),
+                Err(err) => println!("Error listing {} from the $I30 index: {:?}", path, err),
+            }
+        }
+        read_local_dir(Path::new(path))
+    }
+
+    fn open_volume(&self, path: &str) -> ForensicResult<Box<dyn VolumeReader>> {
+        Ok(Box::new(open_live_volume(path)?))
+    }
+
+    fn registry(&self) -> Option<Box<dyn RegistryReader>> {
+        Some(Box::new(LiveRegistryReader::new()))
+    }
+
+    fn upcase(&self, path: &str) -> UpCase {
+        let volume = get_drive_and_disk(path).ok().and_then(|(_, disk)| self.ntfs_volume(&disk));
+        match volume {
+            Some(volume) => volume.lock().unwrap().upcase.clone(),
+            None => UpCase::default(),
+        }
+    }
+
+    fn file_id(&self, path: &str) -> Option<u64> {
+        let (_, disk) = get_drive_and_disk(path).ok()?;
+        let volume = self.ntfs_volume(&disk)?;
+        let id = volume.lock().unwrap().find(path).ok();
+        id
+    }
+}
+
+/// Collects from an NTFS volume read directly, like a partition of a disk image. Folders are
+/// listed with their $I30 index, so the walk sees every entry whatever its ACL.
+pub struct NtfsBackend<R: Read + Seek, F> {
+    letter: char,
+    open_reader: F,
+    /// Volume parsed once, used to find the files. Each opened file reads its clusters with a new
+    /// reader so the worker threads do not wait for each other.
+    volume: Mutex<NtfsVolume<R>>,
+}
+
+impl<R, F> NtfsBackend<R, F>
+where
+    R: Read + Seek + Send + 'static,
+    F: Fn() -> ForensicResult<R> + Send + Sync,
+{
+    /// Maps the volume to the drive `letter`. `open_reader` must return a new reader of the raw
+    /// volume each time it is called, it is used to open the collected files.
+    pub fn new(letter: char, open_reader: F) -> ForensicResult<Self> {
+        let volume = NtfsVolume::new(open_reader()?)?;
+        Ok(Self {
+            letter: letter.to_ascii_uppercase(),
+            open_reader,
+            volume: Mutex::new(volume),
+        })
+    }
+
+    fn check_volume(&self, path: &str) -> ForensicResult<()> {
+        let (_, disk) = get_drive_and_disk(path)?;
+        if !disk.starts_with(|v: char| v.to_ascii_uppercase() == self.letter) {
+            return Err(ForensicError::missing_string(format!("Volume {} is not mounted", disk)));
+        }
+        Ok(())
+    }
+}
+
+impl<R, F> VolumeSource for NtfsBackend<R, F>
+where
+    R: Read + Seek + Send + 'static,
+    F: Fn() -> ForensicResult<R> + Send + Sync,
+{
+    fn volumes(&self) -> Vec<String> {
+        vec![format!("{}:\\", self.letter)]
+    }
+
+    fn mounted_volumes(&self) -> Vec<MountedVolume> {
+        let boot = self.volume.lock().unwrap().boot.clone();
+        let mut volume = MountedVolume::new(&format!("{}:\\", self.letter)).with_file_system("NTFS");
+        volume.serial_number = boot.serial_number;
+        volume.cluster_size = boot.cluster_size;
+        volume.total_size = boot.total_sectors * boot.bytes_per_sector;
+        vec![volume]
+    }
+}
+
+impl<R, F> CollectionBackend for NtfsBackend<R, F>
+where
+    R: Read + Seek + Send + 'static,
+    F: Fn() -> ForensicResult<R> + Send + Sync,
+{
+    fn open(&self, path: &str) -> ForensicResult<OpenedFile> {
+        self.check_volume(path)?;
+        let reader = (self.open_reader)()?;
+        let mut volume = self.volume.lock().unwrap();
+        let stream = volume.data_stream(path)?;
+        let file = volume.with_reader(reader).into_stream(stream);
+        Ok(OpenedFile {
+            size: file.size(),
+            reader: Box::new(file),
+        })
+    }
+
+    fn read_dir(&self, path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+        self.check_volume(path)?;
+        self.volume.lock().unwrap().list_dir(path)
+    }
+
+    fn open_volume(&self, path: &str) -> ForensicResult<Box<dyn VolumeReader>> {
+        self.check_volume(path)?;
+        Ok(Box::new((self.open_reader)()?))
+    }
+
+    fn registry(&self) -> Option<Box<dyn RegistryReader>> {
+        offline_registry(self)
+    }
+
+    fn upcase(&self, _path: &str) -> UpCase {
+        self.volume.lock().unwrap().upcase.clone()
+    }
+
+    fn file_id(&self, path: &str) -> Option<u64> {
+        self.check_volume(path).ok()?;
+        self.volume.lock().unwrap().find(path).ok()
+    }
+}
+
+/// Collects from a Windows volume mounted as a directory, like a disk image mounted with `ntfs-3g`
+/// in Linux. `C:` is mapped to the mount point and paths are resolved ignoring case.
+#[derive(Debug, Clone)]
+pub struct LogicalBackend {
+    volumes: BTreeMap<char, PathBuf>,
+    /// Mounts of the host, read from `/proc/self/mountinfo` when not given
+    mount_info: Option<MountInfo>,
+}
+
+impl LogicalBackend {
+    /// Maps the system drive `C:` to `root`
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            volumes: BTreeMap::new(),
+            mount_info: None,
+        }
+        .with_volume('C', root)
+    }
+
+    /// Describes the volumes with `mount_info` instead of the mounts of the host
+    pub fn with_mount_info(mut self, mount_info: MountInfo) -> Self {
+        self.mount_info = Some(mount_info);
+        self
+    }
+
+    /// Maps another drive letter to the mount point of its volume
+    pub fn with_volume<P: Into<PathBuf>>(mut self, letter: char, root: P) -> Self {
+        self.volumes.insert(letter.to_ascii_uppercase(), root.into());
+        self
+    }
+
+    /// Location in the local file system of a Windows path. Paths with `..` components and
+    /// symbolic links that lead outside of the mount point are refused, so files of the analysis
+    /// host are never collected.
+    pub fn resolve(&self, path: &str) -> ForensicResult<PathBuf> {
+        let (_, disk) = get_drive_and_disk(path)?;
+        let letter = disk.chars().next().unwrap_or_default().to_ascii_uppercase();
+        let root = match self.volumes.get(&letter) {
+            Some(v) => v,
+            None => return Err(ForensicError::missing_string(format!("Volume {} is not mounted", disk))),
+        };
+        let mut current = root.clone();
+        for component in path_components(path) {
+            if component == ".." {
+                return Err(ForensicError::Other(format!("{} leaves the volume", path)));
+            }
+            let exact = current.join(component);
+            if exact.symlink_metadata().is_ok() {
+                current = exact;
+                continue;
+            }
+            current = match find_ignoring_case(&current, component)? {
+                Some(v) => v,
+                None => return Err(ForensicError::missing_string(format!("Cannot find {}", path))),
+            };
+        }
+        let current = std::fs::canonicalize(current)?;
+        if !current.starts_with(std::fs::canonicalize(root)?) {
+            return Err(ForensicError::Other(format!(
+                "{} is a link to {}, outside of the volume",
+                path,
+                current.to_string_lossy()
+            )));
+        }
+        Ok(current)
+    }
+}
+
+impl VolumeSource for LogicalBackend {
+    fn volumes(&self) -> Vec<String> {
+        self.volumes.keys().map(|v| format!("{}:\\", v)).collect()
+    }
+
+    fn mounted_volumes(&self) -> Vec<MountedVolume> {
+        let mount_info = match &self.mount_info {
+            Some(v) => v.clone(),
+            None => MountInfo::read().unwrap_or_default(),
+        };
+        self.volumes
+            .iter()
+            .map(|(letter, path)| mount_info.volume(&format!("{}:\\", letter), path))
+            .collect()
+    }
+}
+
+impl CollectionBackend for LogicalBackend {
+    fn open(&self, path: &str) -> ForensicResult<OpenedFile> {
+        let file = std::fs::File::open(self.resolve(path)?)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(ForensicError::missing_string(format!("{} is not a file", path)));
+        }
+        Ok(OpenedFile {
+            size: metadata.len(),
+            reader: Box::new(file),
+        })
+    }
+
+    fn read_dir(&self, path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+        read_local_dir(&self.resolve(path)?)
+    }
+
+    fn registry(&self) -> Option<Box<dyn RegistryReader>> {
+        offline_registry(self)
+    }
+
+    #[cfg(unix)]
+    fn file_id(&self, path: &str) -> Option<u64> {
+        use std::os::unix::fs::MetadataExt;
+        Some(self.resolve(path).ok()?.metadata().ok()?.ino())
+    }
+}
+
+/// Registry built from the hives of `C:\Windows` of an offline system
+fn offline_registry(backend: &dyn CollectionBackend) -> Option<Box<dyn RegistryReader>> {
+    match OfflineRegistry::from_backend(backend, r"C:\Windows") {
+        Ok(v) => Some(Box::new(v)),
+        Err(err) => {
+            println!("Error loading the registry of the examined system: {:?}", err);
+            None
+        }
+    }
+}
+
+fn find_ignoring_case(directory: &Path, name: &str) -> ForensicResult<Option<PathBuf>> {
+    let upcase = UpCase::default();
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        if upcase.eq(&entry.file_name().to_string_lossy(), name) {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+fn read_local_dir(directory: &Path) -> ForensicResult<Vec<RawDirEntry>> {
+    let mut entries = Vec::with_capacity(64);
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let filetime = |time: std::io::Result<std::time::SystemTime>| time.map(system_time_to_filetime).unwrap_or(0);
+        entries.push(RawDirEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_directory: metadata.is_dir(),
+            deleted: false,
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            created: filetime(metadata.created()),
+            modified: filetime(metadata.modified()),
+            accessed: filetime(metadata.accessed()),
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+    use crate::helpers::test_tree::TestTree;
+
+    #[test]
+    fn should_resolve_windows_paths_ignoring_case() {
+        let tree = TestTree::new("logical-backend", &[]);
+        tree.write(r"WINDOWS\system32\Config\system", b"regf");
+
+        let root = std::fs::canonicalize(tree.root()).unwrap();
+        let mount_info = format!(
+            "22 1 8:1 / / rw - ext4 /dev/sda1 rw\n35 22 8:17 / {} rw - ntfs3 /dev/sdb1 rw\n",
+            root.to_string_lossy().replace(' ', "\\040")
+        );
+        let backend = LogicalBackend::new(tree.root()).with_mount_info(MountInfo::parse(&mount_info));
+        let path = backend.resolve(r"C:\Windows\System32\config\SYSTEM").unwrap();
+        assert_eq!(root.join("WINDOWS").join("system32").join("Config").join("system"), path);
+        assert_eq!(4, backend.open(r"c:\windows\SYSTEM32\CONFIG\system").unwrap().size);
+        assert!(backend.resolve(r"C:\Windows\System32\config\SAM").is_err());
+        assert!(backend.resolve(r"D:\Windows").is_err());
+        let entries = backend.read_dir(r"C:\Windows\System32").unwrap();
+        assert_eq!(1, entries.len());
+        assert!(entries[0].is_directory);
+        assert_eq!(vec![r"C:\".to_string()], backend.volumes());
+        let volumes = backend.mounted_volumes();
+        assert_eq!((r"C:\", "NTFS"), (volumes[0].root.as_str(), volumes[0].file_system.as_str()));
+    }
+
+    #[test]
+    fn should_not_resolve_paths_outside_of_the_volume() {
+        let tree = TestTree::new("logical-backend-escape", &[r"outside\shadow"]);
+        let (mount, outside) = (tree.path("mount"), tree.path("outside"));
+        tree.create_dir(r"mount\Windows");
+
+        let backend = LogicalBackend::new(&mount);
+        assert!(backend.resolve(r"C:\Windows").is_ok());
+        assert!(backend.resolve(r"C:\..\outside\shadow").is_err());
+        assert!(backend.open(r"C:\Windows\..\..\outside\shadow").is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&outside, mount.join("Windows").join("Temp")).unwrap();
+            assert!(backend.resolve(r"C:\Windows\Temp\shadow").is_err());
+            assert!(backend.read_dir(r"C:\Windows\Temp").is_err());
+        }
+    }
+
+    #[test]
+    fn should_list_folders_from_ntfs_indexes() {
+        let image = crate::ntfs::test_image::build(&[
+            (r"\System Volume Information\tracking.log", b"log"),
+            (r"\Users\Bob\AppData\Local\History.dat", &[b'H'; 2000]),
+        ]);
+        let backend = NtfsBackend::new('c', || Ok(std::io::Cursor::new(image.clone()))).unwrap();
+        let entries = backend.read_dir(r"C:\system volume information").unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("tracking.log", entries[0].name);
+        let mut file = backend.open(r"C:\Users\bob\AppData\Local\history.DAT").unwrap();
+        let mut content = Vec::new();
+        file.reader.read_to_end(&mut content).unwrap();
+        assert_eq!(vec![b'H'; 2000], content);
+        assert!(backend.file_id(r"C:\Users\Bob").is_some());
+        assert!(backend.read_dir(r"D:\Users").is_err());
+        assert_eq!(vec![r"C:\".to_string()], backend.volumes());
+        let volumes = backend.mounted_volumes();
+        assert_eq!(1, volumes.len());
+        assert_eq!((r"C:\", "NTFS"), (volumes[0].root.as_str(), volumes[0].file_system.as_str()));
+        assert_eq!(backend.volume.lock().unwrap().boot.cluster_size, volumes[0].cluster_size);
+    }
+}
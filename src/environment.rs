@@ -0,0 +1,510 @@
+//! Expansion of Windows environment variables like `%SystemRoot%` or `%ProgramFiles(x86)%` in any
+//! position of a path. The system variables are read from the registry of the examined system and
+//! the variables of each user from its `NTUSER.DAT`.
+
+use std::collections::BTreeMap;
+
+use forensic_rs::prelude::{ForensicError, ForensicResult, RegHiveKey, RegValue, RegistryReader};
+
+use crate::{regf::Hive, sys_vars::SystemLocations};
+
+/// Variables inside the value of a variable expanded at most, to stop on cycles
+const MAX_NESTING: usize = 8;
+
+const SESSION_ENVIRONMENT_KEY: &str = r"SYSTEM\CurrentControlSet\Control\Session Manager\Environment";
+const CURRENT_VERSION_KEY: &str = r"SOFTWARE\Microsoft\Windows\CurrentVersion";
+const PROFILE_LIST_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList";
+const SHELL_FOLDERS_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Explorer\Shell Folders";
+const USER_SHELL_FOLDERS_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Explorer\User Shell Folders";
+
+/// Variables of the installation kept in `Windows.old` by an upgrade: its SystemRoot and the folder
+/// of each of its profiles
+pub const PREVIOUS_VARIABLES: [&str; 2] = ["PREVIOUSROOT", "PREVIOUSUSERHOME"];
+
+/// Variables that take the root of each volume, `%FIXEDDRIVES%` only of the fixed disks,
+/// `%NTFSDRIVES%` of the local drives that may be NTFS and `%ALLDRIVES%` of every drive but
+/// optical and network ones
+pub const DRIVE_VARIABLES: [&str; 3] = ["ALLDRIVES", "FIXEDDRIVES", "NTFSDRIVES"];
+
+/// Variables that take a different value for each user. `%USERHOME%` is the profile folder.
+pub const USER_VARIABLES: [&str; 10] = [
+    "USERHOME",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "HOMEDRIVE",
+    "HOMEPATH",
+    "USERNAME",
+    "TEMP",
+    "TMP",
+    "PREVIOUSUSERHOME",
+];
+
+/// Environment variables of the examined system. Names are compared ignoring case.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// Values by uppercase name, they can reference other variables
+    variables: BTreeMap<String, (String, String)>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Default locations of a Windows installation in `system_drive` (`C:`)
+    pub fn with_defaults(system_drive: &str) -> Self {
+        let mut environment = Self::new();
+        environment.set("SystemDrive", system_drive);
+        environment.set("SystemRoot", r"%SystemDrive%\Windows");
+        environment.set("windir", "%SystemRoot%");
+        environment.set("ProgramData", r"%SystemDrive%\ProgramData");
+        environment.set("ALLUSERSPROFILE", "%ProgramData%");
+        environment.set("PUBLIC", r"%SystemDrive%\Users\Public");
+        environment.set("ProgramFiles", r"%SystemDrive%\Program Files");
+        environment.set("ProgramFiles(x86)", r"%SystemDrive%\Program Files (x86)");
+        environment.set("ProgramW6432", r"%SystemDrive%\Program Files");
+        environment.set("CommonProgramFiles", r"%ProgramFiles%\Common Files");
+        environment.set("CommonProgramFiles(x86)", r"%ProgramFiles(x86)%\Common Files");
+        environment.set("CommonProgramW6432", r"%ProgramW6432%\Common Files");
+        environment.set("TEMP", r"%SystemRoot%\TEMP");
+        environment.set("TMP", r"%SystemRoot%\TEMP");
+        environment.set("ComSpec", r"%SystemRoot%\system32\cmd.exe");
+        environment
+    }
+
+    /// Reads the system variables from the registry: the locations set by the installation and the
+    /// variables of `Session Manager\Environment`. Missing values keep their default location.
+    pub fn from_registry(registry: &mut Box<dyn RegistryReader>) -> Self {
+        let locations = SystemLocations::from_registry(registry);
+        Self::from_locations(registry, &locations)
+    }
+
+    /// Variables of the system installed in `system`, with the other locations and variables read
+    /// from the registry
+    pub fn from_locations(registry: &mut Box<dyn RegistryReader>, system: &SystemLocations) -> Self {
+        let mut environment = Self::with_defaults(&system.system_drive);
+        environment.set("SystemRoot", &system.system_root);
+        environment.set("ProgramData", &system.program_data);
+        let locations = [
+            (PROFILE_LIST_KEY, "Public", "PUBLIC"),
+            (CURRENT_VERSION_KEY, "ProgramFilesDir", "ProgramFiles"),
+            (CURRENT_VERSION_KEY, "ProgramFilesDir (x86)", "ProgramFiles(x86)"),
+            (CURRENT_VERSION_KEY, "ProgramW6432Dir", "ProgramW6432"),
+            (CURRENT_VERSION_KEY, "CommonFilesDir", "CommonProgramFiles"),
+            (CURRENT_VERSION_KEY, "CommonFilesDir (x86)", "CommonProgramFiles(x86)"),
+            (CURRENT_VERSION_KEY, "CommonW6432Dir", "CommonProgramW6432"),
+        ];
+        for (key, value, variable) in locations {
+            if let Ok(v) = read_string(registry, key, value) {
+                environment.set(variable, &v);
+            }
+        }
+        match read_values(registry, SESSION_ENVIRONMENT_KEY) {
+            Ok(values) => {
+                for (name, value) in values {
+                    environment.set(&name, &value);
+                }
+            }
+            Err(err) => println!(r"Error reading HKLM\{}: {:?}", SESSION_ENVIRONMENT_KEY, err),
+        }
+        environment
+    }
+
+    /// Environment of the user whose profile is at `home`, with the variables of its `NTUSER.DAT`
+    /// when available: the `Shell Folders` and `User Shell Folders` that relocate the AppData
+    /// folders, and the `Volatile Environment` and `Environment` keys.
+    pub fn for_user(&self, home: &str, hive: Option<&Hive>) -> Self {
+        let mut environment = self.clone();
+        environment.set("USERPROFILE", home);
+        environment.set("USERHOME", home);
+        if let Some(1) = home.find(':') {
+            environment.set("HOMEDRIVE", &home[..2]);
+            environment.set("HOMEPATH", &home[2..]);
+        }
+        if let Some(name) = home.trim_end_matches('\\').rsplit('\\').next() {
+            environment.set("USERNAME", name);
+        }
+        environment.set("APPDATA", r"%USERPROFILE%\AppData\Roaming");
+        environment.set("LOCALAPPDATA", r"%USERPROFILE%\AppData\Local");
+        environment.set("TEMP", r"%USERPROFILE%\AppData\Local\Temp");
+        environment.set("TMP", r"%USERPROFILE%\AppData\Local\Temp");
+        let hive = match hive {
+            Some(v) => v,
+            None => return environment,
+        };
+        // User Shell Folders can reference variables and take precedence over the expanded copy
+        for key in [SHELL_FOLDERS_KEY, USER_SHELL_FOLDERS_KEY] {
+            for (value, variable) in [("AppData", "APPDATA"), ("Local AppData", "LOCALAPPDATA")] {
+                if let Ok(v) = read_hive_string(hive, key, value) {
+                    environment.set(variable, &v);
+                }
+            }
+        }
+        for key in ["Volatile Environment", "Environment"] {
+            let key = match hive.open_key(key) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for name in hive.value_names(&key).unwrap_or_default() {
+                // The profile folder always comes from the ProfileList
+                if name.eq_ignore_ascii_case("USERPROFILE") || name.eq_ignore_ascii_case("USERHOME") {
+                    continue;
+                }
+                if let Ok(RegValue::SZ(v)) | Ok(RegValue::ExpandSZ(v)) = hive.value(&key, &name) {
+                    environment.set(&name, &v);
+                }
+            }
+        }
+        environment
+    }
+
+    /// The variable is defined, ignoring case
+    pub fn contains(&self, name: &str) -> bool {
+        self.variables.contains_key(&name.to_uppercase())
+    }
+
+    /// Sets a variable. The value can reference other variables, like a `REG_EXPAND_SZ`.
+    pub fn set(&mut self, name: &str, value: &str) {
+        // A root keeps its separator only when it is the whole value: `C:\` is `C:` but `\` stays
+        let value = match value.trim_end_matches('\\') {
+            "" => value,
+            v => v,
+        };
+        self.variables
+            .insert(name.to_uppercase(), (name.to_string(), value.to_string()));
+    }
+
+    /// Moves the values that start with the folder `from` into `to`, like the locations recorded by
+    /// an installation found now in another volume
+    pub fn rebase(&mut self, from: &str, to: &str) {
+        for (_, value) in self.variables.values_mut() {
+            *value = rebase_path(value, from, to);
+        }
+    }
+
+    /// Value of a variable with the variables it references expanded
+    pub fn get(&self, name: &str) -> Option<String> {
+        let (_, value) = self.variables.get(&name.to_uppercase())?;
+        self.expand_nested(value, 0).ok()
+    }
+
+    /// Names of the defined variables
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.variables.values().map(|(name, _)| name.as_str())
+    }
+
+    /// Replaces every `%NAME%` of `text`. A reference to an undefined variable is an error.
+    pub fn expand(&self, text: &str) -> ForensicResult<String> {
+        self.expand_nested(text, 0)
+    }
+
+    fn expand_nested(&self, text: &str, nesting: usize) -> ForensicResult<String> {
+        if nesting > MAX_NESTING {
+            return Err(ForensicError::bad_format_string(format!(
+                "Environment variables nested too deep in {}",
+                text
+            )));
+        }
+        let mut expanded = String::with_capacity(text.len() + 32);
+        let mut rest = text;
+        while let Some(start) = rest.find('%') {
+            expanded.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let end = match after.find('%') {
+                Some(v) => v,
+                None => {
+                    rest = &rest[start..];
+                    break;
+                }
+            };
+            let name = &after[..end];
+            // Not a variable name, the second `%` can start one
+            if name.is_empty() || name.contains('\\') {
+                expanded.push('%');
+                rest = after;
+                continue;
+            }
+            match self.variables.get(&name.to_uppercase()) {
+                Some((_, value)) => expanded.push_str(&self.expand_nested(value, nesting + 1)?),
+                None => {
+                    return Err(ForensicError::missing_string(format!(
+                        "Unresolved environment variable %{}% in {}",
+                        name, text
+                    )))
+                }
+            }
+            rest = &after[end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
+
+/// Replaces the folder `from` at the start of `path` with `to`, ignoring case
+pub fn rebase_path(path: &str, from: &str, to: &str) -> String {
+    let from = from.trim_end_matches('\\');
+    match path.get(..from.len()) {
+        Some(start) if start.eq_ignore_ascii_case(from) && matches!(path[from.len()..].chars().next(), None | Some('\\')) => {
+            format!("{}{}", to.trim_end_matches('\\'), &path[from.len()..])
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// Names of the variables referenced by `text`
+pub fn variable_names(text: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('%') {
+        let after = &rest[start + 1..];
+        let end = match after.find('%') {
+            Some(v) => v,
+            None => break,
+        };
+        let name = &after[..end];
+        if name.is_empty() || name.contains('\\') {
+            rest = after;
+            continue;
+        }
+        names.push(name);
+        rest = &after[end + 1..];
+    }
+    names
+}
+
+/// The text must be expanded once for each user
+pub fn references_user_variables(text: &str) -> bool {
+    references_any(text, &USER_VARIABLES)
+}
+
+/// The text must be expanded with the variables of the previous installations
+pub fn references_previous_variables(text: &str) -> bool {
+    references_any(text, &PREVIOUS_VARIABLES)
+}
+
+/// The text must be expanded once for each volume
+pub fn references_drive_variables(text: &str) -> bool {
+    references_any(text, &DRIVE_VARIABLES)
+}
+
+fn references_any(text: &str, variables: &[&str]) -> bool {
+    variable_names(text)
+        .iter()
+        .any(|name| variables.iter().any(|v| v.eq_ignore_ascii_case(name)))
+}
+
+fn read_hive_string(hive: &Hive, key: &str, value: &str) -> ForensicResult<String> {
+    let key = hive.open_key(key)?;
+    hive.value(&key, value)?.try_into()
+}
+
+/// Reads a string value of a key of HKLM
+pub(crate) fn read_string(registry: &mut Box<dyn RegistryReader>, key: &str, value: &str) -> ForensicResult<String> {
+    let key = registry.open_key(RegHiveKey::HkeyLocalMachine, key)?;
+    let value = registry.read_value(key, value);
+    registry.close_key(key);
+    value?.try_into()
+}
+
+/// Reads the string values of a key of HKLM
+fn read_values(registry: &mut Box<dyn RegistryReader>, key: &str) -> ForensicResult<Vec<(String, String)>> {
+    let key = registry.open_key(RegHiveKey::HkeyLocalMachine, key)?;
+    let mut values = Vec::with_capacity(32);
+    for name in registry.enumerate_values(key).unwrap_or_default() {
+        match registry.read_value(key, &name) {
+            Ok(RegValue::SZ(v)) | Ok(RegValue::ExpandSZ(v)) => values.push((name, v)),
+            _ => continue,
+        }
+    }
+    registry.close_key(key);
+    Ok(values)
+}
+
+#[cfg(test)]
+pub(crate) mod tst {
+    use std::sync::{Arc, Mutex};
+
+    use forensic_rs::prelude::{RegistryKeyInfo, VirtualFile, VirtualFileSystem};
+
+    use super::*;
+
+    const NT_CURRENT_VERSION_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion";
+
+    /// Registry with the given values: `(key path, value name, value)`, paths relative to HKLM
+    #[derive(Clone)]
+    pub(crate) struct TestRegistry {
+        values: Vec<(String, String, RegValue)>,
+        opened: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestRegistry {
+        pub(crate) fn new(values: &[(&str, &str, RegValue)]) -> Self {
+            Self {
+                values: values
+                    .iter()
+                    .map(|(key, name, value)| (key.to_uppercase(), name.to_string(), value.clone()))
+                    .collect(),
+                opened: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        fn path(&self, hkey: RegHiveKey) -> ForensicResult<String> {
+            match hkey {
+                RegHiveKey::HkeyLocalMachine => Ok(String::new()),
+                RegHiveKey::Hkey(v) => Ok(self.opened.lock().unwrap()[v as usize].clone()),
+                _ => Err(ForensicError::missing_str("Hive not available")),
+            }
+        }
+    }
+
+    impl RegistryReader for TestRegistry {
+        fn from_file(&self, _file: Box<dyn VirtualFile>) -> ForensicResult<Box<dyn RegistryReader>> {
+            Err(ForensicError::Other("Not supported".into()))
+        }
+        fn from_fs(&self, _fs: Box<dyn VirtualFileSystem>) -> ForensicResult<Box<dyn RegistryReader>> {
+            Err(ForensicError::Other("Not supported".into()))
+        }
+        fn open_key(&self, hkey: RegHiveKey, key_name: &str) -> ForensicResult<RegHiveKey> {
+            let parent = self.path(hkey)?;
+            let path = if parent.is_empty() {
+                key_name.to_uppercase()
+            } else {
+                format!(r"{}\{}", parent, key_name.to_uppercase())
+            };
+            let prefix = format!(r"{}\", path);
+            if !self.values.iter().any(|(key, _, _)| key == &path || key.starts_with(&prefix)) {
+                return Err(ForensicError::missing_string(format!("Key {} not found", key_name)));
+            }
+            let mut opened = self.opened.lock().unwrap();
+            opened.push(path);
+            Ok(RegHiveKey::Hkey(opened.len() as isize - 1))
+        }
+        fn read_value(&self, hkey: RegHiveKey, value_name: &str) -> ForensicResult<RegValue> {
+            let path = self.path(hkey)?;
+            self.values
+                .iter()
+                .find(|(key, name, _)| key == &path && name.eq_ignore_ascii_case(value_name))
+                .map(|(_, _, value)| value.clone())
+                .ok_or_else(|| ForensicError::missing_string(format!("Value {} not found", value_name)))
+        }
+        fn enumerate_values(&self, hkey: RegHiveKey) -> ForensicResult<Vec<String>> {
+            let path = self.path(hkey)?;
+            Ok(self
+                .values
+                .iter()
+                .filter(|(key, name, _)| key == &path && !name.is_empty())
+                .map(|(_, name, _)| name.clone())
+                .collect())
+        }
+        fn enumerate_keys(&self, hkey: RegHiveKey) -> ForensicResult<Vec<String>> {
+            let path = self.path(hkey)?;
+            let prefix = if path.is_empty() { path } else { format!(r"{}\", path) };
+            let mut keys: Vec<String> = Vec::new();
+            for (key, _, _) in &self.values {
+                if let Some(rest) = key.strip_prefix(&prefix) {
+                    let child = rest.split('\\').next().unwrap_or_default().to_string();
+                    if !child.is_empty() && !keys.contains(&child) {
+                        keys.push(child);
+                    }
+                }
+            }
+            Ok(keys)
+        }
+        fn key_at(&self, hkey: RegHiveKey, pos: u32) -> ForensicResult<String> {
+            self.enumerate_keys(hkey)?
+                .get(pos as usize)
+                .cloned()
+                .ok_or(ForensicError::NoMoreData)
+        }
+        fn value_at(&self, hkey: RegHiveKey, pos: u32) -> ForensicResult<String> {
+            self.enumerate_values(hkey)?
+                .get(pos as usize)
+                .cloned()
+                .ok_or(ForensicError::NoMoreData)
+        }
+        fn key_info(&self, _hkey: RegHiveKey) -> ForensicResult<RegistryKeyInfo> {
+            Ok(RegistryKeyInfo::default())
+        }
+        fn close_key(&self, _hkey: RegHiveKey) {}
+    }
+
+    #[test]
+    fn should_rebase_recorded_locations() {
+        let mut environment = Environment::with_defaults("C:");
+        environment.set("ProgramFiles", r"c:\Program Files");
+        environment.set("Backup", r"C:data\backup");
+        environment.rebase("C:", r"C:\Windows.old");
+        assert_eq!(r"C:\Windows.old\Windows", environment.get("SystemRoot").unwrap());
+        assert_eq!(r"C:\Windows.old\Program Files", environment.get("ProgramFiles").unwrap());
+        assert_eq!(r"C:data\backup", environment.get("Backup").unwrap());
+        assert_eq!(r"D:\Users\dave", rebase_path(r"C:\Users\dave", r"C:\", "D:"));
+    }
+
+    #[test]
+    fn should_expand_variables_in_any_position() {
+        let mut environment = Environment::with_defaults(r"D:\");
+        environment.set("AppRoot", r"%ProgramFiles(x86)%\Vendor");
+        assert_eq!(r"D:\Windows\System32", environment.expand(r"%windir%\System32").unwrap());
+        assert_eq!(
+            r"D:\Program Files (x86)\Vendor\logs\%\app.log",
+            environment.expand(r"%AppRoot%\logs\%\app.log").unwrap()
+        );
+        assert_eq!(r"D:\ProgramData", environment.get("allusersprofile").unwrap());
+        assert_eq!("100% done", environment.expand("100% done").unwrap());
+        assert!(environment.expand(r"%LOCALAPPDATA%\Temp").is_err());
+
+        environment.set("A", "%B%");
+        environment.set("B", "%A%");
+        assert!(environment.expand(r"%A%\x").is_err());
+    }
+
+    #[test]
+    fn should_read_system_variables_from_registry() {
+        let mut registry: Box<dyn RegistryReader> = Box::new(TestRegistry::new(&[
+            (NT_CURRENT_VERSION_KEY, "SystemRoot", RegValue::SZ(r"E:\WINNT".into())),
+            (PROFILE_LIST_KEY, "ProgramData", RegValue::ExpandSZ(r"%SystemDrive%\AllData".into())),
+            (CURRENT_VERSION_KEY, "ProgramFilesDir", RegValue::SZ(r"E:\Apps".into())),
+            (SESSION_ENVIRONMENT_KEY, "TEMP", RegValue::ExpandSZ(r"%SystemRoot%\TEMP".into())),
+            (SESSION_ENVIRONMENT_KEY, "Tools", RegValue::ExpandSZ(r"%ProgramFiles%\Tools".into())),
+            (SESSION_ENVIRONMENT_KEY, "NUMBER_OF_PROCESSORS", RegValue::DWord(4)),
+        ]));
+        let environment = Environment::from_registry(&mut registry);
+        assert_eq!("E:", environment.get("SystemDrive").unwrap());
+        assert_eq!(r"E:\WINNT\TEMP", environment.expand("%TEMP%").unwrap());
+        assert_eq!(r"E:\AllData\Microsoft", environment.expand(r"%ALLUSERSPROFILE%\Microsoft").unwrap());
+        assert_eq!(r"E:\Apps\Tools", environment.expand("%TOOLS%").unwrap());
+        assert_eq!(r"E:\Program Files (x86)", environment.get("ProgramFiles(x86)").unwrap());
+        assert!(environment.get("NUMBER_OF_PROCESSORS").is_none());
+        assert!(environment.names().any(|v| v == "Tools"));
+    }
+
+    #[test]
+    fn should_expand_variables_of_each_user() {
+        let system = Environment::with_defaults("C:");
+        let hive = Hive::new(crate::regf::tst::build(&[
+            (USER_SHELL_FOLDERS_KEY, "Local AppData", RegValue::ExpandSZ(r"%USERPROFILE%\LocalData".into())),
+            (SHELL_FOLDERS_KEY, "AppData", RegValue::SZ(r"\\fileserver\profiles\alice".into())),
+            (SHELL_FOLDERS_KEY, "Local AppData", RegValue::SZ(r"C:\Old".into())),
+            ("Environment", "TEMP", RegValue::ExpandSZ(r"%LOCALAPPDATA%\Temp".into())),
+            ("Environment", "OneDrive", RegValue::SZ(r"C:\Users\alice\OneDrive - Contoso".into())),
+            ("Volatile Environment", "USERPROFILE", RegValue::SZ(r"C:\Users\other".into())),
+        ]))
+        .unwrap();
+        let alice = system.for_user(r"C:\Users\alice", Some(&hive));
+        assert_eq!(r"C:\Users\alice\LocalData\Temp\x", alice.expand(r"%TEMP%\x").unwrap());
+        assert_eq!(r"\\fileserver\profiles\alice", alice.get("APPDATA").unwrap());
+        assert_eq!(r"C:\Users\alice", alice.expand("%USERHOME%").unwrap());
+        assert_eq!(r"C:\Users\alice\OneDrive - Contoso", alice.get("onedrive").unwrap());
+        assert_eq!("alice", alice.get("USERNAME").unwrap());
+        assert_eq!(r"\Users\alice", alice.get("HOMEPATH").unwrap());
+
+        let bob = system.for_user(r"C:\Users\bob", None);
+        assert_eq!(r"C:\Users\bob\AppData\Local\Temp", bob.get("TEMP").unwrap());
+        assert_eq!(r"C:\Windows\TEMP", system.get("TEMP").unwrap());
+
+        assert_eq!(vec!["LOCALAPPDATA", "x"], variable_names(r"%LOCALAPPDATA%\a\%\b%x%"));
+        assert!(references_user_variables(r"%LocalAppData%\Microsoft\**"));
+        assert!(!references_user_variables(r"%SystemRoot%\Tasks\**"));
+    }
+}
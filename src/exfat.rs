@@ -0,0 +1,467 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use forensic_rs::prelude::{ForensicError, ForensicResult};
+
+use crate::{
+    helpers::{dos_datetime_to_filetime, le_u16, le_u32, le_u64, utf16_string},
+    ntfs::path_components,
+    upcase::UpCase,
+    volume::{OpenedFile, RawDirEntry, RawFileSystem, VolumeRef},
+};
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ENTRY_IN_USE: u8 = 0x80;
+const ENTRY_FILE: u8 = 0x05;
+const ENTRY_STREAM_EXTENSION: u8 = 0x40;
+const ENTRY_FILE_NAME: u8 = 0x41;
+const ATTR_DIRECTORY: u16 = 0x10;
+const NO_FAT_CHAIN: u8 = 0x02;
+
+/// Boot sector of an exFAT volume
+#[derive(Debug, Clone)]
+pub struct ExFatBootSector {
+    pub bytes_per_sector: u64,
+    pub cluster_size: u64,
+    pub fat_offset: u64,
+    pub fat_length: u64,
+    pub cluster_heap_offset: u64,
+    pub cluster_count: u32,
+    pub root_cluster: u32,
+    pub serial_number: u32,
+}
+
+impl ExFatBootSector {
+    pub fn parse(buf: &[u8]) -> ForensicResult<Self> {
+        if buf.len() < 512 || &buf[3..11] != b"EXFAT   " {
+            return Err(ForensicError::bad_format_str("Not an exFAT boot sector"));
+        }
+        let sector_shift = buf[0x6C] as u32;
+        let cluster_shift = buf[0x6D] as u32;
+        if !(9..=12).contains(&sector_shift) || sector_shift + cluster_shift > 25 {
+            return Err(ForensicError::bad_format_str("Invalid exFAT geometry"));
+        }
+        let bytes_per_sector = 1u64 << sector_shift;
+        Ok(Self {
+            bytes_per_sector,
+            cluster_size: bytes_per_sector << cluster_shift,
+            fat_offset: le_u32(buf, 0x50) as u64 * bytes_per_sector,
+            fat_length: le_u32(buf, 0x54) as u64 * bytes_per_sector,
+            cluster_heap_offset: le_u32(buf, 0x58) as u64 * bytes_per_sector,
+            cluster_count: le_u32(buf, 0x5C),
+            root_cluster: le_u32(buf, 0x60),
+            serial_number: le_u32(buf, 0x64),
+        })
+    }
+}
+
+/// File or directory described by a File entry set
+#[derive(Debug, Clone)]
+pub struct ExFatDirEntry {
+    pub name: String,
+    pub attributes: u16,
+    pub first_cluster: u32,
+    pub size: u64,
+    pub valid_size: u64,
+    /// The data is stored in consecutive clusters and the FAT is not used
+    pub contiguous: bool,
+    pub deleted: bool,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+impl ExFatDirEntry {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    fn root(cluster: u32) -> Self {
+        Self {
+            name: String::new(),
+            attributes: ATTR_DIRECTORY,
+            first_cluster: cluster,
+            size: 0,
+            valid_size: 0,
+            contiguous: false,
+            deleted: false,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+        }
+    }
+}
+
+impl From<&ExFatDirEntry> for RawDirEntry {
+    fn from(v: &ExFatDirEntry) -> Self {
+        RawDirEntry {
+            name: v.name.clone(),
+            is_directory: v.is_directory(),
+            deleted: v.deleted,
+            size: v.size,
+            created: v.created,
+            modified: v.modified,
+            accessed: v.accessed,
+        }
+    }
+}
+
+/// Read only access to an exFAT volume
+pub struct ExFatVolume<R: Read + Seek> {
+    reader: R,
+    pub boot: ExFatBootSector,
+    fat: Vec<u8>,
+}
+
+impl<R: Read + Seek> ExFatVolume<R> {
+    pub fn new(mut reader: R) -> ForensicResult<Self> {
+        let mut boot = vec![0u8; 512];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut boot)?;
+        let boot = ExFatBootSector::parse(&boot)?;
+        let mut fat = vec![0u8; boot.fat_length as usize];
+        reader.seek(SeekFrom::Start(boot.fat_offset))?;
+        reader.read_exact(&mut fat)?;
+        Ok(Self { reader, boot, fat })
+    }
+
+    fn next_cluster(&self, cluster: u32) -> Option<u32> {
+        let value = le_u32(&self.fat, cluster as usize * 4);
+        if value < 2 || value > self.boot.cluster_count + 1 {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    /// Clusters of an entry. Files flagged as NoFatChain, and deleted files, are contiguous.
+    fn cluster_chain(&self, first_cluster: u32, size: u64, contiguous: bool) -> Vec<u32> {
+        let mut chain = Vec::with_capacity(16);
+        if first_cluster < 2 {
+            return chain;
+        }
+        if contiguous {
+            let clusters = size.div_ceil(self.boot.cluster_size);
+            for i in 0..clusters as u32 {
+                let cluster = first_cluster + i;
+                if cluster > self.boot.cluster_count + 1 {
+                    break;
+                }
+                chain.push(cluster);
+            }
+            return chain;
+        }
+        let mut cluster = first_cluster;
+        loop {
+            chain.push(cluster);
+            if chain.len() > self.boot.cluster_count as usize {
+                break;
+            }
+            match self.next_cluster(cluster) {
+                Some(v) => cluster = v,
+                None => break,
+            }
+        }
+        chain
+    }
+
+    fn entry_chain(&self, entry: &ExFatDirEntry) -> Vec<u32> {
+        let contiguous = entry.contiguous || entry.deleted;
+        let size = if entry.is_directory() && !contiguous { 0 } else { entry.size };
+        self.cluster_chain(entry.first_cluster, size, contiguous)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.boot.cluster_heap_offset + (cluster as u64 - 2) * self.boot.cluster_size
+    }
+
+    fn read_clusters(&mut self, chain: &[u32]) -> ForensicResult<Vec<u8>> {
+        let cluster_size = self.boot.cluster_size as usize;
+        let mut data = vec![0u8; chain.len() * cluster_size];
+        for (i, cluster) in chain.iter().enumerate() {
+            self.reader.seek(SeekFrom::Start(self.cluster_offset(*cluster)))?;
+            self.reader.read_exact(&mut data[i * cluster_size..(i + 1) * cluster_size])?;
+        }
+        Ok(data)
+    }
+
+    /// Lists a directory given its entry
+    pub fn read_dir(&mut self, directory: &ExFatDirEntry) -> ForensicResult<Vec<ExFatDirEntry>> {
+        let chain = self.entry_chain(directory);
+        let data = self.read_clusters(&chain)?;
+        Ok(parse_directory(&data))
+    }
+
+    /// Finds the entry of a path. Allocated entries are preferred over deleted ones with the same name.
+    pub fn find(&mut self, path: &str) -> ForensicResult<ExFatDirEntry> {
+        let mut current = ExFatDirEntry::root(self.boot.root_cluster);
+        let upcase = UpCase::default();
+        for component in path_components(path) {
+            if !current.is_directory() {
+                return Err(ForensicError::missing_string(format!("Cannot find {}", path)));
+            }
+            let mut matching: Vec<ExFatDirEntry> = self
+                .read_dir(&current)?
+                .into_iter()
+                .filter(|v| upcase.eq(&v.name, component))
+                .collect();
+            matching.sort_by_key(|v| v.deleted);
+            current = match matching.into_iter().next() {
+                Some(v) => v,
+                None => return Err(ForensicError::missing_string(format!("Cannot find {}", path))),
+            };
+        }
+        Ok(current)
+    }
+
+    fn file_entry(&mut self, path: &str) -> ForensicResult<(ExFatDirEntry, Vec<u32>)> {
+        let entry = self.find(path)?;
+        if entry.is_directory() {
+            return Err(ForensicError::missing_string(format!("{} is not a file", path)));
+        }
+        let chain = self.entry_chain(&entry);
+        Ok((entry, chain))
+    }
+
+    /// Opens a file as a reader over its clusters
+    pub fn open(&mut self, path: &str) -> ForensicResult<ExFatFile<'_, R>> {
+        let (entry, chain) = self.file_entry(path)?;
+        Ok(ExFatFile {
+            volume: VolumeRef::Borrowed(self),
+            chain,
+            size: entry.size,
+            valid_size: entry.valid_size,
+            position: 0,
+        })
+    }
+
+    /// Opens a file as a reader that owns the volume
+    pub fn into_file(mut self, path: &str) -> ForensicResult<ExFatFile<'static, R>> {
+        let (entry, chain) = self.file_entry(path)?;
+        Ok(ExFatFile {
+            volume: VolumeRef::Owned(self),
+            chain,
+            size: entry.size,
+            valid_size: entry.valid_size,
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read + Seek + 'static> RawFileSystem for ExFatVolume<R> {
+    fn fs_name(&self) -> &'static str {
+        "exFAT"
+    }
+
+    fn list_dir(&mut self, path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+        let directory = self.find(path)?;
+        if !directory.is_directory() {
+            return Err(ForensicError::bad_format_string(format!("{} is not a directory", path)));
+        }
+        Ok(self.read_dir(&directory)?.iter().map(RawDirEntry::from).collect())
+    }
+
+    fn open_file(&mut self, path: &str) -> ForensicResult<Box<dyn Read + '_>> {
+        Ok(Box::new(self.open(path)?))
+    }
+
+    fn into_file(self: Box<Self>, path: &str) -> ForensicResult<OpenedFile> {
+        let file = (*self).into_file(path)?;
+        Ok(OpenedFile {
+            size: file.size(),
+            reader: Box::new(file),
+        })
+    }
+}
+
+/// File stored in an exFAT volume. Bytes after the valid data length are returned as zeros.
+pub struct ExFatFile<'a, R: Read + Seek> {
+    volume: VolumeRef<'a, ExFatVolume<R>>,
+    chain: Vec<u32>,
+    size: u64,
+    valid_size: u64,
+    position: u64,
+}
+
+impl<'a, R: Read + Seek> ExFatFile<'a, R> {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<'a, R: Read + Seek> Read for ExFatFile<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.position >= self.valid_size {
+            let len = ((self.size - self.position) as usize).min(buf.len());
+            buf[..len].fill(0);
+            self.position += len as u64;
+            return Ok(len);
+        }
+        let cluster_size = self.volume.boot.cluster_size;
+        let cluster = match self.chain.get((self.position / cluster_size) as usize) {
+            Some(v) => *v,
+            None => return Ok(0),
+        };
+        let in_cluster = self.position % cluster_size;
+        let len = ((cluster_size - in_cluster).min(self.valid_size - self.position) as usize).min(buf.len());
+        let offset = self.volume.cluster_offset(cluster) + in_cluster;
+        self.volume.reader.seek(SeekFrom::Start(offset))?;
+        self.volume.reader.read_exact(&mut buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for ExFatFile<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(v) => v as i64,
+            SeekFrom::End(v) => self.size as i64 + v,
+            SeekFrom::Current(v) => self.position as i64 + v,
+        };
+        if position < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+fn exfat_timestamp(timestamp: u32, centiseconds: u8) -> u64 {
+    dos_datetime_to_filetime((timestamp >> 16) as u16, timestamp as u16, centiseconds)
+}
+
+/// Parses the entry sets of a directory. Deleted sets have the InUse bit cleared but keep their content.
+pub fn parse_directory(data: &[u8]) -> Vec<ExFatDirEntry> {
+    let mut entries = Vec::with_capacity(data.len() / (DIR_ENTRY_SIZE * 3));
+    let raw: Vec<&[u8]> = data.chunks_exact(DIR_ENTRY_SIZE).collect();
+    let mut i = 0;
+    while i < raw.len() {
+        let entry = raw[i];
+        if entry[0] == 0x00 {
+            break;
+        }
+        let entry_type = entry[0] & !ENTRY_IN_USE;
+        if entry_type != ENTRY_FILE {
+            i += 1;
+            continue;
+        }
+        let deleted = entry[0] & ENTRY_IN_USE == 0;
+        let secondary_count = entry[1] as usize;
+        let stream = match raw.get(i + 1) {
+            Some(v) if v[0] & !ENTRY_IN_USE == ENTRY_STREAM_EXTENSION => *v,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let name_length = stream[3] as usize;
+        let mut name = String::with_capacity(name_length);
+        for name_entry in raw.iter().skip(i + 2).take(secondary_count.saturating_sub(1)) {
+            if name_entry[0] & !ENTRY_IN_USE != ENTRY_FILE_NAME {
+                break;
+            }
+            name.push_str(&utf16_string(name_entry, 2, 15));
+        }
+        let name: String = name.chars().take(name_length).collect();
+        entries.push(ExFatDirEntry {
+            name,
+            attributes: le_u16(entry, 4),
+            first_cluster: le_u32(stream, 20),
+            size: le_u64(stream, 24),
+            valid_size: le_u64(stream, 8),
+            contiguous: stream[1] & NO_FAT_CHAIN != 0,
+            deleted,
+            created: exfat_timestamp(le_u32(entry, 8), entry[20]),
+            modified: exfat_timestamp(le_u32(entry, 12), entry[21]),
+            accessed: exfat_timestamp(le_u32(entry, 16), 0),
+        });
+        i += 1 + secondary_count;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tst {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    fn file_set(name: &str, attributes: u16, cluster: u32, size: u64, in_use: bool) -> Vec<u8> {
+        let chars: Vec<u16> = name.encode_utf16().collect();
+        let name_entries = chars.len().div_ceil(15);
+        let mut set = vec![0u8; 32 * (2 + name_entries)];
+        let in_use = if in_use { ENTRY_IN_USE } else { 0 };
+        set[0] = ENTRY_FILE | in_use;
+        set[1] = (1 + name_entries) as u8;
+        set[4..6].copy_from_slice(&attributes.to_le_bytes());
+        set[32] = ENTRY_STREAM_EXTENSION | in_use;
+        set[33] = 0x01 | NO_FAT_CHAIN;
+        set[35] = chars.len() as u8;
+        set[40..48].copy_from_slice(&size.to_le_bytes());
+        set[52..56].copy_from_slice(&cluster.to_le_bytes());
+        set[56..64].copy_from_slice(&size.to_le_bytes());
+        for (i, chunk) in chars.chunks(15).enumerate() {
+            let entry = &mut set[64 + i * 32..96 + i * 32];
+            entry[0] = ENTRY_FILE_NAME | in_use;
+            for (j, c) in chunk.iter().enumerate() {
+                entry[2 + j * 2..4 + j * 2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        set
+    }
+
+    /// 512 bytes per sector, 8 sectors per cluster, FAT at sector 24, heap at sector 32
+    fn exfat_image() -> Vec<u8> {
+        let mut image = vec![0u8; 32 * 512 + 16 * 4096];
+        image[3..11].copy_from_slice(b"EXFAT   ");
+        image[0x50..0x54].copy_from_slice(&24u32.to_le_bytes());
+        image[0x54..0x58].copy_from_slice(&8u32.to_le_bytes());
+        image[0x58..0x5C].copy_from_slice(&32u32.to_le_bytes());
+        image[0x5C..0x60].copy_from_slice(&16u32.to_le_bytes());
+        image[0x60..0x64].copy_from_slice(&2u32.to_le_bytes());
+        image[0x6C] = 9;
+        image[0x6D] = 3;
+        image[510] = 0x55;
+        image[511] = 0xAA;
+        let fat = 24 * 512;
+        image[fat + 8..fat + 12].copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+        let cluster = |n: usize| 32 * 512 + (n - 2) * 4096;
+        let mut root = Vec::new();
+        root.extend(file_set("A very long file name for exFAT.evtx", 0x20, 3, 5000, true));
+        root.extend(file_set("deleted.ps1", 0x20, 5, 10, false));
+        image[cluster(2)..cluster(2) + root.len()].copy_from_slice(&root);
+        image[cluster(3)..cluster(3) + 5000].fill(b'E');
+        image[cluster(5)..cluster(5) + 10].copy_from_slice(b"Get-Secret");
+        image
+    }
+
+    #[test]
+    fn should_parse_exfat_volume() {
+        let mut volume = ExFatVolume::new(Cursor::new(exfat_image())).unwrap();
+        let root = volume.list_dir(r"F:\").unwrap();
+        assert_eq!(2, root.len());
+        assert_eq!("A very long file name for exFAT.evtx", root[0].name);
+        assert_eq!(5000, root[0].size);
+        assert!(!root[0].deleted);
+        assert_eq!("deleted.ps1", root[1].name);
+        assert!(root[1].deleted);
+
+        let mut content = Vec::new();
+        volume
+            .open_file(r"F:\a very long FILE name for exfat.evtx")
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(vec![b'E'; 5000], content);
+
+        let mut content = Vec::new();
+        volume.open_file(r"F:\deleted.ps1").unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(b"Get-Secret".to_vec(), content);
+    }
+}
@@ -0,0 +1,590 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use forensic_rs::prelude::{ForensicError, ForensicResult};
+
+use crate::{
+    helpers::{dos_datetime_to_filetime, le_u16, le_u32},
+    ntfs::path_components,
+    upcase::UpCase,
+    volume::{OpenedFile, RawDirEntry, RawFileSystem, VolumeRef},
+};
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const DELETED_MARK: u8 = 0xE5;
+const LFN_LAST_ENTRY: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// BIOS Parameter Block of a FAT12/16/32 volume
+#[derive(Debug, Clone)]
+pub struct FatBootSector {
+    pub fat_type: FatType,
+    pub bytes_per_sector: u64,
+    pub cluster_size: u64,
+    pub reserved_sectors: u64,
+    pub number_of_fats: u64,
+    pub root_entries: u64,
+    pub total_sectors: u64,
+    pub fat_size: u64,
+    pub root_cluster: u32,
+    pub cluster_count: u32,
+    pub serial_number: u32,
+    pub label: String,
+}
+
+impl FatBootSector {
+    pub fn parse(buf: &[u8]) -> ForensicResult<Self> {
+        if buf.len() < 512 {
+            return Err(ForensicError::bad_format_str("Truncated FAT boot sector"));
+        }
+        let bytes_per_sector = le_u16(buf, 0x0B) as u64;
+        let sectors_per_cluster = buf[0x0D] as u64;
+        let reserved_sectors = le_u16(buf, 0x0E) as u64;
+        let number_of_fats = buf[0x10] as u64;
+        let root_entries = le_u16(buf, 0x11) as u64;
+        let total_sectors = match le_u16(buf, 0x13) {
+            0 => le_u32(buf, 0x20) as u64,
+            v => v as u64,
+        };
+        let fat_size = match le_u16(buf, 0x16) {
+            0 => le_u32(buf, 0x24) as u64,
+            v => v as u64,
+        };
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || number_of_fats == 0
+            || fat_size == 0
+        {
+            return Err(ForensicError::bad_format_str("Invalid FAT BIOS Parameter Block"));
+        }
+        let root_dir_sectors = (root_entries * DIR_ENTRY_SIZE as u64).div_ceil(bytes_per_sector);
+        let metadata_sectors = reserved_sectors + number_of_fats * fat_size + root_dir_sectors;
+        if total_sectors <= metadata_sectors {
+            return Err(ForensicError::bad_format_str("Invalid FAT volume size"));
+        }
+        let cluster_count = ((total_sectors - metadata_sectors) / sectors_per_cluster) as u32;
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let (root_cluster, serial_offset) = match fat_type {
+            FatType::Fat32 => (le_u32(buf, 0x2C), 0x43),
+            _ => (0, 0x27),
+        };
+        let label = String::from_utf8_lossy(&buf[serial_offset + 4..serial_offset + 15])
+            .trim_end()
+            .to_string();
+        Ok(Self {
+            fat_type,
+            bytes_per_sector,
+            cluster_size: bytes_per_sector * sectors_per_cluster,
+            reserved_sectors,
+            number_of_fats,
+            root_entries,
+            total_sectors,
+            fat_size,
+            root_cluster,
+            cluster_count,
+            serial_number: le_u32(buf, serial_offset),
+            label,
+        })
+    }
+
+    fn root_dir_offset(&self) -> u64 {
+        (self.reserved_sectors + self.number_of_fats * self.fat_size) * self.bytes_per_sector
+    }
+
+    fn data_offset(&self) -> u64 {
+        self.root_dir_offset() + (self.root_entries * DIR_ENTRY_SIZE as u64).div_ceil(self.bytes_per_sector) * self.bytes_per_sector
+    }
+}
+
+/// Directory entry of a FAT volume, with the long file name when present
+#[derive(Debug, Clone)]
+pub struct FatDirEntry {
+    pub name: String,
+    pub short_name: String,
+    pub attributes: u8,
+    pub first_cluster: u32,
+    pub size: u64,
+    pub deleted: bool,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+impl FatDirEntry {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+}
+
+impl From<&FatDirEntry> for RawDirEntry {
+    fn from(v: &FatDirEntry) -> Self {
+        RawDirEntry {
+            name: v.name.clone(),
+            is_directory: v.is_directory(),
+            deleted: v.deleted,
+            size: v.size,
+            created: v.created,
+            modified: v.modified,
+            accessed: v.accessed,
+        }
+    }
+}
+
+/// Read only access to a FAT12, FAT16 or FAT32 volume
+pub struct FatVolume<R: Read + Seek> {
+    reader: R,
+    pub boot: FatBootSector,
+    fat: Vec<u8>,
+}
+
+impl<R: Read + Seek> FatVolume<R> {
+    pub fn new(mut reader: R) -> ForensicResult<Self> {
+        let mut boot = vec![0u8; 512];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut boot)?;
+        let boot = FatBootSector::parse(&boot)?;
+        let mut fat = vec![0u8; (boot.fat_size * boot.bytes_per_sector) as usize];
+        reader.seek(SeekFrom::Start(boot.reserved_sectors * boot.bytes_per_sector))?;
+        reader.read_exact(&mut fat)?;
+        Ok(Self { reader, boot, fat })
+    }
+
+    /// Next cluster in the chain, None at the end of the chain or on bad/free clusters
+    pub fn next_cluster(&self, cluster: u32) -> Option<u32> {
+        let (value, end_of_chain) = match self.boot.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster as usize + cluster as usize / 2;
+                let value = le_u16(&self.fat, offset);
+                let value = if cluster & 1 == 1 { value >> 4 } else { value & 0x0FFF };
+                (value as u32, 0x0FF7)
+            }
+            FatType::Fat16 => (le_u16(&self.fat, cluster as usize * 2) as u32, 0xFFF7),
+            FatType::Fat32 => (le_u32(&self.fat, cluster as usize * 4) & 0x0FFF_FFFF, 0x0FFF_FFF7),
+        };
+        if value < 2 || value >= end_of_chain || value > self.boot.cluster_count + 1 {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    /// Clusters of a file. Deleted files lost their chain so they are assumed to be contiguous.
+    pub fn cluster_chain(&self, first_cluster: u32, size: Option<u64>, deleted: bool) -> Vec<u32> {
+        let mut chain = Vec::with_capacity(16);
+        if first_cluster < 2 {
+            return chain;
+        }
+        if deleted {
+            let clusters = size.unwrap_or(0).div_ceil(self.boot.cluster_size).max(1);
+            for i in 0..clusters as u32 {
+                let cluster = first_cluster + i;
+                if cluster > self.boot.cluster_count + 1 {
+                    break;
+                }
+                chain.push(cluster);
+            }
+            return chain;
+        }
+        let mut cluster = first_cluster;
+        loop {
+            chain.push(cluster);
+            if chain.len() > self.boot.cluster_count as usize {
+                break;
+            }
+            match self.next_cluster(cluster) {
+                Some(v) => cluster = v,
+                None => break,
+            }
+        }
+        chain
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.boot.data_offset() + (cluster as u64 - 2) * self.boot.cluster_size
+    }
+
+    fn read_clusters(&mut self, chain: &[u32]) -> ForensicResult<Vec<u8>> {
+        let cluster_size = self.boot.cluster_size as usize;
+        let mut data = vec![0u8; chain.len() * cluster_size];
+        for (i, cluster) in chain.iter().enumerate() {
+            self.reader.seek(SeekFrom::Start(self.cluster_offset(*cluster)))?;
+            self.reader.read_exact(&mut data[i * cluster_size..(i + 1) * cluster_size])?;
+        }
+        Ok(data)
+    }
+
+    /// Lists the root directory
+    pub fn read_root(&mut self) -> ForensicResult<Vec<FatDirEntry>> {
+        let data = match self.boot.fat_type {
+            FatType::Fat32 => {
+                let chain = self.cluster_chain(self.boot.root_cluster, None, false);
+                self.read_clusters(&chain)?
+            }
+            _ => {
+                let mut data = vec![0u8; self.boot.root_entries as usize * DIR_ENTRY_SIZE];
+                self.reader.seek(SeekFrom::Start(self.boot.root_dir_offset()))?;
+                self.reader.read_exact(&mut data)?;
+                data
+            }
+        };
+        Ok(parse_directory(&data))
+    }
+
+    /// Lists a directory given its entry
+    pub fn read_dir(&mut self, directory: &FatDirEntry) -> ForensicResult<Vec<FatDirEntry>> {
+        if directory.first_cluster == 0 {
+            return self.read_root();
+        }
+        let chain = self.cluster_chain(directory.first_cluster, None, directory.deleted);
+        let data = self.read_clusters(&chain)?;
+        Ok(parse_directory(&data)
+            .into_iter()
+            .filter(|v| v.name != "." && v.name != "..")
+            .collect())
+    }
+
+    /// Finds the entry of a path. Allocated entries are preferred over deleted ones with the same name.
+    pub fn find(&mut self, path: &str) -> ForensicResult<Option<FatDirEntry>> {
+        let mut current: Option<FatDirEntry> = None;
+        let upcase = UpCase::default();
+        for component in path_components(path) {
+            let entries = match &current {
+                None => self.read_root()?,
+                Some(v) if v.is_directory() => self.read_dir(v)?,
+                Some(_) => return Err(ForensicError::missing_string(format!("Cannot find {}", path))),
+            };
+            let mut matching: Vec<FatDirEntry> = entries
+                .into_iter()
+                .filter(|v| upcase.eq(&v.name, component) || upcase.eq(&v.short_name, component))
+                .collect();
+            matching.sort_by_key(|v| v.deleted);
+            current = match matching.into_iter().next() {
+                Some(v) => Some(v),
+                None => return Err(ForensicError::missing_string(format!("Cannot find {}", path))),
+            };
+        }
+        Ok(current)
+    }
+
+    /// Reads the content of a file entry
+    pub fn read_file(&mut self, entry: &FatDirEntry) -> ForensicResult<Vec<u8>> {
+        let chain = self.cluster_chain(entry.first_cluster, Some(entry.size), entry.deleted);
+        let mut data = self.read_clusters(&chain)?;
+        data.truncate(entry.size as usize);
+        Ok(data)
+    }
+
+    fn file_entry(&mut self, path: &str) -> ForensicResult<(Vec<u32>, u64)> {
+        let entry = match self.find(path)? {
+            Some(v) if !v.is_directory() => v,
+            _ => return Err(ForensicError::missing_string(format!("{} is not a file", path))),
+        };
+        let chain = self.cluster_chain(entry.first_cluster, Some(entry.size), entry.deleted);
+        Ok((chain, entry.size))
+    }
+
+    /// Opens a file as a reader over its cluster chain
+    pub fn open(&mut self, path: &str) -> ForensicResult<FatFile<'_, R>> {
+        let (chain, size) = self.file_entry(path)?;
+        Ok(FatFile {
+            volume: VolumeRef::Borrowed(self),
+            chain,
+            size,
+            position: 0,
+        })
+    }
+
+    /// Opens a file as a reader that owns the volume
+    pub fn into_file(mut self, path: &str) -> ForensicResult<FatFile<'static, R>> {
+        let (chain, size) = self.file_entry(path)?;
+        Ok(FatFile {
+            volume: VolumeRef::Owned(self),
+            chain,
+            size,
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read + Seek + 'static> RawFileSystem for FatVolume<R> {
+    fn fs_name(&self) -> &'static str {
+        match self.boot.fat_type {
+            FatType::Fat12 => "FAT12",
+            FatType::Fat16 => "FAT16",
+            FatType::Fat32 => "FAT32",
+        }
+    }
+
+    fn list_dir(&mut self, path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+        let entries = match self.find(path)? {
+            None => self.read_root()?,
+            Some(v) if v.is_directory() => self.read_dir(&v)?,
+            Some(_) => return Err(ForensicError::bad_format_string(format!("{} is not a directory", path))),
+        };
+        Ok(entries.iter().map(RawDirEntry::from).collect())
+    }
+
+    fn open_file(&mut self, path: &str) -> ForensicResult<Box<dyn Read + '_>> {
+        Ok(Box::new(self.open(path)?))
+    }
+
+    fn into_file(self: Box<Self>, path: &str) -> ForensicResult<OpenedFile> {
+        let file = (*self).into_file(path)?;
+        Ok(OpenedFile {
+            size: file.size(),
+            reader: Box::new(file),
+        })
+    }
+}
+
+/// File stored in a FAT volume
+pub struct FatFile<'a, R: Read + Seek> {
+    volume: VolumeRef<'a, FatVolume<R>>,
+    chain: Vec<u32>,
+    size: u64,
+    position: u64,
+}
+
+impl<'a, R: Read + Seek> FatFile<'a, R> {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<'a, R: Read + Seek> Read for FatFile<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let cluster_size = self.volume.boot.cluster_size;
+        let index = (self.position / cluster_size) as usize;
+        let cluster = match self.chain.get(index) {
+            Some(v) => *v,
+            None => return Ok(0),
+        };
+        let in_cluster = self.position % cluster_size;
+        let len = ((cluster_size - in_cluster).min(self.size - self.position) as usize).min(buf.len());
+        let offset = self.volume.cluster_offset(cluster) + in_cluster;
+        self.volume.reader.seek(SeekFrom::Start(offset))?;
+        self.volume.reader.read_exact(&mut buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for FatFile<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(v) => v as i64,
+            SeekFrom::End(v) => self.size as i64 + v,
+            SeekFrom::Current(v) => self.position as i64 + v,
+        };
+        if position < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+fn lfn_checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, v| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*v))
+}
+
+fn lfn_chars(entry: &[u8]) -> Vec<u16> {
+    let mut chars = Vec::with_capacity(13);
+    for range in [(1, 11), (14, 26), (28, 32)] {
+        for c in entry[range.0..range.1].chunks_exact(2) {
+            chars.push(u16::from_le_bytes([c[0], c[1]]));
+        }
+    }
+    chars
+}
+
+fn short_name(entry: &[u8], deleted: bool) -> String {
+    let mut base = entry[0..8].to_vec();
+    if deleted {
+        base[0] = b'_';
+    } else if base[0] == 0x05 {
+        base[0] = DELETED_MARK;
+    }
+    let base = String::from_utf8_lossy(&base).trim_end().to_string();
+    let extension = String::from_utf8_lossy(&entry[8..11]).trim_end().to_string();
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, extension)
+    }
+}
+
+/// Parses the 32 byte entries of a directory, joining long file names and keeping deleted entries
+pub fn parse_directory(data: &[u8]) -> Vec<FatDirEntry> {
+    let mut entries = Vec::with_capacity(data.len() / DIR_ENTRY_SIZE);
+    let mut lfn: Vec<&[u8]> = Vec::with_capacity(4);
+    for entry in data.chunks_exact(DIR_ENTRY_SIZE) {
+        if entry[0] == 0x00 {
+            break;
+        }
+        let attributes = entry[11];
+        if attributes & 0x3F == ATTR_LONG_NAME {
+            if entry[0] & LFN_LAST_ENTRY != 0 && entry[0] != DELETED_MARK {
+                lfn.clear();
+            }
+            lfn.push(entry);
+            continue;
+        }
+        let deleted = entry[0] == DELETED_MARK;
+        if attributes & ATTR_VOLUME_ID != 0 {
+            lfn.clear();
+            continue;
+        }
+        let short = short_name(entry, deleted);
+        let checksum = lfn_checksum(&entry[0..11]);
+        let name = if !lfn.is_empty() && lfn.iter().all(|v| v[13] == checksum || deleted) {
+            let mut chars: Vec<u16> = lfn.iter().rev().flat_map(|v| lfn_chars(v)).collect();
+            if let Some(end) = chars.iter().position(|v| *v == 0) {
+                chars.truncate(end);
+            }
+            String::from_utf16_lossy(&chars)
+        } else {
+            short.clone()
+        };
+        lfn.clear();
+        entries.push(FatDirEntry {
+            name,
+            short_name: short,
+            attributes,
+            first_cluster: ((le_u16(entry, 20) as u32) << 16) | le_u16(entry, 26) as u32,
+            size: le_u32(entry, 28) as u64,
+            deleted,
+            created: dos_datetime_to_filetime(le_u16(entry, 16), le_u16(entry, 14), entry[13]),
+            modified: dos_datetime_to_filetime(le_u16(entry, 24), le_u16(entry, 22), 0),
+            accessed: dos_datetime_to_filetime(le_u16(entry, 18), 0, 0),
+        });
+    }
+    entries
+}
+
+#[cfg(test)]
+pub(crate) mod tst {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    fn short_entry(name: &[u8; 11], attributes: u8, cluster: u16, size: u32) -> Vec<u8> {
+        let mut entry = vec![0u8; 32];
+        entry[0..11].copy_from_slice(name);
+        entry[11] = attributes;
+        // 2023-05-17 10:30:00
+        entry[24..26].copy_from_slice(&(((2023 - 1980) << 9) | (5 << 5) | 17u16).to_le_bytes());
+        entry[22..24].copy_from_slice(&((10u16 << 11) | (30 << 5)).to_le_bytes());
+        entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    fn lfn_entry(ordinal: u8, name: &str, short: &[u8; 11]) -> Vec<u8> {
+        let mut chars: Vec<u16> = name.encode_utf16().collect();
+        chars.push(0);
+        chars.resize(13, 0xFFFF);
+        let mut entry = vec![0u8; 32];
+        entry[0] = ordinal;
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = lfn_checksum(short);
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (c, offset) in chars.iter().zip(offsets) {
+            entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entry
+    }
+
+    /// FAT16 volume: 512 bytes per sector, 4 sectors per cluster, 1 FAT of 32 sectors, 512 root entries
+    pub(crate) fn fat16_image() -> Vec<u8> {
+        let total_sectors: u32 = 4 * 5000 + 1 + 32 + 32;
+        let mut image = vec![0u8; total_sectors as usize * 512];
+        image[3..11].copy_from_slice(b"MSDOS5.0");
+        image[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        image[0x0D] = 4;
+        image[0x0E..0x10].copy_from_slice(&1u16.to_le_bytes());
+        image[0x10] = 1;
+        image[0x11..0x13].copy_from_slice(&512u16.to_le_bytes());
+        image[0x20..0x24].copy_from_slice(&total_sectors.to_le_bytes());
+        image[0x16..0x18].copy_from_slice(&32u16.to_le_bytes());
+        image[0x27..0x2B].copy_from_slice(&0xCAFEu32.to_le_bytes());
+        image[0x2B..0x36].copy_from_slice(b"USB        ");
+        image[510] = 0x55;
+        image[511] = 0xAA;
+        // FAT: DCIM dir in cluster 2, photo in clusters 3 -> 4
+        let fat = 512;
+        for (cluster, value) in [(0u16, 0xFFF8u16), (1, 0xFFFF), (2, 0xFFFF), (3, 4), (4, 0xFFFF)] {
+            let offset = fat + cluster as usize * 2;
+            image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        let root = 512 * 33;
+        let mut entries = Vec::new();
+        entries.extend(short_entry(b"DCIM       ", ATTR_DIRECTORY, 2, 0));
+        let mut deleted = short_entry(b"SECRET  TXT", 0x20, 5, 11);
+        deleted[0] = DELETED_MARK;
+        entries.extend(deleted);
+        image[root..root + entries.len()].copy_from_slice(&entries);
+        let data = root + 512 * 32;
+        let cluster = |n: usize| data + (n - 2) * 2048;
+        let short = b"HOLIDA~1JPG";
+        let mut entries = Vec::new();
+        entries.extend(short_entry(b".          ", ATTR_DIRECTORY, 2, 0));
+        entries.extend(short_entry(b"..         ", ATTR_DIRECTORY, 0, 0));
+        entries.extend(lfn_entry(0x42, "jpg", short));
+        entries.extend(lfn_entry(0x01, "Holiday 2023.", short));
+        entries.extend(short_entry(short, 0x20, 3, 3000));
+        image[cluster(2)..cluster(2) + entries.len()].copy_from_slice(&entries);
+        image[cluster(3)..cluster(3) + 3000].fill(b'J');
+        image[cluster(5)..cluster(5) + 11].copy_from_slice(b"top secret!");
+        image
+    }
+
+    #[test]
+    fn should_parse_fat16_volume() {
+        let mut volume = FatVolume::new(Cursor::new(fat16_image())).unwrap();
+        assert_eq!(FatType::Fat16, volume.boot.fat_type);
+        assert_eq!("USB", volume.boot.label);
+
+        let root = volume.list_dir(r"E:\").unwrap();
+        assert_eq!(2, root.len());
+        assert!(root[0].is_directory);
+        assert_eq!("_ECRET.TXT", root[1].name);
+        assert!(root[1].deleted);
+
+        let dcim = volume.list_dir(r"E:\DCIM").unwrap();
+        assert_eq!("Holiday 2023.jpg", dcim[0].name);
+        assert_eq!(3000, dcim[0].size);
+        assert_eq!(133287930000000000, dcim[0].modified);
+
+        let mut content = Vec::new();
+        volume.open_file(r"E:\dcim\HOLIDAY 2023.JPG").unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(vec![b'J'; 3000], content);
+
+        let mut content = Vec::new();
+        volume.open_file(r"E:\_ECRET.TXT").unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(b"top secret!".to_vec(), content);
+    }
+}
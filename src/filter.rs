@@ -0,0 +1,353 @@
+//! Metadata conditions of a collection target, written after a `|`:
+//!
+//! `%SYSTEMDRIVE%\Users\**|ext=ps1,bat,vbs;modified>30d;size<10MB`
+//!
+//! * `size<N`, `size>N`, `size<=N` and `size>=N`, with an optional `B`, `KB`, `MB` or `GB` unit.
+//! * `modified` and `created` compared with a date (`2024-01-31`, `2024-01-31T10:00:00`, in UTC)
+//!   or with an age (`30d`, `12h`, `45m`): `modified>30d` keeps the files modified in the last 30 days.
+//! * `ext=ps1,.bat,*.vbs` keeps the files with one of the extensions, compared ignoring case.
+//! * `type=pe,zip,ole2,sqlite,lnk,evtx` keeps the files whose header has one of the signatures,
+//!   whatever their extension. The header is read through the collection backend, so the files
+//!   matching every other condition are opened while expanding the target.
+//!
+//! Files whose timestamps are unknown are kept by the time conditions.
+
+use forensic_rs::prelude::{ForensicError, ForensicResult};
+
+use crate::{helpers::days_from_civil, upcase::UpCase, volume::RawDirEntry};
+
+/// Ticks of 100 nanoseconds between 1601-01-01 and 1970-01-01
+const UNIX_EPOCH_FILETIME: i64 = 116_444_736_000_000_000;
+const FILETIME_SECOND: u64 = 10_000_000;
+/// Bytes read from the start of a file to check its signature
+pub const SIGNATURE_PROBE_SIZE: usize = 1024;
+
+/// Formats recognized by the header of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSignature {
+    /// MZ executable with a PE header: EXE, DLL, SYS...
+    Pe,
+    /// ZIP archives and the formats built on them: DOCX, JAR, APPX...
+    Zip,
+    /// OLE2 compound files: DOC, XLS, MSI...
+    Ole2,
+    Sqlite,
+    Lnk,
+    Evtx,
+}
+
+impl FileSignature {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "pe" | "exe" | "mz" => Self::Pe,
+            "zip" => Self::Zip,
+            "ole2" | "ole" | "cfb" => Self::Ole2,
+            "sqlite" => Self::Sqlite,
+            "lnk" => Self::Lnk,
+            "evtx" => Self::Evtx,
+            _ => return None,
+        })
+    }
+
+    pub fn matches(&self, header: &[u8]) -> bool {
+        match self {
+            Self::Pe => {
+                if !header.starts_with(b"MZ") {
+                    return false;
+                }
+                // The PE header may be out of the probed bytes, the MZ stub is enough then
+                match header.get(0x3C..0x40) {
+                    Some(v) => {
+                        let offset = u32::from_le_bytes([v[0], v[1], v[2], v[3]]) as usize;
+                        match header.get(offset..offset + 4) {
+                            Some(v) => v == b"PE\0\0",
+                            None => offset + 4 > header.len(),
+                        }
+                    }
+                    None => false,
+                }
+            }
+            Self::Zip => header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06"),
+            Self::Ole2 => header.starts_with(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]),
+            Self::Sqlite => header.starts_with(b"SQLite format 3\0"),
+            Self::Lnk => header.starts_with(&[
+                0x4C, 0x00, 0x00, 0x00, 0x01, 0x14, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x46,
+            ]),
+            Self::Evtx => header.starts_with(b"ElfFile\0"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileFilter {
+    /// Minimum size in bytes, inclusive
+    pub min_size: Option<u64>,
+    /// Maximum size in bytes, inclusive
+    pub max_size: Option<u64>,
+    /// FILETIME the file must have been modified after
+    pub modified_after: Option<u64>,
+    pub modified_before: Option<u64>,
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    /// Extensions without the leading dot. Empty accepts every extension.
+    pub extensions: Vec<String>,
+    /// Signatures of the header of the file. Empty accepts every file.
+    pub signatures: Vec<FileSignature>,
+}
+
+impl FileFilter {
+    /// Parses the conditions of a target separated by `;`. Ages are relative to `now`, a FILETIME.
+    pub fn parse(conditions: &str, now: u64) -> ForensicResult<Self> {
+        let mut filter = Self::default();
+        for condition in conditions.split(';').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            if let Some(extensions) = condition.strip_prefix("ext=") {
+                filter.extensions.extend(
+                    extensions
+                        .split(',')
+                        .map(|v| v.trim().trim_start_matches('*').trim_start_matches('.'))
+                        .filter(|v| !v.is_empty())
+                        .map(|v| v.to_string()),
+                );
+                continue;
+            }
+            if let Some(signatures) = condition.strip_prefix("type=") {
+                for name in signatures.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+                    match FileSignature::from_name(name) {
+                        Some(v) => filter.signatures.push(v),
+                        None => return Err(invalid_condition(condition)),
+                    }
+                }
+                continue;
+            }
+            let position = match condition.find(['<', '>']) {
+                Some(v) => v,
+                None => return Err(invalid_condition(condition)),
+            };
+            let field = condition[..position].trim();
+            let operator = &condition[position..];
+            let (greater, inclusive, value) = match operator.as_bytes() {
+                [b'>', b'=', ..] => (true, true, &operator[2..]),
+                [b'<', b'=', ..] => (false, true, &operator[2..]),
+                [b'>', ..] => (true, false, &operator[1..]),
+                _ => (false, false, &operator[1..]),
+            };
+            let value = value.trim();
+            match field {
+                "size" => {
+                    let size = parse_size(value).ok_or_else(|| invalid_condition(condition))?;
+                    if greater {
+                        filter.min_size = Some(if inclusive { size } else { size.saturating_add(1) });
+                    } else if inclusive {
+                        filter.max_size = Some(size);
+                    } else {
+                        filter.max_size = Some(size.checked_sub(1).ok_or_else(|| invalid_condition(condition))?);
+                    }
+                }
+                "modified" | "created" => {
+                    // An age is compared with the time it points to: `>30d` is after 30 days ago
+                    let time = match parse_age(value) {
+                        Some(age) => now.saturating_sub(age),
+                        None => parse_date(value).ok_or_else(|| invalid_condition(condition))?,
+                    };
+                    let bound = match (greater, inclusive) {
+                        (true, true) => time.saturating_sub(1),
+                        (false, true) => time.saturating_add(1),
+                        _ => time,
+                    };
+                    let (after_field, before_field) = if field == "modified" {
+                        (&mut filter.modified_after, &mut filter.modified_before)
+                    } else {
+                        (&mut filter.created_after, &mut filter.created_before)
+                    };
+                    if greater {
+                        *after_field = Some(bound);
+                    } else {
+                        *before_field = Some(bound);
+                    }
+                }
+                _ => return Err(invalid_condition(condition)),
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The filter accepts every file
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Checks the conditions on the metadata of the file
+    pub fn matches(&self, entry: &RawDirEntry, upcase: &UpCase) -> bool {
+        if self.min_size.map(|v| entry.size < v).unwrap_or(false)
+            || self.max_size.map(|v| entry.size > v).unwrap_or(false)
+        {
+            return false;
+        }
+        if !in_range(entry.modified, self.modified_after, self.modified_before)
+            || !in_range(entry.created, self.created_after, self.created_before)
+        {
+            return false;
+        }
+        if self.extensions.is_empty() {
+            return true;
+        }
+        match entry.name.rsplit_once('.') {
+            Some((_, extension)) => self.extensions.iter().any(|v| upcase.eq(v, extension)),
+            None => false,
+        }
+    }
+
+    /// The header of the file must be read with `matches_content` to accept it
+    pub fn needs_content(&self) -> bool {
+        !self.signatures.is_empty()
+    }
+
+    /// Checks the signatures with the first `SIGNATURE_PROBE_SIZE` bytes of the file
+    pub fn matches_content(&self, header: &[u8]) -> bool {
+        self.signatures.is_empty() || self.signatures.iter().any(|v| v.matches(header))
+    }
+}
+
+/// Splits a target into its path and its conditions
+pub fn split_target(target: &str) -> (&str, Option<&str>) {
+    match target.split_once('|') {
+        Some((path, conditions)) => (path.trim_end(), Some(conditions)),
+        None => (target, None),
+    }
+}
+
+fn in_range(time: u64, after: Option<u64>, before: Option<u64>) -> bool {
+    time == 0 || (after.map(|v| time > v).unwrap_or(true) && before.map(|v| time < v).unwrap_or(true))
+}
+
+fn parse_size(value: &str) -> Option<u64> {
+    let upper = value.to_ascii_uppercase();
+    let (number, multiplier) = if let Some(v) = upper.strip_suffix("GB") {
+        (v, 1 << 30)
+    } else if let Some(v) = upper.strip_suffix("MB") {
+        (v, 1 << 20)
+    } else if let Some(v) = upper.strip_suffix("KB") {
+        (v, 1 << 10)
+    } else {
+        (upper.strip_suffix('B').unwrap_or(&upper), 1)
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Age in FILETIME ticks
+fn parse_age(value: &str) -> Option<u64> {
+    let unit = match value.chars().last()? {
+        'd' | 'D' => 86_400,
+        'h' | 'H' => 3_600,
+        'm' | 'M' => 60,
+        _ => return None,
+    };
+    let number: u64 = value[..value.len() - 1].parse().ok()?;
+    number.checked_mul(unit)?.checked_mul(FILETIME_SECOND)
+}
+
+/// `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS` in UTC to FILETIME
+fn parse_date(value: &str) -> Option<u64> {
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let mut date = date.split('-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+    if date.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+    if let Some(time) = time {
+        let mut parts = time.split(':');
+        let hours: i64 = parts.next()?.parse().ok()?;
+        let minutes: i64 = parts.next().unwrap_or("0").parse().ok()?;
+        let secs: i64 = parts.next().unwrap_or("0").parse().ok()?;
+        seconds += hours * 3_600 + minutes * 60 + secs;
+    }
+    let filetime = seconds.checked_mul(FILETIME_SECOND as i64)?.checked_add(UNIX_EPOCH_FILETIME)?;
+    u64::try_from(filetime).ok()
+}
+
+fn invalid_condition(condition: &str) -> ForensicError {
+    ForensicError::bad_format_string(format!("Invalid target condition {}", condition))
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+
+    fn entry(name: &str, size: u64, modified: u64) -> RawDirEntry {
+        RawDirEntry {
+            name: name.to_string(),
+            is_directory: false,
+            deleted: false,
+            size,
+            created: modified,
+            modified,
+            accessed: modified,
+        }
+    }
+
+    #[test]
+    fn should_filter_files_by_metadata() {
+        let day = 86_400 * FILETIME_SECOND;
+        let now = parse_date("2024-03-01").unwrap();
+        let upcase = UpCase::default();
+
+        let filter = FileFilter::parse("ext=*.ps1,.bat,vbs; modified>30d; size<10MB", now).unwrap();
+        assert_eq!(vec!["ps1", "bat", "vbs"], filter.extensions);
+        assert!(filter.matches(&entry("run.PS1", 100, now - 2 * day), &upcase));
+        assert!(filter.matches(&entry("run.bat", 100, 0), &upcase));
+        assert!(!filter.matches(&entry("run.bat", 100, now - 31 * day), &upcase));
+        assert!(!filter.matches(&entry("run.bat", 10 << 20, now), &upcase));
+        assert!(!filter.matches(&entry("run.exe", 100, now), &upcase));
+        assert!(!filter.matches(&entry("ps1", 100, now), &upcase));
+
+        let filter = FileFilter::parse("size>=1KB;modified<2024-01-01;created>2023-06-01T12:00:00", now).unwrap();
+        assert_eq!(Some(1024), filter.min_size);
+        assert!(filter.matches(&entry("a.log", 1024, parse_date("2023-12-31").unwrap()), &upcase));
+        assert!(!filter.matches(&entry("a.log", 1023, parse_date("2023-12-31").unwrap()), &upcase));
+        assert!(!filter.matches(&entry("a.log", 2048, parse_date("2024-01-01").unwrap()), &upcase));
+        assert!(!filter.matches(&entry("a.log", 2048, parse_date("2023-06-01").unwrap()), &upcase));
+
+        assert!(FileFilter::parse("", now).unwrap().is_empty());
+        assert!(FileFilter::parse("size<", now).is_err());
+        assert!(FileFilter::parse("owner=alice", now).is_err());
+        assert!(FileFilter::parse("modified>yesterday", now).is_err());
+        assert_eq!(
+            (r"C:\Users\**", Some("ext=ps1")),
+            split_target(r"C:\Users\** |ext=ps1")
+        );
+        assert_eq!((r"C:\Windows", None), split_target(r"C:\Windows"));
+    }
+
+    #[test]
+    fn should_filter_files_by_signature() {
+        let mut pe = vec![0; 0x100];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3C] = 0x80;
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let mut dos = pe.clone();
+        dos[0x80..0x84].copy_from_slice(b"NE\0\0");
+        let mut stub = pe[..0x40].to_vec();
+        stub[0x3C..0x40].copy_from_slice(&0x2000u32.to_le_bytes());
+
+        let filter = FileFilter::parse("type=pe,SQLite;size<20MB", 0).unwrap();
+        assert!(filter.needs_content());
+        assert_eq!(vec![FileSignature::Pe, FileSignature::Sqlite], filter.signatures);
+        assert!(filter.matches_content(&pe));
+        assert!(filter.matches_content(&stub));
+        assert!(!filter.matches_content(&dos));
+        assert!(filter.matches_content(b"SQLite format 3\0\x10\x00"));
+        assert!(!filter.matches_content(b"PK\x03\x04"));
+        assert!(!filter.matches_content(b""));
+        assert!(FileSignature::Evtx.matches(b"ElfFile\0\0\0"));
+        assert!(FileSignature::Ole2.matches(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1, 0]));
+        assert!(!FileFilter::parse("ext=exe", 0).unwrap().needs_content());
+        assert!(FileFilter::parse("type=elf", 0).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+//! Glob patterns over Windows paths.
+//!
+//! * `*` matches any sequence of characters inside a single name and `?` a single character.
+//! * `[abc]`, `[a-z]` and `[!abc]` match a character of a class.
+//! * `{a,b}` expands into one pattern per alternative, alternatives can contain `\`.
+//! * `**` matches any number of folders and `**3` at most 3 folders. At the end of a pattern it
+//!   matches every file of those folders.
+//! * A backtick escapes the next character, like in PowerShell: `` `[ `` matches a literal `[`.
+//!
+//! Names are compared ignoring case with the `UpCase` table of the file system being walked.
+
+use forensic_rs::prelude::{ForensicError, ForensicResult};
+
+use crate::upcase::UpCase;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+/// Pattern of a single file or folder name
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamePattern {
+    tokens: Vec<Token>,
+}
+
+impl NamePattern {
+    pub fn new(pattern: &str) -> ForensicResult<Self> {
+        let mut tokens = Vec::with_capacity(pattern.len());
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '`' => match chars.next() {
+                    Some(v) => tokens.push(Token::Literal(v)),
+                    None => return Err(invalid_pattern(pattern, "ends with an escape character")),
+                },
+                '*' => {
+                    if tokens.last() != Some(&Token::Star) {
+                        tokens.push(Token::Star);
+                    }
+                }
+                '?' => tokens.push(Token::Any),
+                '[' => {
+                    let negated = matches!(chars.peek(), Some('!') | Some('^'));
+                    if negated {
+                        chars.next();
+                    }
+                    let mut ranges = Vec::with_capacity(8);
+                    let mut closed = false;
+                    while let Some(c) = chars.next() {
+                        let start = match c {
+                            ']' if !ranges.is_empty() => {
+                                closed = true;
+                                break;
+                            }
+                            '`' => match chars.next() {
+                                Some(v) => v,
+                                None => break,
+                            },
+                            _ => c,
+                        };
+                        let mut end = start;
+                        if chars.peek() == Some(&'-') {
+                            let mut lookahead = chars.clone();
+                            lookahead.next();
+                            if let Some(v) = lookahead.next().filter(|v| *v != ']') {
+                                chars = lookahead;
+                                end = v;
+                            }
+                        }
+                        ranges.push((start, end));
+                    }
+                    if !closed {
+                        return Err(invalid_pattern(pattern, "has an unclosed character class"));
+                    }
+                    tokens.push(Token::Class { negated, ranges });
+                }
+                _ => tokens.push(Token::Literal(c)),
+            }
+        }
+        Ok(Self { tokens })
+    }
+
+    /// The name when the pattern has no wildcards
+    pub fn literal(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|v| match v {
+                Token::Literal(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, name: &str, upcase: &UpCase) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        let mut token = 0;
+        let mut position = 0;
+        // Last `*` seen and the position of the name it is currently absorbing up to
+        let mut backtrack: Option<(usize, usize)> = None;
+        while position < name.len() {
+            if let Some(Token::Star) = self.tokens.get(token) {
+                backtrack = Some((token, position));
+                token += 1;
+                continue;
+            }
+            let matched = match self.tokens.get(token) {
+                Some(Token::Literal(c)) => *c == name[position] || upcase.upcase(*c) == upcase.upcase(name[position]),
+                Some(Token::Any) => true,
+                Some(Token::Class { negated, ranges }) => {
+                    let c = name[position];
+                    let variants = [c, upcase.upcase(c), c.to_lowercase().next().unwrap_or(c)];
+                    let found = ranges
+                        .iter()
+                        .any(|(start, end)| variants.iter().any(|v| start <= v && v <= end));
+                    found != *negated
+                }
+                _ => false,
+            };
+            if matched {
+                token += 1;
+                position += 1;
+            } else if let Some((star, absorbed)) = backtrack {
+                token = star + 1;
+                position = absorbed + 1;
+                backtrack = Some((star, absorbed + 1));
+            } else {
+                return false;
+            }
+        }
+        self.tokens[token..].iter().all(|v| *v == Token::Star)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// `**`, with the maximum number of folders it can match
+    Recursive(Option<usize>),
+    Name(NamePattern),
+}
+
+impl Segment {
+    pub fn new(segment: &str) -> ForensicResult<Self> {
+        if let Some(depth) = segment.strip_prefix("**") {
+            if depth.is_empty() {
+                return Ok(Segment::Recursive(None));
+            }
+            if let Ok(depth) = depth.parse() {
+                return Ok(Segment::Recursive(Some(depth)));
+            }
+        }
+        Ok(Segment::Name(NamePattern::new(segment)?))
+    }
+}
+
+/// A pattern without alternatives: the folder where the wildcards start and the segments below it
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobPath {
+    pub base: String,
+    pub segments: Vec<Segment>,
+}
+
+impl GlobPath {
+    fn new(pattern: &str) -> ForensicResult<Self> {
+        let mut base = Vec::with_capacity(8);
+        let mut segments = Vec::with_capacity(8);
+        for part in pattern.split(['\\', '/']) {
+            let segment = Segment::new(part)?;
+            if segments.is_empty() {
+                if let Segment::Name(name) = &segment {
+                    if let Some(name) = name.literal() {
+                        base.push(name);
+                        continue;
+                    }
+                }
+            }
+            segments.push(segment);
+        }
+        let mut base = base.join("\\");
+        if base.ends_with(':') {
+            base.push('\\');
+        }
+        Ok(Self { base, segments })
+    }
+
+    fn matches(&self, path: &str, upcase: &UpCase) -> bool {
+        let base: Vec<&str> = components(&self.base).collect();
+        let path: Vec<&str> = components(path).collect();
+        if path.len() < base.len() || base.iter().zip(path.iter()).any(|(a, b)| !upcase.eq(a, b)) {
+            return false;
+        }
+        matches_segments(&self.segments, &path[base.len()..], upcase)
+    }
+
+    /// Checks whether every file below `folder` matches, which happens when the pattern ends with
+    /// an unbounded `**` and the rest of it matches the folder
+    fn matches_all_below(&self, folder: &str, upcase: &UpCase) -> bool {
+        let prefix = match self.segments.split_last() {
+            Some((Segment::Recursive(None), prefix)) => prefix,
+            _ => return false,
+        };
+        let base: Vec<&str> = components(&self.base).collect();
+        let folder: Vec<&str> = components(folder).collect();
+        if folder.len() < base.len() || base.iter().zip(folder.iter()).any(|(a, b)| !upcase.eq(a, b)) {
+            return false;
+        }
+        matches_segments(prefix, &folder[base.len()..], upcase)
+    }
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['\\', '/']).filter(|v| !v.is_empty())
+}
+
+fn matches_segments(segments: &[Segment], components: &[&str], upcase: &UpCase) -> bool {
+    match segments.first() {
+        None => components.is_empty(),
+        Some(Segment::Name(name)) => match components.first() {
+            Some(component) => {
+                name.matches(component, upcase) && matches_segments(&segments[1..], &components[1..], upcase)
+            }
+            None => false,
+        },
+        Some(Segment::Recursive(depth)) => {
+            if segments.len() == 1 {
+                // The last component is the file, the rest are the folders matched by `**`
+                return !components.is_empty() && depth.map(|v| components.len() - 1 <= v).unwrap_or(true);
+            }
+            let max = depth.unwrap_or(usize::MAX).min(components.len());
+            (0..=max).any(|skip| matches_segments(&segments[1..], &components[skip..], upcase))
+        }
+    }
+}
+
+/// Compiled glob. Patterns with `{a,b}` compile into one `GlobPath` per alternative.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    pub paths: Vec<GlobPath>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> ForensicResult<Self> {
+        let paths = expand_braces(pattern)?
+            .iter()
+            .map(|v| GlobPath::new(v))
+            .collect::<ForensicResult<Vec<_>>>()?;
+        Ok(Self { paths })
+    }
+
+    /// Checks a full path against the pattern
+    pub fn is_match(&self, path: &str, upcase: &UpCase) -> bool {
+        self.paths.iter().any(|v| v.matches(path, upcase))
+    }
+
+    /// Checks whether every file below a folder matches the pattern, so the folder can be skipped
+    /// when the pattern is an exclusion
+    pub fn matches_all_below(&self, folder: &str, upcase: &UpCase) -> bool {
+        self.paths.iter().any(|v| v.matches_all_below(folder, upcase))
+    }
+}
+
+/// Checks whether a path has characters with a special meaning in a glob
+pub fn has_wildcards(path: &str) -> bool {
+    path.contains(['*', '?', '[', '{', '`'])
+}
+
+/// Expands `{a,b}` alternatives, including nested ones, into every pattern they represent
+pub fn expand_braces(pattern: &str) -> ForensicResult<Vec<String>> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut open = None;
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '`' => i += 1,
+            '{' => {
+                if depth == 0 {
+                    open = Some(i);
+                    commas.clear();
+                }
+                depth += 1;
+            }
+            ',' if depth == 1 => commas.push(i),
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let start = open.unwrap_or_default();
+                    let prefix: String = chars[..start].iter().collect();
+                    let suffix: String = chars[i + 1..].iter().collect();
+                    let mut bounds = vec![start];
+                    bounds.extend(commas.iter().copied());
+                    bounds.push(i);
+                    let mut expanded = Vec::with_capacity(bounds.len());
+                    for pair in bounds.windows(2) {
+                        let alternative: String = chars[pair[0] + 1..pair[1]].iter().collect();
+                        expanded.extend(expand_braces(&format!("{}{}{}", prefix, alternative, suffix))?);
+                    }
+                    return Ok(expanded);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if depth > 0 {
+        return Err(invalid_pattern(pattern, "has an unclosed brace"));
+    }
+    Ok(vec![pattern.to_string()])
+}
+
+fn invalid_pattern(pattern: &str, reason: &str) -> ForensicError {
+    ForensicError::bad_format_string(format!("Pattern {} {}", pattern, reason))
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+
+    #[test]
+    fn should_match_names() {
+        let upcase = UpCase::default();
+        let pattern = NamePattern::new("$I*").unwrap();
+        assert!(pattern.matches("$IR5A0B1.txt", &upcase));
+        assert!(!pattern.matches("$RR5A0B1.txt", &upcase));
+        assert!(NamePattern::new("*.LOG?").unwrap().matches("SYSTEM.LOG1", &upcase));
+        assert!(!NamePattern::new("*.LOG?").unwrap().matches("SYSTEM.LOG", &upcase));
+        assert!(NamePattern::new("*a*b*c").unwrap().matches("xxaxxbxxbcxc", &upcase));
+        assert!(NamePattern::new("UsrClass.dat.LOG[12]").unwrap().matches("UsrClass.dat.LOG2", &upcase));
+        assert!(!NamePattern::new("UsrClass.dat.LOG[!12]").unwrap().matches("UsrClass.dat.LOG2", &upcase));
+        assert!(NamePattern::new("[a-c]x[-]").unwrap().matches("bx-", &upcase));
+        assert!(NamePattern::new("`[1`]*").unwrap().matches("[1] notes.txt", &upcase));
+        assert!(!NamePattern::new("`*").unwrap().matches("a", &upcase));
+        assert_eq!(Some("hosts".to_string()), NamePattern::new("hosts").unwrap().literal());
+        assert!(NamePattern::new("[abc").is_err());
+    }
+
+    #[test]
+    fn should_match_ignoring_case() {
+        let upcase = UpCase::default();
+        assert!(NamePattern::new("NTUser.DAT").unwrap().matches("NTUSER.DAT", &upcase));
+        assert!(NamePattern::new("*.EVTX").unwrap().matches("Security.evtx", &upcase));
+        assert!(NamePattern::new("[a-f]*.pf").unwrap().matches("CMD.EXE-0BD30981.PF", &upcase));
+        assert!(NamePattern::new("[A-F]*").unwrap().matches("cmd.exe", &upcase));
+        assert!(!NamePattern::new("[!a-f]*").unwrap().matches("CMD.EXE", &upcase));
+        let glob = Glob::new(r"C:\Windows\System32\Logfiles\W3SVC1\**").unwrap();
+        assert!(glob.is_match(r"c:\WINDOWS\system32\LogFiles\w3svc1\u_ex240101.log", &upcase));
+    }
+
+    #[test]
+    fn should_expand_braces() {
+        assert_eq!(
+            vec![
+                r"C:\Users\*\Chrome\History".to_string(),
+                r"C:\Users\*\Edge\History".to_string(),
+                r"C:\Users\*\Edge\Beta\History".to_string(),
+            ],
+            expand_braces(r"C:\Users\*\{Chrome,Edge{,\Beta}}\History").unwrap()
+        );
+        assert_eq!(vec!["a`{b,c}".to_string()], expand_braces("a`{b,c}").unwrap());
+        assert!(expand_braces("{a,b").is_err());
+    }
+
+    #[test]
+    fn should_compile_and_match_paths() {
+        let upcase = UpCase::default();
+        let glob = Glob::new(r"C:\$Recycle.Bin\**\$I*").unwrap();
+        assert_eq!(r"C:\$Recycle.Bin", glob.paths[0].base);
+        assert!(glob.is_match(r"C:\$Recycle.Bin\$I123456", &upcase));
+        assert!(glob.is_match(r"C:\$Recycle.Bin\S-1-5-21\$IR5A0B1.txt", &upcase));
+        assert!(!glob.is_match(r"C:\$Recycle.Bin\S-1-5-21\$RR5A0B1.txt", &upcase));
+
+        let glob = Glob::new(r"C:\Windows\*.log").unwrap();
+        assert!(glob.is_match(r"C:\Windows\setupact.log", &upcase));
+        assert!(!glob.is_match(r"C:\Windows\Panther\setupact.log", &upcase));
+
+        let glob = Glob::new(r"C:\Windows\Tasks\**1").unwrap();
+        assert!(glob.is_match(r"C:\Windows\Tasks\a.job", &upcase));
+        assert!(glob.is_match(r"C:\Windows\Tasks\Sub\a.job", &upcase));
+        assert!(!glob.is_match(r"C:\Windows\Tasks\Sub\Deeper\a.job", &upcase));
+        assert!(Glob::new(r"C:\Windows\Tasks\**").unwrap().is_match(r"C:\Windows\Tasks\Sub\Deeper\a.job", &upcase));
+
+        let glob = Glob::new(r"C:\Users\*\AppData\Local\{Google\Chrome,Microsoft\Edge}\User Data\*\History").unwrap();
+        assert_eq!(2, glob.paths.len());
+        assert_eq!(r"C:\Users", glob.paths[1].base);
+        assert!(glob.is_match(r"C:\Users\Alice\AppData\Local\Microsoft\Edge\User Data\Default\History", &upcase));
+    }
+
+    #[test]
+    fn should_match_whole_folders() {
+        let upcase = UpCase::default();
+        let glob = Glob::new(r"**\cache2\**").unwrap();
+        assert!(glob.is_match(r"C:\Users\Alice\AppData\Local\Mozilla\Firefox\Profiles\x.default\cache2\entries\A1", &upcase));
+        assert!(glob.matches_all_below(r"C:\Users\Alice\AppData\Local\Mozilla\Firefox\Profiles\x.default\cache2", &upcase));
+        assert!(!glob.matches_all_below(r"C:\Users\Alice\AppData\Local\Mozilla", &upcase));
+        assert!(!Glob::new(r"**\cache2\*").unwrap().matches_all_below(r"C:\cache2", &upcase));
+    }
+}
@@ -1,4 +1,5 @@
 use forensic_rs::err::{ForensicError, ForensicResult};
+#[cfg(windows)]
 use windows::{
     core::PCWSTR,
     Win32::{
@@ -23,83 +24,6 @@ pub fn encode_pcwstr(txt: &str, buffer : &mut Vec<u16>) {
     buffer.push(0);
 }
 
-pub fn replace_envvars(
-    txt: &str,
-    system_drive: &str,
-    system_root: &str,
-    prog_data: &str,
-) -> String {
-    let pos = match txt[..].find(r"%\") {
-        Some(pos) => pos,
-        None => return txt.to_string(),
-    };
-
-    let variable = &txt[1..pos];
-    if is_system_drive_env(variable) {
-        return if system_drive.ends_with(r"\") {
-            format!(r"{}{}", system_drive, &txt[pos + 2..])
-        } else {
-            format!(r"{}\{}", system_drive, &txt[pos + 2..])
-        };
-    } else if is_system_root_env(variable) {
-        return if system_root.ends_with(r"\") {
-            format!(r"{}{}", system_root, &txt[pos + 2..])
-        } else {
-            format!(r"{}\{}", system_root, &txt[pos + 2..])
-        };
-    } else if is_program_data_env(variable) {
-        return if prog_data.ends_with(r"\") {
-            format!(r"{}{}", prog_data, &txt[pos + 2..])
-        } else {
-            format!(r"{}\{}", prog_data, &txt[pos + 2..])
-        };
-    }
-    txt.to_string()
-}
-
-pub fn replace_home_vars(txt: &str, homes: &Vec<String>) -> Vec<String> {
-    let pos = match txt[..].find(r"%\") {
-        Some(pos) => pos,
-        None => return vec![],
-    };
-    let mut to_ret = Vec::with_capacity(32);
-    for home in homes {
-        if home.ends_with(r"\") {
-            to_ret.push(format!(r"{}{}", home, &txt[pos + 2..]));
-        } else {
-            to_ret.push(format!(r"{}\{}", home, &txt[pos + 2..]));
-        }
-    }
-    to_ret
-}
-
-pub fn contains_env_var(txt: &str) -> bool {
-    txt.starts_with("%")
-}
-
-pub fn is_system_drive_env(txt: &str) -> bool {
-    if txt.len() != "systemdrive".len() {
-        return false;
-    }
-    txt.to_uppercase() == "SYSTEMDRIVE"
-}
-pub fn is_system_root_env(txt: &str) -> bool {
-    if txt.len() != "systemroot".len() {
-        return false;
-    }
-    txt.to_uppercase() == "SYSTEMROOT"
-}
-pub fn is_program_data_env(txt: &str) -> bool {
-    if txt.len() != "programdata".len() {
-        return false;
-    }
-    txt.to_uppercase() == "PROGRAMDATA"
-}
-
-pub fn is_user_home_env(txt: &str) -> bool {
-    txt.starts_with("%USERHOME%")
-}
-
 /// Obtains the Drive path and Disk base path
 pub fn get_drive_and_disk(pth: &str) -> ForensicResult<(String, String)> {
     let position = match pth.find(":") {
@@ -121,6 +45,7 @@ pub fn get_drive_and_disk(pth: &str) -> ForensicResult<(String, String)> {
     ))
 }
 
+#[cfg(windows)]
 pub fn get_drive_metadata(pth: &str, buffer : &mut Buffer) -> ForensicResult<(HANDLE, u32, u32)> {
     let (drive_path, disk_letter) = get_drive_and_disk(pth)?;
     let mut disk_name = buffer.u16_vec();
@@ -162,6 +87,7 @@ pub fn get_drive_metadata(pth: &str, buffer : &mut Buffer) -> ForensicResult<(HA
     Ok((disk_pointer, sectors_in_cluster, bytes_per_sector))
 }
 
+#[cfg(windows)]
 pub fn get_file_pointer_and_size(pth: &str, buffer : &mut Buffer) -> ForensicResult<(HANDLE, u64)>{
     let filename = format!("\\\\.\\{}\0", pth);
     let mut buff = buffer.u16_vec();
@@ -187,6 +113,7 @@ pub fn get_file_pointer_and_size(pth: &str, buffer : &mut Buffer) -> ForensicRes
     Ok((file_pointer, file_size))
 }
 
+#[cfg(windows)]
 pub fn get_retrieval_pointers(file_pointer : HANDLE, buffer : &mut Buffer) -> ForensicResult<RetrievalPointersBuffer> {
     let mut in_buffer = STARTING_VCN_INPUT_BUFFER::default();
     let mut bytes_returned = 0;
@@ -208,6 +135,7 @@ pub fn get_retrieval_pointers(file_pointer : HANDLE, buffer : &mut Buffer) -> Fo
     Ok(retrieval_pinters)
 }
 
+#[cfg(windows)]
 pub fn move_disk_position(disk_pointer : HANDLE, offset : i64) -> Result<(), std::io::Error> {
     if let Err(e) = unsafe{ SetFilePointerEx(disk_pointer, offset, None, FILE_BEGIN) } {
         let res : i32 = e.code().0;
@@ -217,6 +145,7 @@ pub fn move_disk_position(disk_pointer : HANDLE, offset : i64) -> Result<(), std
     Ok(())
 }
 
+#[cfg(windows)]
 pub fn read_file_from_disk_pointer_buffered(disk_pointer : HANDLE, buffer : &mut Buffer, to_be_readed : u32) -> Result<u32, std::io::Error> {
     let buf = buffer.u8();
     let buf = &mut buf[0..to_be_readed as usize];
@@ -234,6 +163,7 @@ pub fn read_file_from_disk_pointer_buffered(disk_pointer : HANDLE, buffer : &mut
     Ok(readed_bytes)
 }
 
+#[cfg(windows)]
 pub fn read_file_from_disk_pointer(disk_pointer : HANDLE, buf : &mut [u8], to_be_readed : u32) -> Result<u32, std::io::Error> {
     if buf.len() < to_be_readed as usize {
         return Err(std::io::Error::from_raw_os_error(ERROR_INSUFFICIENT_BUFFER.0 as _))
@@ -337,4 +267,157 @@ impl Buffer {
             self.u16.set_len(u16l);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Reads a little endian u16 at `offset`. Returns 0 if the buffer is too short.
+pub fn le_u16(buf: &[u8], offset: usize) -> u16 {
+    match buf.get(offset..offset + 2) {
+        Some(v) => u16::from_le_bytes([v[0], v[1]]),
+        None => 0,
+    }
+}
+
+/// Reads a little endian u32 at `offset`. Returns 0 if the buffer is too short.
+pub fn le_u32(buf: &[u8], offset: usize) -> u32 {
+    match buf.get(offset..offset + 4) {
+        Some(v) => u32::from_le_bytes([v[0], v[1], v[2], v[3]]),
+        None => 0,
+    }
+}
+
+/// Reads a little endian u64 at `offset`. Returns 0 if the buffer is too short.
+pub fn le_u64(buf: &[u8], offset: usize) -> u64 {
+    match buf.get(offset..offset + 8) {
+        Some(v) => u64::from_le_bytes([v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]]),
+        None => 0,
+    }
+}
+
+/// Decodes `chars` UTF-16LE code units starting at `offset`
+pub fn utf16_string(buf: &[u8], offset: usize, chars: usize) -> String {
+    let end = (offset + chars * 2).min(buf.len());
+    if offset >= end {
+        return String::new();
+    }
+    let units: Vec<u16> = buf[offset..end]
+        .chunks_exact(2)
+        .map(|v| u16::from_le_bytes([v[0], v[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Formats a GUID stored in its mixed-endian on-disk representation
+pub fn format_guid(buf: &[u8]) -> String {
+    if buf.len() < 16 {
+        return String::new();
+    }
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        le_u32(buf, 0),
+        le_u16(buf, 4),
+        le_u16(buf, 6),
+        buf[8],
+        buf[9],
+        buf[10],
+        buf[11],
+        buf[12],
+        buf[13],
+        buf[14],
+        buf[15]
+    )
+}
+
+/// Days between 1970-01-01 and the given civil date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Converts a MS-DOS date and time (FAT, exFAT) to FILETIME. Returns 0 for empty dates.
+pub fn dos_datetime_to_filetime(date: u16, time: u16, centiseconds: u8) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0x0F).clamp(1, 12) as u32;
+    let day = (date & 0x1F).max(1) as u32;
+    let seconds = (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3F) as i64 * 60 + (time & 0x1F) as i64 * 2;
+    let unix = days_from_civil(year, month, day) * 86_400 + seconds;
+    ((unix + 11_644_473_600) * 10_000_000 + centiseconds as i64 * 100_000) as u64
+}
+
+/// Converts a FILETIME to the seconds since the UNIX epoch used by `VMetadata`. `None` for empty
+/// times and times before 1970.
+pub fn filetime_to_unix_seconds(filetime: u64) -> Option<usize> {
+    (filetime / 10_000_000).checked_sub(11_644_473_600).map(|v| v as usize)
+}
+
+/// Converts a time of the local file system to FILETIME. Returns 0 for times before 1601.
+pub fn system_time_to_filetime(time: std::time::SystemTime) -> u64 {
+    let unix = match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(v) => v.as_nanos() as i128 / 100,
+        Err(e) => -(e.duration().as_nanos() as i128 / 100),
+    };
+    (unix + 116_444_736_000_000_000).max(0) as u64
+}
+
+/// Folders of files created below the temporary folder to test the logical backends
+#[cfg(test)]
+pub(crate) mod test_tree {
+    use std::path::{Path, PathBuf};
+
+    /// Folder `frnsc-triage-<name>-<pid>` of the temporary folder, removed when dropped
+    pub(crate) struct TestTree {
+        root: PathBuf,
+    }
+
+    impl TestTree {
+        /// Creates the folder with the files at `paths`, relative to it with `\` separators, each one
+        /// containing `data`
+        pub fn new(name: &str, paths: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!("frnsc-triage-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            let tree = Self { root };
+            for path in paths {
+                tree.write(path, b"data");
+            }
+            tree
+        }
+
+        pub fn root(&self) -> &Path {
+            &self.root
+        }
+
+        /// Host path of `path`, relative to the root with `\` separators
+        pub fn path(&self, path: &str) -> PathBuf {
+            path.split('\\').filter(|v| !v.is_empty()).fold(self.root.clone(), |path, v| path.join(v))
+        }
+
+        /// Creates the folder at `path` and its parents
+        pub fn create_dir(&self, path: &str) -> PathBuf {
+            let path = self.path(path);
+            std::fs::create_dir_all(&path).unwrap();
+            path
+        }
+
+        /// Writes the file at `path`, creating its folders
+        pub fn write(&self, path: &str, data: &[u8]) -> PathBuf {
+            let path = self.path(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, data).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestTree {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+}
@@ -1,5 +1,20 @@
+#[cfg(windows)]
 pub mod raw_file;
 pub mod triage;
+pub mod backend;
 pub mod artifacts;
 pub mod sys_vars;
-pub mod helpers;
\ No newline at end of file
+pub mod environment;
+pub mod regf;
+pub mod offline_registry;
+pub mod mount_info;
+pub mod helpers;
+pub mod ntfs;
+pub mod vss;
+pub mod volume;
+pub mod fat;
+pub mod exfat;
+pub mod vfs;
+pub mod glob;
+pub mod filter;
+pub mod upcase;
@@ -0,0 +1,142 @@
+//! Mounts of a Linux host read from `/proc/self/mountinfo`, used to describe the folders that a
+//! `LogicalBackend` maps as Windows drives: a FAT USB stick, a DVD or an SMB share mounted there is
+//! not searched for NTFS metadata files.
+
+use std::path::{Path, PathBuf};
+
+use forensic_rs::prelude::ForensicResult;
+
+use crate::backend::{DriveType, MountedVolume};
+
+const MOUNT_INFO_PATH: &str = "/proc/self/mountinfo";
+
+/// File system mounted in a folder of the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    pub mount_point: PathBuf,
+    /// File system type as named by Linux: `ntfs3`, `vfat`, `iso9660`...
+    pub fs_type: String,
+    /// Device or share mounted: `/dev/sdb1`, `//server/share`...
+    pub source: String,
+}
+
+/// Mount table of the host
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountInfo {
+    mounts: Vec<MountEntry>,
+}
+
+impl MountInfo {
+    /// Parses the content of a `mountinfo` file. Malformed lines are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mounts = text
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                let separator = fields.iter().position(|v| *v == "-")?;
+                if separator < 6 {
+                    return None;
+                }
+                Some(MountEntry {
+                    mount_point: PathBuf::from(unescape(fields[4])),
+                    fs_type: fields.get(separator + 1)?.to_string(),
+                    source: unescape(fields.get(separator + 2)?),
+                })
+            })
+            .collect();
+        Self { mounts }
+    }
+
+    /// Reads the mounts of the current process
+    pub fn read() -> ForensicResult<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(MOUNT_INFO_PATH)?))
+    }
+
+    /// Mount that contains `path`: the one with the longest mount point. The last mounted wins
+    /// when several share it.
+    pub fn mount_of(&self, path: &Path) -> Option<&MountEntry> {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.mounts
+            .iter()
+            .rev()
+            .filter(|v| path.starts_with(&v.mount_point))
+            .max_by_key(|v| v.mount_point.components().count())
+    }
+
+    /// Describes the folder `path` mapped as the volume `root`. The file system is unknown when
+    /// the folder is not in the table.
+    pub fn volume(&self, root: &str, path: &Path) -> MountedVolume {
+        let volume = MountedVolume::new(root);
+        let mount = match self.mount_of(path) {
+            Some(v) => v,
+            None => return volume,
+        };
+        let (drive_type, file_system) = match mount.fs_type.as_str() {
+            "ntfs" | "ntfs3" => (DriveType::Fixed, "NTFS"),
+            "vfat" | "msdos" => (DriveType::Fixed, "FAT"),
+            "exfat" => (DriveType::Fixed, "exFAT"),
+            "iso9660" => (DriveType::Optical, "CDFS"),
+            "udf" => (DriveType::Optical, "UDF"),
+            "nfs" | "nfs4" | "cifs" | "smb3" | "smbfs" => (DriveType::Network, mount.fs_type.as_str()),
+            "tmpfs" | "ramfs" => (DriveType::RamDisk, mount.fs_type.as_str()),
+            // ntfs-3g and other FUSE file systems do not tell what they are mounting
+            v if v.starts_with("fuse") => (DriveType::Fixed, ""),
+            v => (DriveType::Fixed, v),
+        };
+        volume.with_drive_type(drive_type).with_file_system(file_system)
+    }
+}
+
+/// Replaces the octal escapes of `mountinfo` fields, like `\040` for spaces
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        let octal = bytes.get(position + 1..position + 4).unwrap_or_default();
+        if bytes[position] == b'\\' && octal.len() == 3 && octal.iter().all(|v| (b'0'..=b'7').contains(v)) {
+            let value = octal.iter().fold(0u32, |acc, v| acc * 8 + (v - b'0') as u32);
+            unescaped.push(value as u8);
+            position += 4;
+            continue;
+        }
+        unescaped.push(bytes[position]);
+        position += 1;
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+
+    const MOUNT_INFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 8:17 / /mnt/evidence rw,relatime shared:2 - ntfs3 /dev/sdb1 rw,uid=0
+36 22 8:33 / /media/usb\\040stick rw,relatime shared:3 - vfat /dev/sdc1 rw,fmask=0022
+37 22 11:0 / /media/cdrom ro,relatime shared:4 - iso9660 /dev/sr0 ro
+38 22 0:45 / /mnt/share rw,relatime shared:5 - cifs //server/share rw,vers=3.1.1
+39 35 0:46 / /mnt/evidence/tmp rw - tmpfs tmpfs rw
+broken line
+";
+
+    #[test]
+    fn should_describe_volumes_from_mount_info() {
+        let mount_info = MountInfo::parse(MOUNT_INFO);
+        let usb = mount_info.mount_of(Path::new("/media/usb stick/DCIM")).unwrap();
+        assert_eq!("vfat", usb.fs_type);
+        assert_eq!("/dev/sdc1", usb.source);
+
+        let evidence = mount_info.volume(r"C:\", Path::new("/mnt/evidence/Windows"));
+        assert_eq!(MountedVolume::new(r"C:\").with_file_system("NTFS"), evidence);
+        assert!(evidence.may_be_ntfs());
+        let usb = mount_info.volume(r"D:\", Path::new("/media/usb stick"));
+        assert_eq!("FAT", usb.file_system);
+        assert!(!usb.may_be_ntfs());
+        assert_eq!(DriveType::Optical, mount_info.volume(r"E:\", Path::new("/media/cdrom")).drive_type);
+        assert_eq!(DriveType::Network, mount_info.volume(r"Z:\", Path::new("/mnt/share")).drive_type);
+        assert_eq!(DriveType::RamDisk, mount_info.volume(r"T:\", Path::new("/mnt/evidence/tmp")).drive_type);
+        assert_eq!("ext4", mount_info.volume(r"F:\", Path::new("/home")).file_system);
+        assert_eq!(MountedVolume::new(r"G:\"), MountInfo::default().volume(r"G:\", Path::new("/home")));
+    }
+}
@@ -0,0 +1,1241 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use forensic_rs::prelude::{ForensicError, ForensicResult};
+
+use crate::{
+    helpers::{le_u16, le_u32, le_u64, utf16_string},
+    upcase::UpCase,
+    volume::{OpenedFile, RawDirEntry, RawFileSystem, VolumeRef},
+};
+
+/// MFT record number of the root directory
+pub const ROOT_RECORD: u64 = 5;
+pub const UPCASE_RECORD: u64 = 10;
+
+pub const ATTR_STANDARD_INFORMATION: u32 = 0x10;
+pub const ATTR_ATTRIBUTE_LIST: u32 = 0x20;
+pub const ATTR_FILE_NAME: u32 = 0x30;
+pub const ATTR_DATA: u32 = 0x80;
+pub const ATTR_INDEX_ROOT: u32 = 0x90;
+pub const ATTR_INDEX_ALLOCATION: u32 = 0xA0;
+pub const ATTR_BITMAP: u32 = 0xB0;
+const ATTR_END: u32 = 0xFFFF_FFFF;
+
+const RECORD_IN_USE: u16 = 0x0001;
+const RECORD_IS_DIRECTORY: u16 = 0x0002;
+const ATTR_FLAG_COMPRESSED: u16 = 0x0001;
+const FILE_NAME_DOS: u8 = 2;
+const INDEX_ENTRY_LAST: u32 = 0x02;
+/// Largest MFT and index record accepted from the boot sector
+const MAX_RECORD_SIZE: u64 = 64 * 1024;
+/// Largest metadata stream read at once: $UpCase, attribute lists and index bitmaps
+const MAX_METADATA_SIZE: u64 = 4 * 1024 * 1024;
+/// Size of the chunks used to read whole streams
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Parsed NTFS boot sector
+#[derive(Debug, Clone)]
+pub struct NtfsBootSector {
+    pub bytes_per_sector: u64,
+    pub cluster_size: u64,
+    pub total_sectors: u64,
+    pub mft_lcn: u64,
+    pub mft_record_size: u64,
+    pub index_record_size: u64,
+    pub serial_number: u64,
+}
+
+impl NtfsBootSector {
+    pub fn parse(buf: &[u8]) -> ForensicResult<Self> {
+        if buf.len() < 512 || &buf[3..11] != b"NTFS    " {
+            return Err(ForensicError::bad_format_str("Not an NTFS boot sector"));
+        }
+        let bytes_per_sector = le_u16(buf, 0x0B) as u64;
+        let sectors_per_cluster = match buf[0x0D] {
+            v if v > 0x80 => shifted_size(256 - v as u32)?,
+            v => v as u64,
+        };
+        if bytes_per_sector == 0 || sectors_per_cluster == 0 {
+            return Err(ForensicError::bad_format_str("Invalid NTFS cluster geometry"));
+        }
+        let cluster_size = bytes_per_sector * sectors_per_cluster;
+        Ok(Self {
+            bytes_per_sector,
+            cluster_size,
+            total_sectors: le_u64(buf, 0x28),
+            mft_lcn: le_u64(buf, 0x30),
+            mft_record_size: record_size(buf[0x40] as i8, cluster_size)?,
+            index_record_size: record_size(buf[0x44] as i8, cluster_size)?,
+            serial_number: le_u64(buf, 0x48),
+        })
+    }
+}
+
+/// Sizes in the boot sector are expressed in clusters, or as a power of two when negative
+fn record_size(value: i8, cluster_size: u64) -> ForensicResult<u64> {
+    let size = if value < 0 {
+        shifted_size(value.unsigned_abs() as u32)?
+    } else {
+        value as u64 * cluster_size
+    };
+    if !(512..=MAX_RECORD_SIZE).contains(&size) {
+        return Err(ForensicError::bad_format_string(format!("Invalid NTFS record size {}", size)));
+    }
+    Ok(size)
+}
+
+/// Power of two stored as its exponent in the boot sector
+fn shifted_size(shift: u32) -> ForensicResult<u64> {
+    if shift > 31 {
+        return Err(ForensicError::bad_format_str("Invalid NTFS size exponent"));
+    }
+    Ok(1u64 << shift)
+}
+
+/// Contiguous group of clusters of a non-resident attribute. Sparse runs have no LCN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataRun {
+    pub vcn: u64,
+    pub length: u64,
+    pub lcn: Option<u64>,
+}
+
+/// Decodes the mapping pairs of a non-resident attribute starting at `starting_vcn`
+pub fn decode_data_runs(buf: &[u8], starting_vcn: u64) -> ForensicResult<Vec<DataRun>> {
+    let mut runs = Vec::with_capacity(8);
+    let mut pos = 0;
+    let mut vcn = starting_vcn;
+    let mut lcn: i64 = 0;
+    while pos < buf.len() && buf[pos] != 0 {
+        let length_size = (buf[pos] & 0x0F) as usize;
+        let offset_size = (buf[pos] >> 4) as usize;
+        pos += 1;
+        if length_size == 0 || length_size > 8 || offset_size > 8 || pos + length_size + offset_size > buf.len() {
+            return Err(ForensicError::bad_format_str("Invalid NTFS data run"));
+        }
+        let length = read_var_uint(&buf[pos..pos + length_size]);
+        pos += length_size;
+        let run_lcn = if offset_size == 0 {
+            None
+        } else {
+            lcn = match lcn.checked_add(read_var_int(&buf[pos..pos + offset_size])) {
+                Some(v) => v,
+                None => return Err(ForensicError::bad_format_str("Invalid LCN in NTFS data run")),
+            };
+            pos += offset_size;
+            if lcn < 0 {
+                return Err(ForensicError::bad_format_str("Negative LCN in NTFS data run"));
+            }
+            Some(lcn as u64)
+        };
+        runs.push(DataRun {
+            vcn,
+            length,
+            lcn: run_lcn,
+        });
+        vcn = match vcn.checked_add(length) {
+            Some(v) => v,
+            None => return Err(ForensicError::bad_format_str("Invalid length in NTFS data run")),
+        };
+    }
+    Ok(runs)
+}
+
+fn read_var_uint(buf: &[u8]) -> u64 {
+    buf.iter()
+        .rev()
+        .fold(0u64, |acc, v| (acc << 8) | *v as u64)
+}
+
+fn read_var_int(buf: &[u8]) -> i64 {
+    let value = read_var_uint(buf);
+    let bits = buf.len() * 8;
+    if bits < 64 && (value >> (bits - 1)) & 1 == 1 {
+        (value | (!0u64 << bits)) as i64
+    } else {
+        value as i64
+    }
+}
+
+/// Applies the update sequence array of a FILE or INDX record
+pub fn apply_fixups(buf: &mut [u8]) -> ForensicResult<()> {
+    let usa_offset = le_u16(buf, 4) as usize;
+    let usa_count = le_u16(buf, 6) as usize;
+    if usa_count < 2 || usa_offset + usa_count * 2 > buf.len() {
+        return Err(ForensicError::bad_format_str("Invalid update sequence array"));
+    }
+    let stride = buf.len() / (usa_count - 1);
+    if stride < 2 {
+        return Err(ForensicError::bad_format_str("Invalid update sequence array"));
+    }
+    let usn = [buf[usa_offset], buf[usa_offset + 1]];
+    for i in 1..usa_count {
+        let end = i * stride;
+        if buf[end - 2..end] != usn {
+            return Err(ForensicError::bad_format_str("Update sequence mismatch, torn record"));
+        }
+        buf[end - 2] = buf[usa_offset + i * 2];
+        buf[end - 1] = buf[usa_offset + i * 2 + 1];
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub enum AttributeContent {
+    Resident(Vec<u8>),
+    NonResident {
+        runs: Vec<DataRun>,
+        data_size: u64,
+        initialized_size: u64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub type_code: u32,
+    pub name: String,
+    pub id: u16,
+    pub flags: u16,
+    pub starting_vcn: u64,
+    pub content: AttributeContent,
+}
+
+impl Attribute {
+    pub fn is_compressed(&self) -> bool {
+        self.flags & ATTR_FLAG_COMPRESSED != 0
+    }
+
+    pub fn data_size(&self) -> u64 {
+        match &self.content {
+            AttributeContent::Resident(v) => v.len() as u64,
+            AttributeContent::NonResident { data_size, .. } => *data_size,
+        }
+    }
+
+    pub fn resident_data(&self) -> Option<&[u8]> {
+        match &self.content {
+            AttributeContent::Resident(v) => Some(&v[..]),
+            _ => None,
+        }
+    }
+
+    fn parse(buf: &[u8]) -> ForensicResult<Self> {
+        let type_code = le_u32(buf, 0);
+        let non_resident = buf.get(8).copied().unwrap_or(0) != 0;
+        let name_length = buf.get(9).copied().unwrap_or(0) as usize;
+        let name_offset = le_u16(buf, 10) as usize;
+        let name = utf16_string(buf, name_offset, name_length);
+        let flags = le_u16(buf, 12);
+        let id = le_u16(buf, 14);
+        if non_resident {
+            if buf.len() < 64 {
+                return Err(ForensicError::bad_format_str("Truncated non-resident attribute"));
+            }
+            let starting_vcn = le_u64(buf, 16);
+            let runs_offset = le_u16(buf, 32) as usize;
+            if runs_offset > buf.len() {
+                return Err(ForensicError::bad_format_str("Invalid data runs offset"));
+            }
+            let runs = decode_data_runs(&buf[runs_offset..], starting_vcn)?;
+            Ok(Self {
+                type_code,
+                name,
+                id,
+                flags,
+                starting_vcn,
+                content: AttributeContent::NonResident {
+                    runs,
+                    data_size: le_u64(buf, 48),
+                    initialized_size: le_u64(buf, 56),
+                },
+            })
+        } else {
+            let value_length = le_u32(buf, 16) as usize;
+            let value_offset = le_u16(buf, 20) as usize;
+            let value = match buf.get(value_offset..value_offset + value_length) {
+                Some(v) => v.to_vec(),
+                None => return Err(ForensicError::bad_format_str("Truncated resident attribute")),
+            };
+            Ok(Self {
+                type_code,
+                name,
+                id,
+                flags,
+                starting_vcn: 0,
+                content: AttributeContent::Resident(value),
+            })
+        }
+    }
+}
+
+/// NTFS timestamps and attributes stored in $STANDARD_INFORMATION or $FILE_NAME, as FILETIME
+#[derive(Debug, Clone, Default)]
+pub struct NtfsTimes {
+    pub created: u64,
+    pub modified: u64,
+    pub mft_modified: u64,
+    pub accessed: u64,
+}
+
+impl NtfsTimes {
+    fn parse(buf: &[u8]) -> Self {
+        Self {
+            created: le_u64(buf, 0),
+            modified: le_u64(buf, 8),
+            mft_modified: le_u64(buf, 16),
+            accessed: le_u64(buf, 24),
+        }
+    }
+}
+
+/// Content of a $FILE_NAME attribute
+#[derive(Debug, Clone)]
+pub struct FileName {
+    pub parent: u64,
+    pub times: NtfsTimes,
+    pub allocated_size: u64,
+    pub size: u64,
+    pub flags: u32,
+    pub namespace: u8,
+    pub name: String,
+}
+
+impl FileName {
+    pub fn parse(buf: &[u8]) -> ForensicResult<Self> {
+        if buf.len() < 66 {
+            return Err(ForensicError::bad_format_str("Truncated $FILE_NAME"));
+        }
+        Ok(Self {
+            parent: le_u64(buf, 0) & 0x0000_FFFF_FFFF_FFFF,
+            times: NtfsTimes::parse(&buf[8..]),
+            allocated_size: le_u64(buf, 40),
+            size: le_u64(buf, 48),
+            flags: le_u32(buf, 56),
+            namespace: buf[65],
+            name: utf16_string(buf, 66, buf[64] as usize),
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & 0x1000_0000 != 0
+    }
+}
+
+/// MFT entry with all its attributes, including the ones stored in extension records
+#[derive(Debug, Clone)]
+pub struct MftRecord {
+    pub number: u64,
+    pub sequence: u16,
+    pub flags: u16,
+    pub base_record: u64,
+    pub attributes: Vec<Attribute>,
+}
+
+impl MftRecord {
+    pub fn parse(number: u64, buf: &mut [u8]) -> ForensicResult<Self> {
+        if buf.len() < 48 || &buf[0..4] != b"FILE" {
+            return Err(ForensicError::bad_format_string(format!(
+                "MFT record {} has no FILE signature",
+                number
+            )));
+        }
+        apply_fixups(buf)?;
+        let used_size = (le_u32(buf, 24) as usize).min(buf.len());
+        let mut offset = le_u16(buf, 20) as usize;
+        let mut attributes = Vec::with_capacity(8);
+        while offset + 8 <= used_size {
+            let type_code = le_u32(buf, offset);
+            if type_code == ATTR_END {
+                break;
+            }
+            let length = le_u32(buf, offset + 4) as usize;
+            if length < 16 || offset + length > used_size {
+                return Err(ForensicError::bad_format_string(format!(
+                    "Invalid attribute length in MFT record {}",
+                    number
+                )));
+            }
+            attributes.push(Attribute::parse(&buf[offset..offset + length])?);
+            offset += length;
+        }
+        Ok(Self {
+            number,
+            sequence: le_u16(buf, 16),
+            flags: le_u16(buf, 22),
+            base_record: le_u64(buf, 32) & 0x0000_FFFF_FFFF_FFFF,
+            attributes,
+        })
+    }
+
+    pub fn in_use(&self) -> bool {
+        self.flags & RECORD_IN_USE != 0
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & RECORD_IS_DIRECTORY != 0
+    }
+
+    pub fn attribute(&self, type_code: u32, name: &str) -> Option<&Attribute> {
+        self.attributes
+            .iter()
+            .find(|v| v.type_code == type_code && v.name == name)
+    }
+
+    /// Standard information timestamps
+    pub fn times(&self) -> NtfsTimes {
+        match self
+            .attribute(ATTR_STANDARD_INFORMATION, "")
+            .and_then(|v| v.resident_data())
+        {
+            Some(v) => NtfsTimes::parse(v),
+            None => NtfsTimes::default(),
+        }
+    }
+
+    /// Preferred (non DOS) file name of the record
+    pub fn file_name(&self) -> Option<FileName> {
+        let mut found = None;
+        for attr in &self.attributes {
+            if attr.type_code != ATTR_FILE_NAME {
+                continue;
+            }
+            let fname = match attr.resident_data().map(FileName::parse) {
+                Some(Ok(v)) => v,
+                _ => continue,
+            };
+            if fname.namespace != FILE_NAME_DOS {
+                return Some(fname);
+            }
+            found = Some(fname);
+        }
+        found
+    }
+
+    /// Size of the unnamed $DATA stream
+    pub fn size(&self) -> u64 {
+        self.attribute(ATTR_DATA, "").map(|v| v.data_size()).unwrap_or(0)
+    }
+}
+
+/// Entry of a directory index
+#[derive(Debug, Clone)]
+pub struct NtfsDirEntry {
+    pub name: String,
+    pub record: u64,
+    pub is_directory: bool,
+    pub size: u64,
+    pub times: NtfsTimes,
+}
+
+/// Stream of a file: either the content of a resident attribute or the runs on disk
+#[derive(Debug, Clone)]
+pub struct NtfsStream {
+    pub record: u64,
+    pub content: AttributeContent,
+}
+
+impl NtfsStream {
+    pub fn size(&self) -> u64 {
+        match &self.content {
+            AttributeContent::Resident(v) => v.len() as u64,
+            AttributeContent::NonResident { data_size, .. } => *data_size,
+        }
+    }
+}
+
+/// Read only access to an NTFS file system stored in a raw volume, an image or a shadow copy.
+pub struct NtfsVolume<R: Read + Seek> {
+    reader: R,
+    pub boot: NtfsBootSector,
+    mft: NtfsStream,
+    /// Table used by the volume to compare names ignoring case
+    pub upcase: UpCase,
+}
+
+impl<R: Read + Seek> NtfsVolume<R> {
+    pub fn new(mut reader: R) -> ForensicResult<Self> {
+        let mut boot = vec![0u8; 512];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut boot)?;
+        let boot = NtfsBootSector::parse(&boot)?;
+        let mut record = vec![0u8; boot.mft_record_size as usize];
+        let mft_offset = match boot.mft_lcn.checked_mul(boot.cluster_size) {
+            Some(v) => v,
+            None => return Err(ForensicError::bad_format_str("Invalid $MFT cluster")),
+        };
+        reader.seek(SeekFrom::Start(mft_offset))?;
+        reader.read_exact(&mut record)?;
+        let mft_record = MftRecord::parse(0, &mut record)?;
+        let mft = match mft_record.attribute(ATTR_DATA, "") {
+            Some(v) => NtfsStream {
+                record: 0,
+                content: v.content.clone(),
+            },
+            None => return Err(ForensicError::missing_str("$MFT has no $DATA attribute")),
+        };
+        let mut volume = Self {
+            reader,
+            boot,
+            mft,
+            upcase: UpCase::default(),
+        };
+        // A fragmented $MFT keeps the rest of its runs in extension records
+        if mft_record.attribute(ATTR_ATTRIBUTE_LIST, "").is_some() {
+            let mft_record = volume.merge_attribute_list(mft_record)?;
+            if let Some(data) = merged_attribute(&mft_record, ATTR_DATA, "") {
+                volume.mft.content = data.content;
+            }
+        }
+        if let Some(upcase) = volume.read_upcase() {
+            volume.upcase = upcase;
+        }
+        Ok(volume)
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        self.boot.cluster_size
+    }
+
+    fn read_upcase(&mut self) -> Option<UpCase> {
+        let record = self.record(UPCASE_RECORD).ok()?;
+        let stream = self.stream(&record, ATTR_DATA, "").ok()?;
+        UpCase::from_ntfs_table(&self.read_stream_to_vec(&stream, MAX_METADATA_SIZE).ok()?)
+    }
+
+    /// Reads an MFT record without resolving its attribute list
+    fn raw_record(&mut self, number: u64) -> ForensicResult<MftRecord> {
+        let size = self.boot.mft_record_size as usize;
+        let mut buf = vec![0u8; size];
+        let mft = self.mft.clone();
+        let readed = match number.checked_mul(size as u64) {
+            Some(offset) => self.read_stream(&mft, offset, &mut buf)?,
+            None => 0,
+        };
+        if readed != size {
+            return Err(ForensicError::missing_string(format!(
+                "MFT record {} is out of the $MFT",
+                number
+            )));
+        }
+        MftRecord::parse(number, &mut buf)
+    }
+
+    /// Reads an MFT record including the attributes stored in extension records
+    pub fn record(&mut self, number: u64) -> ForensicResult<MftRecord> {
+        let record = self.raw_record(number)?;
+        if record.attribute(ATTR_ATTRIBUTE_LIST, "").is_none() {
+            return Ok(record);
+        }
+        self.merge_attribute_list(record)
+    }
+
+    fn merge_attribute_list(&mut self, mut record: MftRecord) -> ForensicResult<MftRecord> {
+        let list = match record.attribute(ATTR_ATTRIBUTE_LIST, "") {
+            Some(v) => v.clone(),
+            None => return Ok(record),
+        };
+        let list = match &list.content {
+            AttributeContent::Resident(v) => v.clone(),
+            AttributeContent::NonResident { .. } => {
+                let stream = NtfsStream {
+                    record: record.number,
+                    content: list.content.clone(),
+                };
+                self.read_stream_to_vec(&stream, MAX_METADATA_SIZE)?
+            }
+        };
+        let mut extensions = Vec::with_capacity(4);
+        let mut offset = 0;
+        while offset + 26 <= list.len() {
+            let length = le_u16(&list, offset + 4) as usize;
+            if length == 0 {
+                break;
+            }
+            let reference = le_u64(&list, offset + 16) & 0x0000_FFFF_FFFF_FFFF;
+            if reference != record.number && !extensions.contains(&reference) {
+                extensions.push(reference);
+            }
+            offset += length;
+        }
+        for extension in extensions {
+            let ext_record = self.raw_record(extension)?;
+            record.attributes.extend(
+                ext_record
+                    .attributes
+                    .into_iter()
+                    .filter(|v| v.type_code != ATTR_ATTRIBUTE_LIST),
+            );
+        }
+        Ok(record)
+    }
+
+    /// Finds a stream of a record. Non-resident attributes split among extension records are joined.
+    pub fn stream(&self, record: &MftRecord, type_code: u32, name: &str) -> ForensicResult<NtfsStream> {
+        let attribute = match merged_attribute(record, type_code, name) {
+            Some(v) => v,
+            None => {
+                return Err(ForensicError::missing_string(format!(
+                    "MFT record {} has no stream {:?}",
+                    record.number, name
+                )))
+            }
+        };
+        if attribute.is_compressed() {
+            return Err(ForensicError::Other(format!(
+                "Compressed streams are not supported: record {}",
+                record.number
+            )));
+        }
+        Ok(NtfsStream {
+            record: record.number,
+            content: attribute.content,
+        })
+    }
+
+    /// Reads the content of a stream at `offset`. Returns the number of bytes copied into `buf`.
+    pub fn read_stream(&mut self, stream: &NtfsStream, offset: u64, buf: &mut [u8]) -> ForensicResult<usize> {
+        let (runs, data_size, initialized_size) = match &stream.content {
+            AttributeContent::Resident(data) => {
+                if offset >= data.len() as u64 {
+                    return Ok(0);
+                }
+                let data = &data[offset as usize..];
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok(len);
+            }
+            AttributeContent::NonResident {
+                runs,
+                data_size,
+                initialized_size,
+            } => (runs, *data_size, *initialized_size),
+        };
+        if offset >= data_size {
+            return Ok(0);
+        }
+        let to_read = ((data_size - offset) as usize).min(buf.len());
+        let cluster_size = self.boot.cluster_size;
+        let mut done = 0;
+        while done < to_read {
+            let position = offset + done as u64;
+            let chunk = &mut buf[done..to_read];
+            if position >= initialized_size {
+                chunk.fill(0);
+                break;
+            }
+            let vcn = position / cluster_size;
+            let run = match runs.iter().find(|r| vcn >= r.vcn && vcn < r.vcn + r.length) {
+                Some(v) => v,
+                None => {
+                    return Err(ForensicError::bad_format_string(format!(
+                        "VCN {} not mapped in record {}",
+                        vcn, stream.record
+                    )))
+                }
+            };
+            let run_end = match (run.vcn + run.length).checked_mul(cluster_size) {
+                Some(v) => v,
+                None => return Err(run_out_of_volume(stream)),
+            };
+            let len = ((run_end - position) as usize)
+                .min(chunk.len())
+                .min((initialized_size - position) as usize);
+            match run.lcn {
+                Some(lcn) => {
+                    let disk_offset = lcn
+                        .checked_mul(cluster_size)
+                        .and_then(|v| v.checked_add(position - run.vcn * cluster_size));
+                    let disk_offset = match disk_offset {
+                        Some(v) => v,
+                        None => return Err(run_out_of_volume(stream)),
+                    };
+                    self.reader.seek(SeekFrom::Start(disk_offset))?;
+                    self.reader.read_exact(&mut chunk[..len])?;
+                }
+                None => chunk[..len].fill(0),
+            }
+            done += len;
+        }
+        Ok(to_read)
+    }
+
+    /// Reads a whole stream in chunks, failing when it is larger than `max_size`
+    pub fn read_stream_to_vec(&mut self, stream: &NtfsStream, max_size: u64) -> ForensicResult<Vec<u8>> {
+        if stream.size() > max_size {
+            return Err(ForensicError::bad_format_string(format!(
+                "Stream of MFT record {} has {} bytes, more than {}",
+                stream.record,
+                stream.size(),
+                max_size
+            )));
+        }
+        let mut data = Vec::with_capacity(stream.size().min(READ_CHUNK_SIZE as u64) as usize);
+        let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+        loop {
+            let readed = self.read_stream(stream, data.len() as u64, &mut chunk)?;
+            if readed == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(&chunk[..readed]);
+        }
+    }
+
+    /// Lists the content of a directory using its $I30 index
+    pub fn read_dir(&mut self, directory: u64) -> ForensicResult<Vec<NtfsDirEntry>> {
+        let record = self.record(directory)?;
+        if !record.is_directory() {
+            return Err(ForensicError::bad_format_string(format!(
+                "MFT record {} is not a directory",
+                directory
+            )));
+        }
+        let mut entries = Vec::with_capacity(32);
+        let root = match record.attribute(ATTR_INDEX_ROOT, "$I30").and_then(|v| v.resident_data()) {
+            Some(v) => v,
+            None => return Err(ForensicError::missing_str("Directory without $INDEX_ROOT")),
+        };
+        if root.len() < 32 {
+            return Err(ForensicError::bad_format_str("Truncated $INDEX_ROOT"));
+        }
+        parse_index_node(&root[16..], &mut entries);
+        if let Ok(allocation) = self.stream(&record, ATTR_INDEX_ALLOCATION, "$I30") {
+            let bitmap = match self.stream(&record, ATTR_BITMAP, "$I30") {
+                Ok(v) => Some(self.read_stream_to_vec(&v, MAX_METADATA_SIZE)?),
+                Err(_) => None,
+            };
+            let block_size = self.boot.index_record_size;
+            let blocks = allocation.size() / block_size;
+            let mut block = vec![0u8; block_size as usize];
+            for i in 0..blocks {
+                if let Some(bitmap) = &bitmap {
+                    let in_use = bitmap
+                        .get((i / 8) as usize)
+                        .map(|v| v & (1 << (i % 8)) != 0)
+                        .unwrap_or(false);
+                    if !in_use {
+                        continue;
+                    }
+                }
+                if self.read_stream(&allocation, i * block_size, &mut block)? != block.len() {
+                    break;
+                }
+                if &block[0..4] != b"INDX" || apply_fixups(&mut block).is_err() {
+                    continue;
+                }
+                parse_index_node(&block[24..], &mut entries);
+            }
+        }
+        let mut seen = std::collections::BTreeSet::new();
+        entries.retain(|v| seen.insert(v.record));
+        Ok(entries)
+    }
+
+    /// Resolves a Windows path (`C:\Windows\System32` or `\Windows\System32`) to its MFT record number
+    pub fn find(&mut self, path: &str) -> ForensicResult<u64> {
+        let mut current = ROOT_RECORD;
+        for component in path_components(path) {
+            let entries = self.read_dir(current)?;
+            current = match entries.iter().find(|v| self.upcase.eq(&v.name, component)) {
+                Some(v) => v.record,
+                None => {
+                    return Err(ForensicError::missing_string(format!(
+                        "Cannot find {} in {}",
+                        component, path
+                    )))
+                }
+            };
+        }
+        Ok(current)
+    }
+
+    /// Opens a file. Alternate data streams are selected with `file:stream`, as in `$UsnJrnl:$J`
+    pub fn open(&mut self, path: &str) -> ForensicResult<NtfsFile<'_, R>> {
+        let stream = self.data_stream(path)?;
+        Ok(NtfsFile {
+            volume: VolumeRef::Borrowed(self),
+            stream,
+            position: 0,
+        })
+    }
+
+    /// Opens a file as a reader that owns the volume
+    pub fn into_file(mut self, path: &str) -> ForensicResult<NtfsFile<'static, R>> {
+        let stream = self.data_stream(path)?;
+        Ok(self.into_stream(stream))
+    }
+
+    /// Reads a stream found in this volume or in another reader of the same volume
+    pub fn into_stream(self, stream: NtfsStream) -> NtfsFile<'static, R> {
+        NtfsFile {
+            volume: VolumeRef::Owned(self),
+            stream,
+            position: 0,
+        }
+    }
+
+    /// Reads the same volume from another reader, reusing the parsed boot sector, $MFT runs and
+    /// $UpCase table
+    pub fn with_reader<T: Read + Seek>(&self, reader: T) -> NtfsVolume<T> {
+        NtfsVolume {
+            reader,
+            boot: self.boot.clone(),
+            mft: self.mft.clone(),
+            upcase: self.upcase.clone(),
+        }
+    }
+
+    /// Finds the stream of a path, selected with `file:stream`
+    pub fn data_stream(&mut self, path: &str) -> ForensicResult<NtfsStream> {
+        let (path, stream_name) = split_stream_name(path);
+        let number = self.find(path)?;
+        let record = self.record(number)?;
+        self.stream(&record, ATTR_DATA, stream_name)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek + 'static> RawFileSystem for NtfsVolume<R> {
+    fn fs_name(&self) -> &'static str {
+        "NTFS"
+    }
+
+    fn list_dir(&mut self, path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+        let directory = self.find(path)?;
+        Ok(self
+            .read_dir(directory)?
+            .into_iter()
+            .map(|v| RawDirEntry {
+                name: v.name,
+                is_directory: v.is_directory,
+                deleted: false,
+                size: v.size,
+                created: v.times.created,
+                modified: v.times.modified,
+                accessed: v.times.accessed,
+            })
+            .collect())
+    }
+
+    fn open_file(&mut self, path: &str) -> ForensicResult<Box<dyn Read + '_>> {
+        Ok(Box::new(self.open(path)?))
+    }
+
+    fn into_file(self: Box<Self>, path: &str) -> ForensicResult<OpenedFile> {
+        let file = (*self).into_file(path)?;
+        Ok(OpenedFile {
+            size: file.size(),
+            reader: Box::new(file),
+        })
+    }
+}
+
+/// Error of a data run whose clusters cannot be addressed
+fn run_out_of_volume(stream: &NtfsStream) -> ForensicError {
+    ForensicError::bad_format_string(format!("Data run out of the volume in MFT record {}", stream.record))
+}
+
+/// Joins the pieces of an attribute that were split among several extension records
+fn merged_attribute(record: &MftRecord, type_code: u32, name: &str) -> Option<Attribute> {
+    let mut pieces: Vec<&Attribute> = record
+        .attributes
+        .iter()
+        .filter(|v| v.type_code == type_code && v.name == name)
+        .collect();
+    if pieces.len() <= 1 {
+        return pieces.first().map(|v| (*v).clone());
+    }
+    pieces.sort_by_key(|v| v.starting_vcn);
+    let mut merged = pieces[0].clone();
+    if let AttributeContent::NonResident { runs, .. } = &mut merged.content {
+        for piece in &pieces[1..] {
+            if let AttributeContent::NonResident { runs: more, .. } = &piece.content {
+                runs.extend(more.iter().cloned());
+            }
+        }
+    }
+    Some(merged)
+}
+
+fn parse_index_node(node: &[u8], entries: &mut Vec<NtfsDirEntry>) {
+    let mut offset = le_u32(node, 0) as usize;
+    let end = (le_u32(node, 4) as usize).min(node.len());
+    while offset + 16 <= end {
+        let length = le_u16(node, offset + 8) as usize;
+        let key_length = le_u16(node, offset + 10) as usize;
+        let flags = le_u32(node, offset + 12);
+        if flags & INDEX_ENTRY_LAST != 0 || length < 16 {
+            break;
+        }
+        if key_length > 0 && offset + 16 + key_length <= end {
+            if let Ok(fname) = FileName::parse(&node[offset + 16..offset + 16 + key_length]) {
+                if fname.namespace != FILE_NAME_DOS {
+                    entries.push(NtfsDirEntry {
+                        is_directory: fname.is_directory(),
+                        size: fname.size,
+                        times: fname.times,
+                        name: fname.name,
+                        record: le_u64(node, offset) & 0x0000_FFFF_FFFF_FFFF,
+                    });
+                }
+            }
+        }
+        offset += length;
+    }
+}
+
+/// Splits a Windows path into its components, skipping the drive letter
+pub fn path_components(path: &str) -> impl Iterator<Item = &str> {
+    let path = match path.find(':') {
+        Some(1) => &path[2..],
+        _ => path,
+    };
+    path.split(['\\', '/']).filter(|v| !v.is_empty() && *v != ".")
+}
+
+/// Separates the alternate data stream of the last component: `\$Extend\$UsnJrnl:$J`
+pub fn split_stream_name(path: &str) -> (&str, &str) {
+    let start = match path.find(':') {
+        Some(1) => 2,
+        _ => 0,
+    };
+    let file_start = path.rfind(['\\', '/']).map(|v| v + 1).unwrap_or(0).max(start);
+    match path[file_start..].find(':') {
+        Some(pos) => (&path[..file_start + pos], &path[file_start + pos + 1..]),
+        None => (path, ""),
+    }
+}
+
+/// Stream of an NTFS file that can be read like a regular file
+pub struct NtfsFile<'a, R: Read + Seek> {
+    volume: VolumeRef<'a, NtfsVolume<R>>,
+    stream: NtfsStream,
+    position: u64,
+}
+
+impl<'a, R: Read + Seek> NtfsFile<'a, R> {
+    pub fn size(&self) -> u64 {
+        self.stream.size()
+    }
+}
+
+impl<'a, R: Read + Seek> Read for NtfsFile<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let readed = self
+            .volume
+            .read_stream(&self.stream, self.position, buf)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        self.position += readed as u64;
+        Ok(readed)
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for NtfsFile<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(v) => v as i64,
+            SeekFrom::End(v) => self.stream.size() as i64 + v,
+            SeekFrom::Current(v) => self.position as i64 + v,
+        };
+        if position < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+
+    #[test]
+    fn should_decode_data_runs() {
+        // 0x18 clusters at LCN 0x5634, then 0x10 clusters 0x100 clusters before, then a sparse run
+        let runs = [0x21, 0x18, 0x34, 0x56, 0x21, 0x10, 0x00, 0xFF, 0x01, 0x08, 0x00];
+        let runs = decode_data_runs(&runs, 0).unwrap();
+        assert_eq!(
+            vec![
+                DataRun { vcn: 0, length: 0x18, lcn: Some(0x5634) },
+                DataRun { vcn: 0x18, length: 0x10, lcn: Some(0x5534) },
+                DataRun { vcn: 0x28, length: 0x08, lcn: None },
+            ],
+            runs
+        );
+    }
+
+    #[test]
+    fn should_split_stream_names() {
+        assert_eq!((r"C:\$Extend\$UsnJrnl", "$J"), split_stream_name(r"C:\$Extend\$UsnJrnl:$J"));
+        assert_eq!((r"C:\$MFT", ""), split_stream_name(r"C:\$MFT"));
+        let components: Vec<&str> = path_components(r"C:\Windows\System32").collect();
+        assert_eq!(vec!["Windows", "System32"], components);
+    }
+
+    #[test]
+    fn should_apply_fixups() {
+        let mut record = vec![0u8; 1024];
+        record[0..4].copy_from_slice(b"FILE");
+        record[4..6].copy_from_slice(&48u16.to_le_bytes());
+        record[6..8].copy_from_slice(&3u16.to_le_bytes());
+        record[48..54].copy_from_slice(&[0x07, 0x00, 0xAA, 0xBB, 0xCC, 0xDD]);
+        record[510..512].copy_from_slice(&[0x07, 0x00]);
+        record[1022..1024].copy_from_slice(&[0x07, 0x00]);
+        apply_fixups(&mut record).unwrap();
+        assert_eq!([0xAA, 0xBB], record[510..512]);
+        assert_eq!([0xCC, 0xDD], record[1022..1024]);
+        record[510] = 0;
+        record[511] = 0;
+        assert!(apply_fixups(&mut record).is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_boot_sectors() {
+        let image = test_image::build(&[]);
+        assert_eq!(1024, NtfsBootSector::parse(&image[..512]).unwrap().mft_record_size);
+        for (offset, value) in [(0x0D, 0x81), (0x0D, 0xC0), (0x40, 0x80), (0x40, 0xE0), (0x44, 0x7F)] {
+            let mut boot = image[..512].to_vec();
+            boot[offset] = value;
+            assert!(NtfsBootSector::parse(&boot).is_err());
+        }
+    }
+
+    #[test]
+    fn should_reject_runs_out_of_the_volume() {
+        let runs = [0x18, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x01, 0x01, 0x00];
+        assert!(decode_data_runs(&runs, 0).is_err());
+
+        let mut volume = NtfsVolume::new(std::io::Cursor::new(test_image::build(&[]))).unwrap();
+        let stream = NtfsStream {
+            record: 64,
+            content: AttributeContent::NonResident {
+                runs: vec![DataRun { vcn: 0, length: 1, lcn: Some(u64::MAX / 2) }],
+                data_size: 4096,
+                initialized_size: 4096,
+            },
+        };
+        assert!(volume.read_stream(&stream, 0, &mut [0u8; 16]).is_err());
+        assert!(volume.read_stream_to_vec(&stream, 1024).is_err());
+    }
+}
+
+/// Builds small NTFS images in memory to test the readers without a real volume
+#[cfg(test)]
+pub(crate) mod test_image {
+    const CLUSTER: usize = 4096;
+    const RECORD: usize = 1024;
+    const MFT_LCN: usize = 4;
+    const FIRST_USER_RECORD: usize = 16;
+    const MFT_RECORDS: usize = 64;
+
+    struct Node {
+        name: String,
+        parent: usize,
+        data: Option<Vec<u8>>,
+        children: Vec<usize>,
+    }
+
+    /// Creates an image with the given files. Parent directories are created as needed.
+    /// File contents bigger than 512 bytes are stored in non-resident clusters.
+    pub(crate) fn build(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut nodes: Vec<(usize, Node)> = vec![(
+            super::ROOT_RECORD as usize,
+            Node { name: ".".into(), parent: super::ROOT_RECORD as usize, data: None, children: vec![] },
+        )];
+        for (path, data) in files {
+            let mut current = 0;
+            let components: Vec<&str> = super::path_components(path).collect();
+            for (i, component) in components.iter().enumerate() {
+                let is_file = i == components.len() - 1;
+                let found = nodes[current]
+                    .1
+                    .children
+                    .iter()
+                    .copied()
+                    .find(|v| nodes[*v].1.name.eq_ignore_ascii_case(component));
+                current = match found {
+                    Some(v) => v,
+                    None => {
+                        let record = FIRST_USER_RECORD + nodes.len() - 1;
+                        let parent = nodes[current].0;
+                        nodes.push((
+                            record,
+                            Node {
+                                name: component.to_string(),
+                                parent,
+                                data: if is_file { Some(data.to_vec()) } else { None },
+                                children: vec![],
+                            },
+                        ));
+                        let pos = nodes.len() - 1;
+                        nodes[current].1.children.push(pos);
+                        pos
+                    }
+                };
+            }
+        }
+        let data_start = MFT_LCN + MFT_RECORDS * RECORD / CLUSTER;
+        let mut data_area: Vec<u8> = Vec::new();
+        let mut image = vec![0u8; data_start * CLUSTER];
+        // Boot sector
+        image[3..11].copy_from_slice(b"NTFS    ");
+        image[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        image[0x0D] = (CLUSTER / 512) as u8;
+        image[0x30..0x38].copy_from_slice(&(MFT_LCN as u64).to_le_bytes());
+        image[0x40] = 0xF6;
+        image[0x44] = 1;
+        image[0x48..0x50].copy_from_slice(&0x1234_5678u64.to_le_bytes());
+        // $MFT
+        let mft_attrs = vec![non_resident(
+            0x80,
+            MFT_LCN as u64,
+            (MFT_RECORDS * RECORD / CLUSTER) as u64,
+            (MFT_RECORDS * RECORD) as u64,
+        )];
+        write_record(&mut image, 0, 0x01, &mft_attrs);
+        for (record, node) in &nodes {
+            let is_dir = node.data.is_none();
+            let size = node.data.as_ref().map(|v| v.len()).unwrap_or(0) as u64;
+            let mut attrs = vec![resident(0x10, "", &[0u8; 48])];
+            attrs.push(resident(0x30, "", &file_name(node.parent as u64, &node.name, is_dir, size)));
+            if is_dir {
+                let mut index = Vec::new();
+                for child in &node.children {
+                    let (child_record, child) = &nodes[*child];
+                    let child_size = child.data.as_ref().map(|v| v.len()).unwrap_or(0) as u64;
+                    let key = file_name(*record as u64, &child.name, child.data.is_none(), child_size);
+                    let length = align8(16 + key.len());
+                    let mut entry = vec![0u8; length];
+                    entry[0..8].copy_from_slice(&(*child_record as u64 | (1u64 << 48)).to_le_bytes());
+                    entry[8..10].copy_from_slice(&(length as u16).to_le_bytes());
+                    entry[10..12].copy_from_slice(&(key.len() as u16).to_le_bytes());
+                    entry[16..16 + key.len()].copy_from_slice(&key);
+                    index.extend(entry);
+                }
+                let mut last = vec![0u8; 16];
+                last[8] = 16;
+                last[12] = 0x02;
+                index.extend(last);
+                let mut root = vec![0u8; 32];
+                root[0..4].copy_from_slice(&0x30u32.to_le_bytes());
+                root[4..8].copy_from_slice(&1u32.to_le_bytes());
+                root[8..12].copy_from_slice(&(CLUSTER as u32).to_le_bytes());
+                root[12] = 1;
+                root[16..20].copy_from_slice(&16u32.to_le_bytes());
+                root[20..24].copy_from_slice(&(16 + index.len() as u32).to_le_bytes());
+                root[24..28].copy_from_slice(&(16 + index.len() as u32).to_le_bytes());
+                root.extend(index);
+                attrs.push(resident(0x90, "$I30", &root));
+            } else {
+                let data = node.data.as_ref().unwrap();
+                if data.len() <= 512 {
+                    attrs.push(resident(0x80, "", data));
+                } else {
+                    let lcn = data_start + data_area.len() / CLUSTER;
+                    let clusters = data.len().div_ceil(CLUSTER);
+                    data_area.extend(data);
+                    data_area.resize(data_area.len().div_ceil(CLUSTER) * CLUSTER, 0);
+                    attrs.push(non_resident(0x80, lcn as u64, clusters as u64, data.len() as u64));
+                }
+            }
+            write_record(&mut image, *record, if is_dir { 0x03 } else { 0x01 }, &attrs);
+        }
+        image.extend(data_area);
+        image
+    }
+
+    fn align8(v: usize) -> usize {
+        v.div_ceil(8) * 8
+    }
+
+    fn file_name(parent: u64, name: &str, is_dir: bool, size: u64) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let mut buf = vec![0u8; 66 + name.len() * 2];
+        buf[0..8].copy_from_slice(&(parent | (1u64 << 48)).to_le_bytes());
+        buf[8..16].copy_from_slice(&0x01D0_0000_0000_0000u64.to_le_bytes());
+        buf[48..56].copy_from_slice(&size.to_le_bytes());
+        if is_dir {
+            buf[56..60].copy_from_slice(&0x1000_0000u32.to_le_bytes());
+        }
+        buf[64] = name.len() as u8;
+        buf[65] = 1;
+        for (i, c) in name.iter().enumerate() {
+            buf[66 + i * 2..68 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        buf
+    }
+
+    fn encode_name(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn resident(type_code: u32, name: &str, value: &[u8]) -> Vec<u8> {
+        let name = encode_name(name);
+        let value_offset = align8(24 + name.len());
+        let length = align8(value_offset + value.len());
+        let mut buf = vec![0u8; length];
+        buf[0..4].copy_from_slice(&type_code.to_le_bytes());
+        buf[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        buf[9] = (name.len() / 2) as u8;
+        buf[10..12].copy_from_slice(&24u16.to_le_bytes());
+        buf[16..20].copy_from_slice(&(value.len() as u32).to_le_bytes());
+        buf[20..22].copy_from_slice(&(value_offset as u16).to_le_bytes());
+        buf[24..24 + name.len()].copy_from_slice(&name);
+        buf[value_offset..value_offset + value.len()].copy_from_slice(value);
+        buf
+    }
+
+    fn non_resident(type_code: u32, lcn: u64, clusters: u64, size: u64) -> Vec<u8> {
+        let mut runs = vec![0x44];
+        runs.extend(&(clusters as u32).to_le_bytes());
+        runs.extend(&(lcn as u32).to_le_bytes());
+        runs.push(0);
+        let length = align8(64 + runs.len());
+        let mut buf = vec![0u8; length];
+        buf[0..4].copy_from_slice(&type_code.to_le_bytes());
+        buf[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        buf[8] = 1;
+        buf[10..12].copy_from_slice(&64u16.to_le_bytes());
+        buf[24..32].copy_from_slice(&(clusters - 1).to_le_bytes());
+        buf[32..34].copy_from_slice(&64u16.to_le_bytes());
+        buf[40..48].copy_from_slice(&(clusters * CLUSTER as u64).to_le_bytes());
+        buf[48..56].copy_from_slice(&size.to_le_bytes());
+        buf[56..64].copy_from_slice(&size.to_le_bytes());
+        buf[64..64 + runs.len()].copy_from_slice(&runs);
+        buf
+    }
+
+    fn write_record(image: &mut [u8], number: usize, flags: u16, attrs: &[Vec<u8>]) {
+        let mut record = vec![0u8; RECORD];
+        record[0..4].copy_from_slice(b"FILE");
+        record[4..6].copy_from_slice(&48u16.to_le_bytes());
+        record[6..8].copy_from_slice(&3u16.to_le_bytes());
+        record[16..18].copy_from_slice(&1u16.to_le_bytes());
+        record[20..22].copy_from_slice(&56u16.to_le_bytes());
+        record[22..24].copy_from_slice(&flags.to_le_bytes());
+        let mut offset = 56;
+        for attr in attrs {
+            record[offset..offset + attr.len()].copy_from_slice(attr);
+            offset += attr.len();
+        }
+        record[offset..offset + 4].copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+        record[24..28].copy_from_slice(&(offset as u32 + 8).to_le_bytes());
+        record[28..32].copy_from_slice(&(RECORD as u32).to_le_bytes());
+        record[44..48].copy_from_slice(&(number as u32).to_le_bytes());
+        // Update sequence number 1 protecting the end of each sector
+        record[48..50].copy_from_slice(&1u16.to_le_bytes());
+        for i in 1..3 {
+            let end = i * 512;
+            record[48 + i * 2] = record[end - 2];
+            record[49 + i * 2] = record[end - 1];
+            record[end - 2..end].copy_from_slice(&1u16.to_le_bytes());
+        }
+        let start = MFT_LCN * CLUSTER + number * RECORD;
+        image[start..start + RECORD].copy_from_slice(&record);
+    }
+}
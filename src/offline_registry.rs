@@ -0,0 +1,280 @@
+//! `RegistryReader` over the hives of an examined system, read through a `CollectionBackend` so
+//! the variables are resolved from the system being collected and not from the analysis host.
+//! The hives are mounted in `HKEY_LOCAL_MACHINE` with their usual names, and
+//! `SYSTEM\CurrentControlSet` is mapped to the `ControlSet00x` pointed by `SYSTEM\Select\Current`.
+
+use std::{io::Read, sync::Mutex};
+
+use forensic_rs::{
+    prelude::{
+        ForensicError, ForensicResult, RegHiveKey, RegValue, RegistryKeyInfo, RegistryReader, VirtualFile,
+        VirtualFileSystem,
+    },
+    utils::time::Filetime,
+};
+
+use crate::{
+    backend::CollectionBackend,
+    regf::{Hive, HiveKey},
+};
+
+/// Hives of `System32\config` loaded by `OfflineRegistry::from_backend`
+pub const SYSTEM_HIVES: [&str; 3] = ["SYSTEM", "SOFTWARE", "SAM"];
+
+const CURRENT_CONTROL_SET: &str = "CurrentControlSet";
+
+/// Registry of an offline system built from its hive files
+pub struct OfflineRegistry {
+    /// Hives mounted in HKLM with their names
+    hives: Vec<(String, Hive)>,
+    /// `ControlSet00x` used as `CurrentControlSet`
+    control_set: Option<String>,
+    /// Keys opened, indexed by the value of `RegHiveKey::Hkey`
+    opened: Mutex<Vec<Option<(usize, HiveKey)>>>,
+}
+
+impl Default for OfflineRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OfflineRegistry {
+    pub fn new() -> Self {
+        Self {
+            hives: Vec::new(),
+            control_set: None,
+            opened: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Mounts `hive` at `HKLM\<name>`. Mounting `SYSTEM` selects the current control set.
+    pub fn with_hive(mut self, name: &str, hive: Hive) -> Self {
+        if name.eq_ignore_ascii_case("SYSTEM") {
+            self.control_set = current_control_set(&hive);
+        }
+        self.hives.retain(|(v, _)| !v.eq_ignore_ascii_case(name));
+        self.hives.push((name.to_string(), hive));
+        self
+    }
+
+    /// Loads the hives of `<system_root>\System32\config` through the backend. Missing hives are
+    /// skipped, it fails only when none of them can be loaded.
+    pub fn from_backend(backend: &dyn CollectionBackend, system_root: &str) -> ForensicResult<Self> {
+        let mut registry = Self::new();
+        for name in SYSTEM_HIVES {
+            let path = format!(r"{}\System32\config\{}", system_root.trim_end_matches('\\'), name);
+            match load_hive(backend, &path) {
+                Ok(hive) => registry = registry.with_hive(name, hive),
+                Err(err) => println!("Error loading the hive {}: {:?}", path, err),
+            }
+        }
+        if registry.hives.is_empty() {
+            return Err(ForensicError::missing_string(format!(
+                r"No registry hives found in {}\System32\config",
+                system_root
+            )));
+        }
+        Ok(registry)
+    }
+
+    /// Name of the `ControlSet00x` key used as `CurrentControlSet`
+    pub fn control_set(&self) -> Option<&str> {
+        self.control_set.as_deref()
+    }
+
+    fn hive_position(&self, name: &str) -> Option<usize> {
+        self.hives.iter().position(|(v, _)| v.eq_ignore_ascii_case(name))
+    }
+
+    fn opened_key(&self, hkey: RegHiveKey) -> ForensicResult<(usize, HiveKey)> {
+        let position = match hkey {
+            RegHiveKey::Hkey(v) => v as usize,
+            _ => return Err(ForensicError::missing_str("Only HKEY_LOCAL_MACHINE is available")),
+        };
+        match self.opened.lock().unwrap().get(position) {
+            Some(Some(v)) => Ok(v.clone()),
+            _ => Err(ForensicError::missing_str("The key is not opened")),
+        }
+    }
+
+    /// Opens `path` below `key`, replacing `CurrentControlSet` below the root of `SYSTEM`
+    fn open_subkey(&self, hive: usize, key: HiveKey, path: &str) -> ForensicResult<HiveKey> {
+        let (name, data) = &self.hives[hive];
+        let root = data.root()?.offset;
+        let mut key = key;
+        for component in path.split('\\').filter(|v| !v.is_empty()) {
+            let component = match &self.control_set {
+                Some(control_set)
+                    if key.offset == root
+                        && name.eq_ignore_ascii_case("SYSTEM")
+                        && component.eq_ignore_ascii_case(CURRENT_CONTROL_SET) =>
+                {
+                    control_set.as_str()
+                }
+                _ => component,
+            };
+            key = data.open_subkey(&key, component)?;
+        }
+        Ok(key)
+    }
+}
+
+/// Reads a whole hive file through the backend
+pub fn load_hive(backend: &dyn CollectionBackend, path: &str) -> ForensicResult<Hive> {
+    let mut file = backend.open(path)?;
+    let mut data = Vec::with_capacity(file.size as usize);
+    file.reader.read_to_end(&mut data)?;
+    Hive::new(data)
+}
+
+/// `ControlSet00x` from the `Current` value of the `Select` key of a SYSTEM hive
+fn current_control_set(hive: &Hive) -> Option<String> {
+    let select = hive.open_key("Select").ok()?;
+    match hive.value(&select, "Current").ok()? {
+        RegValue::DWord(v) => Some(format!("ControlSet{:03}", v)),
+        _ => None,
+    }
+}
+
+impl RegistryReader for OfflineRegistry {
+    fn from_file(&self, _file: Box<dyn VirtualFile>) -> ForensicResult<Box<dyn RegistryReader>> {
+        Err(ForensicError::Other("Use OfflineRegistry::from_backend".to_string()))
+    }
+
+    fn from_fs(&self, _fs: Box<dyn VirtualFileSystem>) -> ForensicResult<Box<dyn RegistryReader>> {
+        Err(ForensicError::Other("Use OfflineRegistry::from_backend".to_string()))
+    }
+
+    fn open_key(&self, hkey: RegHiveKey, key_name: &str) -> ForensicResult<RegHiveKey> {
+        let (hive, key) = match hkey {
+            RegHiveKey::HkeyLocalMachine => {
+                let (hive_name, path) = key_name.split_once('\\').unwrap_or((key_name, ""));
+                let hive = self
+                    .hive_position(hive_name)
+                    .ok_or_else(|| ForensicError::missing_string(format!("Hive {} not loaded", hive_name)))?;
+                (hive, self.open_subkey(hive, self.hives[hive].1.root()?, path)?)
+            }
+            _ => {
+                let (hive, parent) = self.opened_key(hkey)?;
+                (hive, self.open_subkey(hive, parent, key_name)?)
+            }
+        };
+        // Slots of closed keys are reused so the table does not grow with each open
+        let mut opened = self.opened.lock().unwrap();
+        let position = match opened.iter().position(|v| v.is_none()) {
+            Some(position) => {
+                opened[position] = Some((hive, key));
+                position
+            }
+            None => {
+                opened.push(Some((hive, key)));
+                opened.len() - 1
+            }
+        };
+        Ok(RegHiveKey::Hkey(position as isize))
+    }
+
+    fn read_value(&self, hkey: RegHiveKey, value_name: &str) -> ForensicResult<RegValue> {
+        let (hive, key) = self.opened_key(hkey)?;
+        self.hives[hive].1.value(&key, value_name)
+    }
+
+    fn enumerate_values(&self, hkey: RegHiveKey) -> ForensicResult<Vec<String>> {
+        let (hive, key) = self.opened_key(hkey)?;
+        self.hives[hive].1.value_names(&key)
+    }
+
+    fn enumerate_keys(&self, hkey: RegHiveKey) -> ForensicResult<Vec<String>> {
+        if let RegHiveKey::HkeyLocalMachine = hkey {
+            return Ok(self.hives.iter().map(|(name, _)| name.clone()).collect());
+        }
+        let (hive, key) = self.opened_key(hkey)?;
+        Ok(self.hives[hive].1.subkeys(&key)?.into_iter().map(|v| v.name).collect())
+    }
+
+    fn key_at(&self, hkey: RegHiveKey, pos: u32) -> ForensicResult<String> {
+        self.enumerate_keys(hkey)?
+            .into_iter()
+            .nth(pos as usize)
+            .ok_or(ForensicError::NoMoreData)
+    }
+
+    fn value_at(&self, hkey: RegHiveKey, pos: u32) -> ForensicResult<String> {
+        self.enumerate_values(hkey)?
+            .into_iter()
+            .nth(pos as usize)
+            .ok_or(ForensicError::NoMoreData)
+    }
+
+    fn key_info(&self, hkey: RegHiveKey) -> ForensicResult<RegistryKeyInfo> {
+        let (hive, key) = self.opened_key(hkey)?;
+        let hive = &self.hives[hive].1;
+        let subkeys = hive.subkeys(&key)?;
+        let values = hive.value_names(&key)?;
+        Ok(RegistryKeyInfo {
+            subkeys: subkeys.len() as u32,
+            max_subkey_name_length: subkeys.iter().map(|v| v.name.len() as u32).max().unwrap_or_default(),
+            values: values.len() as u32,
+            max_value_name_length: values.iter().map(|v| v.len() as u32).max().unwrap_or_default(),
+            max_value_length: 0,
+            last_write_time: Filetime::new(key.last_written),
+        })
+    }
+
+    fn close_key(&self, hkey: RegHiveKey) {
+        if let RegHiveKey::Hkey(v) = hkey {
+            if let Some(opened) = self.opened.lock().unwrap().get_mut(v as usize) {
+                *opened = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+    use crate::{environment::Environment, regf::tst::build};
+
+    #[test]
+    fn should_map_current_control_set_from_select() {
+        let system = build(&[
+            (r"Select", "Current", RegValue::DWord(2)),
+            (
+                r"ControlSet001\Control\Session Manager\Environment",
+                "AppLogs",
+                RegValue::ExpandSZ(r"%SystemDrive%\Old".into()),
+            ),
+            (
+                r"ControlSet002\Control\Session Manager\Environment",
+                "AppLogs",
+                RegValue::ExpandSZ(r"%SystemDrive%\Logs".into()),
+            ),
+        ]);
+        let software = build(&[(
+            r"Microsoft\Windows NT\CurrentVersion",
+            "SystemRoot",
+            RegValue::SZ(r"D:\Windows".into()),
+        )]);
+        let registry = OfflineRegistry::new()
+            .with_hive("SYSTEM", Hive::new(system).unwrap())
+            .with_hive("SOFTWARE", Hive::new(software).unwrap());
+        assert_eq!(Some("ControlSet002"), registry.control_set());
+
+        let key = registry
+            .open_key(RegHiveKey::HkeyLocalMachine, r"SYSTEM\CurrentControlSet\Control")
+            .unwrap();
+        let key = registry.open_key(key, r"Session Manager\Environment").unwrap();
+        assert_eq!(vec!["AppLogs".to_string()], registry.enumerate_values(key).unwrap());
+        registry.close_key(key);
+        assert!(registry.read_value(key, "AppLogs").is_err());
+        let reopened = registry.open_key(RegHiveKey::HkeyLocalMachine, "SOFTWARE").unwrap();
+        assert_eq!(key, reopened);
+        registry.close_key(reopened);
+
+        let mut registry: Box<dyn RegistryReader> = Box::new(registry);
+        let environment = Environment::from_registry(&mut registry);
+        assert_eq!(r"D:\Logs", environment.expand("%AppLogs%").unwrap());
+        assert!(registry.open_key(RegHiveKey::HkeyLocalMachine, r"SAM\SAM").is_err());
+    }
+}
@@ -0,0 +1,419 @@
+//! Reader of registry hive files (`regf`), like `NTUSER.DAT` or `SYSTEM`, copied from a volume.
+//! Only the primary file is read: changes still in the transaction logs are not applied.
+
+use forensic_rs::prelude::{ForensicError, ForensicResult, RegValue};
+
+use crate::helpers::{le_u16, le_u32, le_u64};
+
+const BASE_BLOCK_SIZE: usize = 4096;
+/// The name of the key is stored in ASCII
+const KEY_COMP_NAME: u16 = 0x20;
+/// The name of the value is stored in ASCII
+const VALUE_COMP_NAME: u16 = 0x01;
+/// The data of the value is stored in the data offset field
+const DATA_IN_OFFSET: u32 = 0x8000_0000;
+/// Bytes of each segment of big data (`db`) cells
+const BIG_DATA_SEGMENT: usize = 16344;
+
+const REG_SZ: u32 = 1;
+const REG_EXPAND_SZ: u32 = 2;
+const REG_DWORD: u32 = 4;
+const REG_DWORD_BIG_ENDIAN: u32 = 5;
+const REG_LINK: u32 = 6;
+const REG_MULTI_SZ: u32 = 7;
+const REG_QWORD: u32 = 11;
+
+/// Registry hive loaded in memory
+#[derive(Debug, Clone)]
+pub struct Hive {
+    data: Vec<u8>,
+    root: u32,
+}
+
+/// Key of a hive, identified by the offset of its `nk` cell
+#[derive(Debug, Clone, PartialEq)]
+pub struct HiveKey {
+    pub offset: u32,
+    pub name: String,
+    /// FILETIME of the last change of the key
+    pub last_written: u64,
+    subkeys: u32,
+    subkeys_list: u32,
+    values: u32,
+    values_list: u32,
+}
+
+impl Hive {
+    pub fn new(data: Vec<u8>) -> ForensicResult<Self> {
+        if data.len() < BASE_BLOCK_SIZE || &data[0..4] != b"regf" {
+            return Err(ForensicError::bad_format_str("Not a registry hive"));
+        }
+        let root = le_u32(&data, 0x24);
+        let hive = Self { data, root };
+        hive.key(root)?;
+        Ok(hive)
+    }
+
+    pub fn root(&self) -> ForensicResult<HiveKey> {
+        self.key(self.root)
+    }
+
+    /// Opens a key given its path from the root, comparing names ignoring case
+    pub fn open_key(&self, path: &str) -> ForensicResult<HiveKey> {
+        self.open_subkey(&self.root()?, path)
+    }
+
+    /// Opens a key given its path from `parent`, comparing names ignoring case
+    pub fn open_subkey(&self, parent: &HiveKey, path: &str) -> ForensicResult<HiveKey> {
+        let mut key = parent.clone();
+        for name in path.split('\\').filter(|v| !v.is_empty()) {
+            key = match self.subkeys(&key)?.into_iter().find(|v| v.name.eq_ignore_ascii_case(name)) {
+                Some(v) => v,
+                None => return Err(ForensicError::missing_string(format!("Key {} not found", path))),
+            };
+        }
+        Ok(key)
+    }
+
+    pub fn subkeys(&self, key: &HiveKey) -> ForensicResult<Vec<HiveKey>> {
+        let mut offsets = Vec::with_capacity(key.subkeys as usize);
+        if key.subkeys > 0 {
+            self.list_offsets(key.subkeys_list, &mut offsets, 0)?;
+        }
+        offsets.into_iter().map(|v| self.key(v)).collect()
+    }
+
+    /// Names of the values of a key. The default value has an empty name.
+    pub fn value_names(&self, key: &HiveKey) -> ForensicResult<Vec<String>> {
+        let mut names = Vec::with_capacity(key.values as usize);
+        for offset in self.value_offsets(key)? {
+            names.push(self.value_cell(offset)?.0);
+        }
+        Ok(names)
+    }
+
+    /// Reads a value of a key given its name, compared ignoring case
+    pub fn value(&self, key: &HiveKey, name: &str) -> ForensicResult<RegValue> {
+        for offset in self.value_offsets(key)? {
+            let (value_name, cell) = self.value_cell(offset)?;
+            if value_name.eq_ignore_ascii_case(name) {
+                return self.value_data(cell);
+            }
+        }
+        Err(ForensicError::missing_string(format!("Value {} not found in {}", name, key.name)))
+    }
+
+    fn cell(&self, offset: u32) -> ForensicResult<&[u8]> {
+        let start = BASE_BLOCK_SIZE + offset as usize;
+        if start + 4 > self.data.len() {
+            return Err(ForensicError::bad_format_string(format!("Cell {} out of the hive", offset)));
+        }
+        let size = (le_u32(&self.data, start) as i32).unsigned_abs() as usize;
+        if size < 4 || start + size > self.data.len() {
+            return Err(ForensicError::bad_format_string(format!("Invalid size of cell {}", offset)));
+        }
+        Ok(&self.data[start + 4..start + size])
+    }
+
+    fn key(&self, offset: u32) -> ForensicResult<HiveKey> {
+        let cell = self.cell(offset)?;
+        if cell.len() < 0x4C || &cell[0..2] != b"nk" {
+            return Err(ForensicError::bad_format_string(format!("Cell {} is not a key", offset)));
+        }
+        let name_length = le_u16(cell, 0x48) as usize;
+        let name = cell
+            .get(0x4C..0x4C + name_length)
+            .ok_or_else(|| ForensicError::bad_format_str("Truncated key name"))?;
+        Ok(HiveKey {
+            offset,
+            name: decode_name(name, le_u16(cell, 2) & KEY_COMP_NAME != 0),
+            last_written: le_u64(cell, 4),
+            subkeys: le_u32(cell, 0x14),
+            subkeys_list: le_u32(cell, 0x1C),
+            values: le_u32(cell, 0x24),
+            values_list: le_u32(cell, 0x28),
+        })
+    }
+
+    /// Offsets of the keys of a subkeys list, following the `ri` lists of lists
+    fn list_offsets(&self, offset: u32, offsets: &mut Vec<u32>, depth: usize) -> ForensicResult<()> {
+        let cell = self.cell(offset)?;
+        if cell.len() < 4 || depth > 8 {
+            return Err(ForensicError::bad_format_string(format!("Invalid subkeys list {}", offset)));
+        }
+        let count = le_u16(cell, 2) as usize;
+        let (stride, nested) = match &cell[0..2] {
+            b"lf" | b"lh" => (8, false),
+            b"li" => (4, false),
+            b"ri" => (4, true),
+            _ => return Err(ForensicError::bad_format_string(format!("Unknown subkeys list {}", offset))),
+        };
+        for i in 0..count {
+            let position = 4 + i * stride;
+            if position + 4 > cell.len() {
+                break;
+            }
+            let child = le_u32(cell, position);
+            if nested {
+                self.list_offsets(child, offsets, depth + 1)?;
+            } else {
+                offsets.push(child);
+            }
+        }
+        Ok(())
+    }
+
+    fn value_offsets(&self, key: &HiveKey) -> ForensicResult<Vec<u32>> {
+        if key.values == 0 {
+            return Ok(Vec::new());
+        }
+        let cell = self.cell(key.values_list)?;
+        Ok(cell
+            .chunks_exact(4)
+            .take(key.values as usize)
+            .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect())
+    }
+
+    fn value_cell(&self, offset: u32) -> ForensicResult<(String, &[u8])> {
+        let cell = self.cell(offset)?;
+        if cell.len() < 0x14 || &cell[0..2] != b"vk" {
+            return Err(ForensicError::bad_format_string(format!("Cell {} is not a value", offset)));
+        }
+        let name_length = le_u16(cell, 2) as usize;
+        let name = cell
+            .get(0x14..0x14 + name_length)
+            .ok_or_else(|| ForensicError::bad_format_str("Truncated value name"))?;
+        Ok((decode_name(name, le_u16(cell, 0x10) & VALUE_COMP_NAME != 0), cell))
+    }
+
+    fn value_data(&self, cell: &[u8]) -> ForensicResult<RegValue> {
+        let size = le_u32(cell, 4);
+        let data_type = le_u32(cell, 0x0C);
+        let data = if size & DATA_IN_OFFSET != 0 {
+            let size = ((size & !DATA_IN_OFFSET) as usize).min(4);
+            cell[8..8 + size].to_vec()
+        } else {
+            self.read_data(le_u32(cell, 8), size as usize)?
+        };
+        Ok(match data_type {
+            REG_SZ => RegValue::SZ(decode_utf16(&data)),
+            REG_EXPAND_SZ | REG_LINK => RegValue::ExpandSZ(decode_utf16(&data)),
+            REG_MULTI_SZ => RegValue::MultiSZ(
+                decode_utf16_all(&data)
+                    .split('\0')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+                    .collect(),
+            ),
+            REG_DWORD if data.len() >= 4 => RegValue::DWord(le_u32(&data, 0)),
+            REG_DWORD_BIG_ENDIAN if data.len() >= 4 => {
+                RegValue::DWord(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+            }
+            REG_QWORD if data.len() >= 8 => RegValue::QWord(le_u64(&data, 0)),
+            _ => RegValue::Binary(data),
+        })
+    }
+
+    fn read_data(&self, offset: u32, size: usize) -> ForensicResult<Vec<u8>> {
+        let cell = self.cell(offset)?;
+        if size <= cell.len() {
+            return Ok(cell[..size].to_vec());
+        }
+        if cell.len() < 8 || &cell[0..2] != b"db" {
+            return Err(ForensicError::bad_format_string(format!("Truncated value data {}", offset)));
+        }
+        let segments = self.cell(le_u32(cell, 4))?;
+        let mut data = Vec::with_capacity(size);
+        for segment in segments.chunks_exact(4).take(le_u16(cell, 2) as usize) {
+            let segment = self.cell(u32::from_le_bytes([segment[0], segment[1], segment[2], segment[3]]))?;
+            let remaining = size - data.len();
+            data.extend_from_slice(&segment[..remaining.min(BIG_DATA_SEGMENT).min(segment.len())]);
+        }
+        Ok(data)
+    }
+}
+
+fn decode_name(name: &[u8], ascii: bool) -> String {
+    if ascii {
+        // Latin-1, every byte is a character
+        name.iter().map(|v| *v as char).collect()
+    } else {
+        decode_utf16_all(name)
+    }
+}
+
+fn decode_utf16_all(data: &[u8]) -> String {
+    let units: Vec<u16> = data.chunks_exact(2).map(|v| u16::from_le_bytes([v[0], v[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Decodes a string up to its first NUL
+fn decode_utf16(data: &[u8]) -> String {
+    let value = decode_utf16_all(data);
+    match value.find('\0') {
+        Some(end) => value[..end].to_string(),
+        None => value,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tst {
+    use super::*;
+
+    struct Node {
+        name: String,
+        values: Vec<(String, RegValue)>,
+        children: Vec<Node>,
+    }
+
+    /// Creates a hive with the given values: `(key path, value name, value)`. An empty value name
+    /// only creates the key.
+    pub(crate) fn build(values: &[(&str, &str, RegValue)]) -> Vec<u8> {
+        let mut root = Node {
+            name: "ROOT".into(),
+            values: vec![],
+            children: vec![],
+        };
+        for (path, name, value) in values {
+            let mut node = &mut root;
+            for component in path.split('\\').filter(|v| !v.is_empty()) {
+                let position = match node.children.iter().position(|v| v.name == component) {
+                    Some(v) => v,
+                    None => {
+                        node.children.push(Node {
+                            name: component.to_string(),
+                            values: vec![],
+                            children: vec![],
+                        });
+                        node.children.len() - 1
+                    }
+                };
+                node = &mut node.children[position];
+            }
+            if !name.is_empty() {
+                node.values.push((name.to_string(), value.clone()));
+            }
+        }
+        let mut bins = vec![0u8; 0x20];
+        bins[0..4].copy_from_slice(b"hbin");
+        let root_offset = write_key(&mut bins, &root, 0);
+        let size = (bins.len() + 4).div_ceil(4096) * 4096;
+        let free = size - bins.len();
+        bins.extend((free as i32).to_le_bytes());
+        bins.resize(size, 0);
+        bins[8..12].copy_from_slice(&(size as u32).to_le_bytes());
+
+        let mut hive = vec![0u8; BASE_BLOCK_SIZE];
+        hive[0..4].copy_from_slice(b"regf");
+        hive[0x24..0x28].copy_from_slice(&root_offset.to_le_bytes());
+        hive[0x28..0x2C].copy_from_slice(&(size as u32).to_le_bytes());
+        hive.extend(bins);
+        hive
+    }
+
+    fn cell(bins: &mut Vec<u8>, content: &[u8]) -> u32 {
+        let offset = bins.len() as u32;
+        let size = (content.len() + 4).div_ceil(8) * 8;
+        bins.extend((-(size as i32)).to_le_bytes());
+        bins.extend(content);
+        bins.resize(offset as usize + size, 0);
+        offset
+    }
+
+    fn utf16(value: &str) -> Vec<u8> {
+        value.encode_utf16().flat_map(|v| v.to_le_bytes()).chain([0, 0]).collect()
+    }
+
+    fn write_key(bins: &mut Vec<u8>, node: &Node, parent: u32) -> u32 {
+        let mut nk = vec![0u8; 0x4C];
+        nk[0..2].copy_from_slice(b"nk");
+        nk[2..4].copy_from_slice(&KEY_COMP_NAME.to_le_bytes());
+        nk[4..12].copy_from_slice(&132_000_000_000_000_000u64.to_le_bytes());
+        nk[0x10..0x14].copy_from_slice(&parent.to_le_bytes());
+        nk[0x48..0x4A].copy_from_slice(&(node.name.len() as u16).to_le_bytes());
+        nk.extend(node.name.as_bytes());
+        let offset = cell(bins, &nk);
+
+        let children: Vec<u32> = node.children.iter().map(|v| write_key(bins, v, offset)).collect();
+        let mut subkeys_list = u32::MAX;
+        if !children.is_empty() {
+            let mut list = b"lf".to_vec();
+            list.extend((children.len() as u16).to_le_bytes());
+            for child in &children {
+                list.extend(child.to_le_bytes());
+                list.extend([0u8; 4]);
+            }
+            subkeys_list = cell(bins, &list);
+        }
+        let mut value_offsets = Vec::new();
+        for (name, value) in &node.values {
+            let (data_type, data) = match value {
+                RegValue::SZ(v) => (REG_SZ, utf16(v)),
+                RegValue::ExpandSZ(v) => (REG_EXPAND_SZ, utf16(v)),
+                RegValue::MultiSZ(v) => (REG_MULTI_SZ, utf16(&format!("{}\0", v.join("\0")))),
+                RegValue::DWord(v) => (REG_DWORD, v.to_le_bytes().to_vec()),
+                RegValue::QWord(v) => (REG_QWORD, v.to_le_bytes().to_vec()),
+                RegValue::Binary(v) => (3, v.clone()),
+            };
+            let mut vk = vec![0u8; 0x14];
+            vk[0..2].copy_from_slice(b"vk");
+            vk[2..4].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            if data.len() <= 4 {
+                vk[4..8].copy_from_slice(&(data.len() as u32 | DATA_IN_OFFSET).to_le_bytes());
+                vk[8..8 + data.len()].copy_from_slice(&data);
+            } else {
+                let data_offset = cell(bins, &data);
+                vk[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+                vk[8..12].copy_from_slice(&data_offset.to_le_bytes());
+            }
+            vk[0x0C..0x10].copy_from_slice(&data_type.to_le_bytes());
+            vk[0x10..0x12].copy_from_slice(&VALUE_COMP_NAME.to_le_bytes());
+            vk.extend(name.as_bytes());
+            value_offsets.push(cell(bins, &vk));
+        }
+        let mut values_list = u32::MAX;
+        if !value_offsets.is_empty() {
+            let list: Vec<u8> = value_offsets.iter().flat_map(|v| v.to_le_bytes()).collect();
+            values_list = cell(bins, &list);
+        }
+        let start = offset as usize + 4;
+        bins[start + 0x14..start + 0x18].copy_from_slice(&(children.len() as u32).to_le_bytes());
+        bins[start + 0x1C..start + 0x20].copy_from_slice(&subkeys_list.to_le_bytes());
+        bins[start + 0x24..start + 0x28].copy_from_slice(&(node.values.len() as u32).to_le_bytes());
+        bins[start + 0x28..start + 0x2C].copy_from_slice(&values_list.to_le_bytes());
+        offset
+    }
+
+    #[test]
+    fn should_read_keys_and_values_of_hives() {
+        let hive = Hive::new(build(&[
+            (r"Environment", "TEMP", RegValue::ExpandSZ(r"%USERPROFILE%\AppData\Local\Temp".into())),
+            (r"Environment", "Retries", RegValue::DWord(3)),
+            (r"Software\Vendor", "Paths", RegValue::MultiSZ(vec!["a".into(), "b".into()])),
+            (r"Software\Vendor", "Id", RegValue::QWord(1 << 40)),
+            (r"Software\Empty", "", RegValue::DWord(0)),
+        ]))
+        .unwrap();
+        let environment = hive.open_key("environment").unwrap();
+        assert_eq!("Environment", environment.name);
+        assert_eq!(vec!["TEMP", "Retries"], hive.value_names(&environment).unwrap());
+        assert_eq!(
+            RegValue::ExpandSZ(r"%USERPROFILE%\AppData\Local\Temp".into()),
+            hive.value(&environment, "temp").unwrap()
+        );
+        assert_eq!(RegValue::DWord(3), hive.value(&environment, "Retries").unwrap());
+        let vendor = hive.open_key(r"SOFTWARE\Vendor").unwrap();
+        assert_eq!(
+            RegValue::MultiSZ(vec!["a".into(), "b".into()]),
+            hive.value(&vendor, "Paths").unwrap()
+        );
+        assert_eq!(RegValue::QWord(1 << 40), hive.value(&vendor, "Id").unwrap());
+        let software = hive.open_key("Software").unwrap();
+        let names: Vec<String> = hive.subkeys(&software).unwrap().into_iter().map(|v| v.name).collect();
+        assert_eq!(vec!["Vendor", "Empty"], names);
+        assert!(hive.open_key(r"Software\Missing").is_err());
+        assert!(hive.value(&vendor, "Missing").is_err());
+        assert!(Hive::new(vec![0; 8192]).is_err());
+    }
+}
@@ -1,74 +1,307 @@
-use forensic_rs::prelude::{ForensicResult, RegistryReader, RegHiveKey};
-use windows::Win32::Storage::FileSystem::GetLogicalDriveStringsW;
+use forensic_rs::prelude::{ForensicResult, RegistryReader, RegHiveKey, RegValue};
 
-fn system_root_from_registry(registry : &mut Box<dyn RegistryReader>) -> ForensicResult<String> {
-    let current_version = registry.open_key(RegHiveKey::HkeyLocalMachine, r"SOFTWARE\Microsoft\Windows NT\CurrentVersion")?;
-    let value: String  = registry.read_value(current_version, "SystemRoot")?.try_into()?;
-    Ok(value)
-}
+use crate::{backend::CollectionBackend, environment::read_string, helpers::{le_u32, utf16_string}};
+#[cfg(windows)]
+use crate::{backend::{DriveType, MountedVolume}, helpers::to_pcwstr};
+#[cfg(windows)]
+use windows::{core::PCWSTR, Win32::Storage::FileSystem::{GetDiskFreeSpaceExW, GetDiskFreeSpaceW, GetDriveTypeW, GetLogicalDriveStringsW, GetVolumeInformationW}};
+
+const NT_CURRENT_VERSION_KEY : &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion";
+const CURRENT_VERSION_KEY : &str = r"SOFTWARE\Microsoft\Windows\CurrentVersion";
+const PROFILE_LIST_KEY : &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList";
 
-fn system_drive_from_registry(registry : &mut Box<dyn RegistryReader>) -> ForensicResult<String> {
-    let current_version = registry.open_key(RegHiveKey::HkeyLocalMachine, r"SOFTWARE\Microsoft\Windows\CurrentVersion\Setup")?;
-    let value: String  = registry.read_value(current_version, "BootDir")?.try_into()?;
-    Ok(value)
+/// Locations of a Windows installation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemLocations {
+    /// Volume of the installation, like `C:`
+    pub system_drive : String,
+    /// Windows folder, like `C:\Windows`
+    pub system_root : String,
+    /// Like `C:\ProgramData`
+    pub program_data : String,
 }
 
-fn program_data_from_registry(registry : &mut Box<dyn RegistryReader>) -> ForensicResult<String> {
-    let current_version = registry.open_key(RegHiveKey::HkeyLocalMachine, r"Software\Microsoft\Windows NT\CurrentVersion\ProfileList")?;
-    let value: String  = registry.read_value(current_version, "ProgramData")?.try_into()?;
-    Ok(value)
+impl Default for SystemLocations {
+    fn default() -> Self {
+        Self::from_system_root(r"C:\Windows")
+    }
 }
 
-pub fn system_drive(registry : &mut Box<dyn RegistryReader>) -> String {
-    match system_drive_from_registry(registry) {
-        Ok(v) => v.to_uppercase(),
-        Err(_) => {
-            println!(r"Error getting the current SystemDrive from HKLM\SOFTWARE\Microsoft\Windows\CurrentVersion\Setup\BootDir, returning default C:\");
-            format!(r"C:\")
+impl SystemLocations {
+    /// Locations of an installation in `system_root` with the default ProgramData
+    pub fn from_system_root(system_root : &str) -> Self {
+        let system_root = system_root.trim_end_matches('\\').to_string();
+        let system_drive = drive_of(&system_root).unwrap_or_else(|| "C:".to_string());
+        Self {
+            program_data : format!(r"{}\ProgramData", system_drive),
+            system_drive,
+            system_root,
         }
     }
+
+    /// Locations of an installation found at `system_root`. The SystemDrive is the folder that contains
+    /// it, so the installation inside `C:\Windows.old\Windows` has its profiles in
+    /// `C:\Windows.old\Users`.
+    pub fn for_installation(system_root : &str) -> Self {
+        let system_root = system_root.trim_end_matches('\\');
+        let system_drive = match system_root.rfind('\\') {
+            Some(pos) if pos > 2 => system_root[..pos].to_string(),
+            _ => drive_of(system_root).unwrap_or_else(|| "C:".to_string()),
+        };
+        Self {
+            program_data : format!(r"{}\ProgramData", system_drive),
+            system_root : system_root.to_string(),
+            system_drive,
+        }
+    }
+
+    /// Reads the SystemRoot from `Windows NT\CurrentVersion`, or its `PathName`, or takes the volume of
+    /// the `ProgramFilesDir`. SystemDrive is the volume of SystemRoot.
+    pub fn from_registry(registry : &mut Box<dyn RegistryReader>) -> Self {
+        let system_root = match read_string(registry, NT_CURRENT_VERSION_KEY, "SystemRoot") {
+            Ok(v) => v,
+            Err(_) => match read_string(registry, NT_CURRENT_VERSION_KEY, "PathName") {
+                Ok(v) => {
+                    println!(r"Error getting the current SystemRoot from HKLM\{}\SystemRoot, using PathName {}", NT_CURRENT_VERSION_KEY, v);
+                    v
+                }
+                Err(_) => match read_string(registry, CURRENT_VERSION_KEY, "ProgramFilesDir").ok().and_then(|v| drive_of(&v)) {
+                    Some(drive) => {
+                        println!(r"Error getting the current SystemRoot from HKLM\{}, using {}\Windows from the ProgramFilesDir", NT_CURRENT_VERSION_KEY, drive);
+                        format!(r"{}\Windows", drive)
+                    }
+                    None => {
+                        println!(r"Error getting the current SystemRoot from HKLM\{}, returning default C:\Windows", NT_CURRENT_VERSION_KEY);
+                        r"C:\Windows".to_string()
+                    }
+                },
+            },
+        };
+        let mut locations = Self::from_system_root(&system_root);
+        match read_string(registry, PROFILE_LIST_KEY, "ProgramData") {
+            Ok(v) => locations.program_data = replace_system_drive(&v, &locations.system_drive),
+            Err(_) => println!(r"Error getting the current ProgramData from HKLM\{}, returning default {}", PROFILE_LIST_KEY, locations.program_data),
+        }
+        locations
+    }
+
+    /// Checks the locations in the volumes of the backend. When `System32\config` is not in the
+    /// SystemRoot, the first of `volumes` with a `Windows\System32\config` folder is used.
+    pub fn validated(self, backend : &dyn CollectionBackend, volumes : &[String]) -> Self {
+        let exists = |path : &str| backend.read_dir(path).is_ok();
+        let mut locations = self;
+        if !exists(&format!(r"{}\System32\config", locations.system_root)) {
+            let found = volumes.iter().map(|v| format!(r"{}\Windows", v.trim_end_matches('\\'))).find(|v| exists(&format!(r"{}\System32\config", v)));
+            match found {
+                Some(system_root) => {
+                    println!(r"The SystemRoot {} is not in the volume, using {}", locations.system_root, system_root);
+                    let relocated = Self::from_system_root(&system_root);
+                    if drive_of(&locations.program_data).as_deref() == Some(locations.system_drive.as_str()) {
+                        locations.program_data = format!("{}{}", relocated.system_drive, &locations.program_data[2..]);
+                    }
+                    locations.system_root = relocated.system_root;
+                    locations.system_drive = relocated.system_drive;
+                }
+                None => println!(r"The SystemRoot {} is not in the volume", locations.system_root),
+            }
+        }
+        if !exists(&locations.program_data) {
+            let default = format!(r"{}\ProgramData", locations.system_drive);
+            if default != locations.program_data && exists(&default) {
+                println!(r"The ProgramData {} is not in the volume, using {}", locations.program_data, default);
+                locations.program_data = default;
+            }
+        }
+        locations
+    }
 }
 
-pub fn system_root(registry : &mut Box<dyn RegistryReader>) -> String {
-    match system_root_from_registry(registry) {
-        Ok(v) => v,
-        Err(_) => {
-            println!(r"Error getting the current SystemRoot from HKLM\SOFTWARE\Microsoft\Windows NT\CurrentVersion\SystemRoot, returning default C:\Windows");
-            format!(r"C:\")
+/// SystemRoot of every Windows installation in the volumes: the folders in the root of a volume, or
+/// inside a `Windows.old` folder, with a `System32\config\SYSTEM` hive
+pub fn find_windows_installations(backend : &dyn CollectionBackend, volumes : &[String]) -> Vec<String> {
+    let has_system_hive = |folder : &str| match backend.read_dir(&format!(r"{}\System32\config", folder)) {
+        Ok(entries) => entries.iter().any(|v| !v.is_directory && v.name.eq_ignore_ascii_case("SYSTEM")),
+        Err(_) => false,
+    };
+    let subfolders = |folder : &str| -> Vec<String> {
+        match backend.read_dir(folder) {
+            Ok(entries) => entries.into_iter().filter(|v| v.is_directory).map(|v| format!(r"{}\{}", folder, v.name)).collect(),
+            Err(_) => vec![],
         }
+    };
+    let mut installations = Vec::new();
+    for volume in volumes {
+        for folder in subfolders(volume.trim_end_matches('\\')) {
+            if is_previous_installation(&format!(r"{}\Windows", folder)) {
+                installations.extend(subfolders(&folder).into_iter().filter(|v| has_system_hive(v)));
+            } else if has_system_hive(&folder) {
+                installations.push(folder);
+            }
+        }
+    }
+    installations
+}
+
+/// The SystemRoot is inside a `Windows.old` folder left by an upgrade, like `C:\Windows.old\Windows`
+/// or `C:\Windows.old.000\Windows`
+pub fn is_previous_installation(system_root : &str) -> bool {
+    let mut folders = system_root.trim_end_matches('\\').rsplit('\\');
+    folders.next();
+    folders.next().is_some_and(|v| v.to_ascii_lowercase().starts_with("windows.old"))
+}
+
+/// `X:` of a path starting with a drive letter
+fn drive_of(path : &str) -> Option<String> {
+    match path.find(':') {
+        Some(1) => Some(path[..2].to_uppercase()),
+        _ => None,
+    }
+}
+
+fn replace_system_drive(path : &str, system_drive : &str) -> String {
+    match path.get(..13) {
+        Some(v) if v.eq_ignore_ascii_case("%SystemDrive%") => format!("{}{}", system_drive, &path[13..]),
+        _ => path.to_string(),
     }
 }
 
+/// SystemDrive with a trailing backslash: `C:\`
+pub fn system_drive(registry : &mut Box<dyn RegistryReader>) -> String {
+    format!(r"{}\", SystemLocations::from_registry(registry).system_drive)
+}
+
+pub fn system_root(registry : &mut Box<dyn RegistryReader>) -> String {
+    SystemLocations::from_registry(registry).system_root
+}
+
 pub fn program_data(registry : &mut Box<dyn RegistryReader>) -> String {
-    match program_data_from_registry(registry) {
-        Ok(v) => v,
-        Err(_) => {
-            println!(r"Error getting the current SystemRoot from HKLM\SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList, returning default C:\ProgramData");
-            format!(r"C:\ProgramData")
+    SystemLocations::from_registry(registry).program_data
+}
+
+/// Windows assigned a temporary profile because the profile of the user could not be loaded
+pub const PROFILE_TEMP_ASSIGNED: u32 = 0x0800;
+
+/// Profile of a user from the ProfileList of the registry
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UserProfile {
+    /// SID of the user, `None` when the profile was found listing `C:\Users`
+    pub sid : Option<String>,
+    /// Name of the account from the SAM, or the name of the profile folder
+    pub username : String,
+    /// ProfileImagePath, it may contain environment variables
+    pub home : String,
+    /// `Flags` value of the profile
+    pub flags : u32,
+    /// `State` value of the profile
+    pub state : u32,
+    /// FILETIME of the last time the profile was loaded
+    pub last_load : Option<u64>,
+    /// FILETIME of the last time the profile was unloaded
+    pub last_unload : Option<u64>,
+    /// The key of the profile ends with `.bak`: Windows made a new profile for the same SID
+    pub backup : bool,
+}
+
+impl UserProfile {
+    /// Profile found as a folder of `C:\Users`
+    pub fn from_home(home: &str) -> Self {
+        Self {
+            username: folder_name(home),
+            home: home.to_string(),
+            ..Default::default()
         }
     }
+
+    pub fn is_temporary(&self) -> bool {
+        self.state & PROFILE_TEMP_ASSIGNED != 0 || self.home.to_uppercase().ends_with(r"\TEMP")
+    }
 }
 
-pub fn list_users_homes_from_reg(registry : &mut Box<dyn RegistryReader>) -> ForensicResult<Vec<String>> {
+/// Profiles of the ProfileList. Profiles that cannot be read are skipped.
+pub fn list_user_profiles(registry : &mut Box<dyn RegistryReader>) -> ForensicResult<Vec<UserProfile>> {
     let mut returned = Vec::with_capacity(32);
-    let profile_list_key = registry.open_key(RegHiveKey::HkeyLocalMachine, r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList")?;
+    let profile_list_key = registry.open_key(RegHiveKey::HkeyLocalMachine, PROFILE_LIST_KEY)?;
     let profile_list = registry.enumerate_keys(profile_list_key)?;
     for profile in &profile_list {
-        let profile_key = registry.open_key(profile_list_key, profile)?;
-        let profile_image_path : String = registry.read_value(profile_key, "ProfileImagePath")?.try_into()?;
-        returned.push(profile_image_path);
-    } 
+        match read_user_profile(registry, profile_list_key, profile) {
+            Ok(v) => returned.push(v),
+            Err(err) => println!(r"Error reading the profile {} from the ProfileList: {:?}", profile, err),
+        }
+    }
+    registry.close_key(profile_list_key);
     Ok(returned)
 }
 
+fn read_user_profile(registry : &mut Box<dyn RegistryReader>, profile_list_key : RegHiveKey, profile : &str) -> ForensicResult<UserProfile> {
+    let profile_key = registry.open_key(profile_list_key, profile)?;
+    let home : ForensicResult<String> = registry.read_value(profile_key, "ProfileImagePath").and_then(|v| v.try_into());
+    let dword = |name : &str| match registry.read_value(profile_key, name) {
+        Ok(RegValue::DWord(v)) => Some(v),
+        _ => None,
+    };
+    let filetime = |high : Option<u32>, low : Option<u32>| match (high, low) {
+        (Some(high), Some(low)) if high != 0 || low != 0 => Some(((high as u64) << 32) | low as u64),
+        _ => None,
+    };
+    let flags = dword("Flags").unwrap_or_default();
+    let state = dword("State").unwrap_or_default();
+    let last_load = filetime(dword("LocalProfileLoadTimeHigh"), dword("LocalProfileLoadTimeLow"));
+    let last_unload = filetime(dword("LocalProfileUnloadTimeHigh"), dword("LocalProfileUnloadTimeLow"));
+    registry.close_key(profile_key);
+    let home = home?;
+    let backup = profile.to_lowercase().ends_with(".bak");
+    let sid = if backup { &profile[..profile.len() - 4] } else { profile }.to_uppercase();
+    let username = username_from_sam(registry, &sid).unwrap_or_else(|| folder_name(&home));
+    Ok(UserProfile {
+        backup,
+        sid: Some(sid),
+        username,
+        home,
+        flags,
+        state,
+        last_load,
+        last_unload,
+    })
+}
+
+/// Name of a local account from the `V` value of its SAM key, or of a well known SID
+fn username_from_sam(registry : &mut Box<dyn RegistryReader>, sid : &str) -> Option<String> {
+    match sid {
+        "S-1-5-18" => return Some("SYSTEM".to_string()),
+        "S-1-5-19" => return Some("LOCAL SERVICE".to_string()),
+        "S-1-5-20" => return Some("NETWORK SERVICE".to_string()),
+        _ => {}
+    }
+    let rid : u32 = sid.rsplit('-').next()?.parse().ok()?;
+    let user_key = registry.open_key(RegHiveKey::HkeyLocalMachine, &format!(r"SAM\SAM\Domains\Account\Users\{:08X}", rid)).ok()?;
+    let v = registry.read_value(user_key, "V");
+    registry.close_key(user_key);
+    let v : Vec<u8> = v.ok()?.try_into().ok()?;
+    // The V value starts with a table of (offset, length, unknown) entries, the second one is the name
+    let offset = le_u32(&v, 0x0C) as usize + 0xCC;
+    let length = le_u32(&v, 0x10) as usize;
+    if length == 0 || offset + length > v.len() {
+        return None;
+    }
+    Some(utf16_string(&v, offset, length / 2))
+}
+
+fn folder_name(home : &str) -> String {
+    home.trim_end_matches('\\').rsplit('\\').next().unwrap_or_default().to_string()
+}
+
+pub fn list_users_homes_from_reg(registry : &mut Box<dyn RegistryReader>) -> ForensicResult<Vec<String>> {
+    Ok(list_user_profiles(registry)?.into_iter().map(|v| v.home).collect())
+}
+
 pub fn list_users_homes(registry : &mut Box<dyn RegistryReader>) -> Vec<String> {
     match list_users_homes_from_reg(registry) {
         Ok(v) => v,
         Err(_) => {
-            println!(r"Error getting the current list_users_homes from HKLM\SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList, returning list of folders in C:\Users");
-            let sys_dir = system_drive(registry);
+            let users = format!(r"{}\Users", SystemLocations::from_registry(registry).system_drive);
+            println!(r"Error getting the current list_users_homes from HKLM\{}, returning list of folders in {}", PROFILE_LIST_KEY, users);
             let mut returned = Vec::with_capacity(32);
-            let users_path = std::path::Path::new(&sys_dir).join("Users");
+            let users_path = std::path::Path::new(&users);
             let readdir = match std::fs::read_dir(users_path) {
                 Ok(v) => v,
                 Err(_) => return returned
@@ -83,7 +316,7 @@ pub fn list_users_homes(registry : &mut Box<dyn RegistryReader>) -> Vec<String>
                     Err(_) => continue
                 };
                 if file_type.is_dir() {
-                    returned.push(format!("{:?}", dir.file_name()));
+                    returned.push(format!(r"{}\{}", users, dir.file_name().to_string_lossy()));
                 }
             }
             returned
@@ -91,8 +324,70 @@ pub fn list_users_homes(registry : &mut Box<dyn RegistryReader>) -> Vec<String>
     }
 }
 
-/// List of mounted devices: ["A:\\", "B:\\", "C:\\", "D:\\"]
-pub fn mounted_devices() -> Vec<String> {
+/// Kind of device of a volume given its root, like `C:\`
+#[cfg(windows)]
+pub fn drive_type(volume : &str) -> DriveType {
+    let root = to_pcwstr(volume);
+    match unsafe { GetDriveTypeW(PCWSTR::from_raw(root.as_ptr())) } {
+        // DRIVE_REMOVABLE
+        2 => DriveType::Removable,
+        // DRIVE_FIXED
+        3 => DriveType::Fixed,
+        // DRIVE_REMOTE
+        4 => DriveType::Network,
+        // DRIVE_CDROM
+        5 => DriveType::Optical,
+        // DRIVE_RAMDISK
+        6 => DriveType::RamDisk,
+        _ => DriveType::Unknown,
+    }
+}
+
+/// Drive type, file system, serial, label and sizes of a volume given its root, like `C:\`. What
+/// cannot be read, like the file system of an empty card reader, is left empty.
+#[cfg(windows)]
+pub fn mounted_volume(root : &str) -> MountedVolume {
+    let mut volume = MountedVolume::new(root).with_drive_type(drive_type(root));
+    let wide_root = to_pcwstr(root);
+    let mut label = vec![0u16; 261];
+    let mut file_system = vec![0u16; 261];
+    let mut serial_number = 0u32;
+    let readed = unsafe {
+        GetVolumeInformationW(PCWSTR::from_raw(wide_root.as_ptr()), Some(&mut label), Some(&mut serial_number), None, None, Some(&mut file_system))
+    };
+    match readed {
+        Ok(_) => {
+            volume.label = from_wide(&label);
+            volume.file_system = from_wide(&file_system);
+            volume.serial_number = serial_number as u64;
+        },
+        Err(e) => println!("Cannot read the volume information of {}: {}", root, e),
+    }
+    let mut sectors_in_cluster = 0;
+    let mut bytes_per_sector = 0;
+    let mut free_clusters = 0;
+    let mut total_clusters = 0;
+    if unsafe { GetDiskFreeSpaceW(PCWSTR::from_raw(wide_root.as_ptr()), Some(&mut sectors_in_cluster), Some(&mut bytes_per_sector), Some(&mut free_clusters), Some(&mut total_clusters)) }.is_ok() {
+        volume.cluster_size = sectors_in_cluster as u64 * bytes_per_sector as u64;
+    }
+    let mut total_size = 0;
+    let mut free_size = 0;
+    if unsafe { GetDiskFreeSpaceExW(PCWSTR::from_raw(wide_root.as_ptr()), None, Some(&mut total_size), Some(&mut free_size)) }.is_ok() {
+        volume.total_size = total_size;
+        volume.free_size = free_size;
+    }
+    volume
+}
+
+/// String of a buffer filled by the Windows API, ending in the first NUL
+#[cfg(windows)]
+fn from_wide(buffer : &[u16]) -> String {
+    String::from_utf16_lossy(&buffer[..buffer.iter().position(|v| *v == 0).unwrap_or(buffer.len())])
+}
+
+/// List of mounted devices: "A:\\", "B:\\", "C:\\", "D:\\"... with their drive type and file system
+#[cfg(windows)]
+pub fn mounted_devices() -> Vec<MountedVolume> {
     let mut to_ret = Vec::with_capacity(32);
     let mut buffer = vec![0u16; 10_000];
     unsafe {
@@ -101,7 +396,7 @@ pub fn mounted_devices() -> Vec<String> {
             if txt.len() == 0{
                 continue;
             }
-            to_ret.push(String::from_utf16_lossy(txt));
+            to_ret.push(mounted_volume(&String::from_utf16_lossy(txt)));
         }
     }
     
@@ -109,6 +404,109 @@ pub fn mounted_devices() -> Vec<String> {
 }
 
 #[cfg(test)]
+mod registry_tst {
+    use forensic_rs::prelude::RegValue;
+
+    use crate::environment::tst::TestRegistry;
+
+    use super::*;
+
+    #[test]
+    fn should_list_user_profiles_skipping_broken_ones() {
+        let profile_list = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList";
+        let alice = format!(r"{}\S-1-5-21-1-2-3-1001", profile_list);
+        let alice_bak = format!(r"{}\S-1-5-21-1-2-3-1001.bak", profile_list);
+        let broken = format!(r"{}\S-1-5-21-1-2-3-1002", profile_list);
+        let system = format!(r"{}\S-1-5-18", profile_list);
+        let mut v = vec![0u8; 0xCC];
+        v[0x0C..0x10].copy_from_slice(&0u32.to_le_bytes());
+        v[0x10..0x14].copy_from_slice(&10u32.to_le_bytes());
+        v.extend("alice".encode_utf16().flat_map(|v| v.to_le_bytes()));
+        let mut registry : Box<dyn RegistryReader> = Box::new(TestRegistry::new(&[
+            (&alice, "ProfileImagePath", RegValue::ExpandSZ(r"%SystemDrive%\Users\alice.CONTOSO".into())),
+            (&alice, "State", RegValue::DWord(PROFILE_TEMP_ASSIGNED)),
+            (&alice, "LocalProfileLoadTimeHigh", RegValue::DWord(0x01DA_0000)),
+            (&alice, "LocalProfileLoadTimeLow", RegValue::DWord(0x1234)),
+            (&alice_bak, "ProfileImagePath", RegValue::SZ(r"C:\Users\alice".into())),
+            (&broken, "Flags", RegValue::DWord(0)),
+            (&system, "ProfileImagePath", RegValue::ExpandSZ(r"%systemroot%\system32\config\systemprofile".into())),
+            (r"SAM\SAM\Domains\Account\Users\000003E9", "V", RegValue::Binary(v)),
+        ]));
+        let profiles = list_user_profiles(&mut registry).unwrap();
+        assert_eq!(3, profiles.len());
+        assert_eq!(Some("S-1-5-21-1-2-3-1001".to_string()), profiles[0].sid);
+        assert_eq!("alice", profiles[0].username);
+        assert_eq!(Some(0x01DA_0000_0000_1234), profiles[0].last_load);
+        assert_eq!(None, profiles[0].last_unload);
+        assert!(profiles[0].is_temporary());
+        assert!(!profiles[0].backup);
+        assert_eq!(Some("S-1-5-21-1-2-3-1001".to_string()), profiles[1].sid);
+        assert!(profiles[1].backup);
+        assert_eq!("SYSTEM", profiles[2].username);
+        assert_eq!("bob", UserProfile::from_home(r"C:\Users\bob\").username);
+    }
+
+    fn locations(values : &[(&str, &str, RegValue)]) -> SystemLocations {
+        let mut registry : Box<dyn RegistryReader> = Box::new(TestRegistry::new(values));
+        SystemLocations::from_registry(&mut registry)
+    }
+
+    #[test]
+    fn should_derive_system_drive_from_system_root() {
+        let found = locations(&[
+            (NT_CURRENT_VERSION_KEY, "SystemRoot", RegValue::SZ(r"d:\WINNT\".into())),
+            (NT_CURRENT_VERSION_KEY, "PathName", RegValue::SZ(r"E:\Windows".into())),
+            (PROFILE_LIST_KEY, "ProgramData", RegValue::ExpandSZ(r"%SystemDrive%\AllData".into())),
+            (r"SOFTWARE\Microsoft\Windows\CurrentVersion\Setup", "BootDir", RegValue::SZ(r"C:\".into())),
+        ]);
+        assert_eq!("D:", found.system_drive);
+        assert_eq!(r"d:\WINNT", found.system_root);
+        assert_eq!(r"D:\AllData", found.program_data);
+    }
+
+    #[test]
+    fn should_fall_back_to_path_name() {
+        let found = locations(&[(NT_CURRENT_VERSION_KEY, "PathName", RegValue::SZ(r"E:\Windows".into()))]);
+        assert_eq!(SystemLocations::from_system_root(r"E:\Windows"), found);
+        assert_eq!(r"E:\ProgramData", found.program_data);
+    }
+
+    #[test]
+    fn should_fall_back_to_program_files_volume() {
+        let found = locations(&[(CURRENT_VERSION_KEY, "ProgramFilesDir", RegValue::SZ(r"F:\Program Files".into()))]);
+        assert_eq!(r"F:\Windows", found.system_root);
+        assert_eq!("F:", found.system_drive);
+    }
+
+    #[test]
+    fn should_fall_back_to_c_windows() {
+        let found = locations(&[(PROFILE_LIST_KEY, "ProgramData", RegValue::SZ(r"C:\ProgramData".into()))]);
+        assert_eq!(r"C:\Windows", found.system_root);
+        assert_eq!("C:", found.system_drive);
+        let mut registry : Box<dyn RegistryReader> = Box::new(TestRegistry::new(&[]));
+        assert_eq!(r"C:\", system_drive(&mut registry));
+        assert_eq!(r"C:\Windows", system_root(&mut registry));
+        assert_eq!(r"C:\ProgramData", program_data(&mut registry));
+    }
+
+    #[test]
+    fn should_relocate_system_root_missing_in_the_volume() {
+        let tree = crate::helpers::test_tree::TestTree::new("locations", &[]);
+        tree.create_dir(r"e\Windows\System32\config");
+        tree.create_dir(r"e\ProgramData");
+        let backend = crate::backend::LogicalBackend::new(tree.create_dir("c")).with_volume('E', tree.path("e"));
+        let volumes = vec![r"C:\".to_string(), r"E:\".to_string()];
+
+        let found = SystemLocations::default().validated(&backend, &volumes);
+        assert_eq!(SystemLocations::from_system_root(r"E:\Windows"), found);
+
+        let mut moved = SystemLocations::from_system_root(r"E:\Windows");
+        moved.program_data = r"E:\Data".to_string();
+        assert_eq!(r"E:\ProgramData", moved.validated(&backend, &volumes).program_data);
+    }
+}
+
+#[cfg(all(test, windows))]
 mod tst {
     use forensic_rs::prelude::RegistryReader;
     use frnsc_liveregistry_rs::LiveRegistryReader;
@@ -135,7 +533,7 @@ mod tst {
     #[test]
     fn should_return_system_root_c_reg() {
         let mut registry = get_registry_reader();
-        let var : String = system_root_from_registry(&mut registry).unwrap().to_uppercase();
+        let var : String = SystemLocations::from_registry(&mut registry).system_root.to_uppercase();
         assert_eq!(r"C:\WINDOWS", var);
     }
 
@@ -148,8 +546,8 @@ mod tst {
     #[test]
     fn should_retur_system_drive_c_reg() {
         let mut registry = get_registry_reader();
-        let var : String = system_drive_from_registry(&mut registry).unwrap();
-        assert_eq!(r"C:\", var);
+        let var : String = SystemLocations::from_registry(&mut registry).system_drive;
+        assert_eq!(r"C:", var);
     }
 
     #[test]
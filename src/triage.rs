@@ -1,41 +1,105 @@
 use std::{
-    collections::{BTreeMap, BTreeSet},
-    fs::ReadDir,
-    io::{Read, Write},
-    path::{Path, PathBuf},
+    collections::{BTreeMap, HashSet},
+    io::{Read, Seek, Write},
+    path::PathBuf,
     sync::{Arc, Mutex},
-    time::Duration,
 };
 
+#[cfg(windows)]
+use crate::backend::LiveBackend;
 use crate::{
-    artifacts::{get_default_collection_paths, USN_JRNL_MAX_PATH, USN_JRNL_PATH},
-    helpers::{contains_env_var, is_user_home_env, replace_envvars, replace_home_vars},
-    raw_file::RawFile,
-    sys_vars::{
-        list_users_homes_from_reg, mounted_devices, program_data, system_drive, system_root,
+    artifacts::{
+        get_default_collection_paths, NTFS_DRIVES_MFT_PATH, NTFS_DRIVES_USN_JRNL_MAX_PATH, NTFS_DRIVES_USN_JRNL_PATH,
+        USN_JRNL_MAX_PATH, USN_JRNL_PATH,
     },
+    backend::{CollectionBackend, DriveType, LogicalBackend, MountedVolume, NtfsBackend, VolumeSource},
+    filter::{split_target, FileFilter, SIGNATURE_PROBE_SIZE},
+    glob::{has_wildcards, Glob, Segment},
+    environment::{
+        rebase_path, references_drive_variables, references_previous_variables, references_user_variables,
+        variable_names, Environment, DRIVE_VARIABLES,
+    },
+    helpers::{get_drive_and_disk, system_time_to_filetime},
+    offline_registry::{load_hive, OfflineRegistry},
+    regf::Hive,
+    sys_vars::{find_windows_installations, is_previous_installation, list_user_profiles, SystemLocations, UserProfile},
+    upcase::UpCase,
+    volume::{open_raw_file_system, read_file_system_type, FileSystemType, RawDirEntry, RawFileSystem},
+    vss::{ShadowVolume, VssCatalog},
 };
-use forensic_rs::{prelude::ForensicResult, traits::registry::RegistryReader};
-use frnsc_liveregistry_rs::LiveRegistryReader;
-use regex::Regex;
-use zip::{write::FileOptions, DateTime};
+use forensic_rs::prelude::{ForensicError, ForensicResult, RegistryReader};
+use zip::{write::FileOptions, ZipWriter};
+
+/// File found by `TriageCollector::dry_run`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedFile {
+    /// Path in the examined system or in its shadow copy, empty for the files written by the
+    /// collector like `user_profiles.csv`
+    pub path: String,
+    /// Path of the file in the archive
+    pub archive_path: String,
+    /// `None` when the file was not found in the listing of its folder, like alternate data streams
+    pub size: Option<u64>,
+}
 
 pub struct TriageCollector {
     params: CollectionParameters,
+    backend: Arc<dyn CollectionBackend>,
+    /// Registry given with `with_registry`, used instead of the one of the backend
+    registry: Option<Mutex<Box<dyn RegistryReader>>>,
+    /// Volumes given with `with_volumes`, used instead of the ones of the backend
+    volume_source: Option<Box<dyn VolumeSource>>,
+}
+
+/// Targets with their variables replaced
+struct ResolvedTargets {
+    paths: Vec<String>,
+    exclusions: Vec<Glob>,
+    /// Profiles with their homes expanded
+    profiles: Vec<UserProfile>,
+    /// Folder of the archive of the installation the targets were resolved for
+    prefix: Option<String>,
+}
+
+/// File waiting for a worker thread
+struct QueuedFile {
+    path: String,
+    /// Backend the file is read from: the examined system or one of its shadow copies
+    backend: Arc<dyn CollectionBackend>,
+    /// Path of the file in the archive
+    entry: String,
+    /// Profile of the user the file belongs to
+    owner: Option<Arc<UserProfile>>,
 }
 
 #[derive(Clone, Debug)]
 pub struct CollectionParameters {
-    /// Collects the MFT from all disks
+    /// Collects the MFT from all disks, like the target `%NTFSDRIVES%\$MFT`
     pub all_disks_mft: bool,
     /// Collects the USN journal from the system drive
     pub usn_jrnl: bool,
-    /// Collects the USN journal for all the drives
+    /// Collects the USN journal for all the drives, like the target `%NTFSDRIVES%\$Extend\$UsnJrnl:$J`
     pub all_usn_jrnl: bool,
+    /// Also collects the artifacts from every Volume Shadow Copy into `vss\<id>\...`
+    pub shadow_copies: bool,
+    /// Writes a listing of every file, including deleted entries, of the FAT and exFAT volumes
+    pub removable_media_listing: bool,
+    /// Paths or globs to collect. Entries starting with `!` are exclusions applied to every target,
+    /// like `!**\cache2\**`, and folders they fully exclude are not walked. A target can end with
+    /// conditions on the size, timestamps or extension of the files, see the `filter` module.
+    /// `%PREVIOUSROOT%` and `%PREVIOUSUSERHOME%` point to the installation kept in `Windows.old`, and
+    /// `%ALLDRIVES%`, `%FIXEDDRIVES%` or `%NTFSDRIVES%` expand the target once for each volume.
     pub paths: Vec<String>,
     pub out_file: String,
     pub threads: usize,
     pub buffer_size: usize,
+    /// Paths waiting for a worker thread. Pattern expansion stops while the queue is full.
+    pub queue_size: usize,
+    /// Folders below the start of a pattern walked at most, `**` included
+    pub max_depth: usize,
+    /// Collects every Windows installation found in the volumes, each one with its own variables
+    /// and profiles, into `installations\<SystemRoot>\...`
+    pub all_installations: bool,
 }
 
 impl Default for CollectionParameters {
@@ -44,30 +108,130 @@ impl Default for CollectionParameters {
             all_disks_mft: false,
             usn_jrnl: false,
             all_usn_jrnl: false,
+            shadow_copies: false,
+            removable_media_listing: false,
             paths: get_default_collection_paths(),
             out_file: "./frnsc-triage.zip".to_string(),
             threads: 4,
             buffer_size: 1_000_000,
+            queue_size: 10_000,
+            max_depth: DEFAULT_MAX_DEPTH,
+            all_installations: false,
         }
     }
 }
 
 impl TriageCollector {
+    /// Collects from the running system
+    #[cfg(windows)]
     pub fn new(params: CollectionParameters) -> Self {
-        Self { params }
+        Self::with_backend(params, LiveBackend::new())
+    }
+
+    /// Collects from a Windows volume mounted at `mount_point`, which takes the place of `C:\`
+    pub fn from_mount_point<P: Into<PathBuf>>(params: CollectionParameters, mount_point: P) -> Self {
+        Self::with_backend(params, LogicalBackend::new(mount_point))
+    }
+
+    pub fn with_backend<B: CollectionBackend + 'static>(params: CollectionParameters, backend: B) -> Self {
+        Self {
+            params,
+            backend: Arc::new(backend),
+            registry: None,
+            volume_source: None,
+        }
+    }
+
+    /// Resolves the variables and the user profiles with `registry` instead of the registry of the
+    /// backend, like an `OfflineRegistry` of hives exported from another system
+    pub fn with_registry(mut self, registry: Box<dyn RegistryReader>) -> Self {
+        self.registry = Some(Mutex::new(registry));
+        self
+    }
+
+    /// Takes the volumes used by `all_disks_mft`, `all_usn_jrnl` and `removable_media_listing`
+    /// from `volumes` instead of the backend
+    pub fn with_volumes<V: VolumeSource + 'static>(mut self, volumes: V) -> Self {
+        self.volume_source = Some(Box::new(volumes));
+        self
+    }
+
+    fn mounted_volumes(&self) -> Vec<MountedVolume> {
+        match &self.volume_source {
+            Some(source) => source.mounted_volumes(),
+            None => self.backend.mounted_volumes(),
+        }
+    }
+
+    /// Resolves and expands the targets like `collect` and returns the entries the archive would
+    /// have, sorted by their archive path, without reading the files or creating the archive. Only
+    /// the targets with `type=` conditions open the files to read their headers.
+    pub fn dry_run(&self) -> Vec<PlannedFile> {
+        let resolved = self.resolve_targets();
+        let mut files = Vec::new();
+        let mut folders: BTreeMap<String, Vec<RawDirEntry>> = BTreeMap::new();
+        let mut listed: Option<Arc<dyn CollectionBackend>> = None;
+        self.queue_files(&resolved, &mut |queued| {
+            // Files are queued grouped by backend, the system first and then each shadow copy
+            if !listed.as_ref().is_some_and(|v| Arc::ptr_eq(v, &queued.backend)) {
+                folders.clear();
+                listed = Some(Arc::clone(&queued.backend));
+            }
+            let size = split_parent(&queued.path).and_then(|(folder, name)| {
+                let upcase = queued.backend.upcase(&queued.path);
+                folders
+                    .entry(folder)
+                    .or_insert_with_key(|folder| queued.backend.read_dir(folder).unwrap_or_default())
+                    .iter()
+                    .find(|v| !v.is_directory && upcase.eq(&v.name, name))
+                    .map(|v| v.size)
+            });
+            files.push(PlannedFile {
+                path: queued.path,
+                archive_path: queued.entry,
+                size,
+            });
+            true
+        });
+        let generated = resolved
+            .iter()
+            .filter(|v| !v.profiles.is_empty())
+            .map(|v| user_profiles_entry(v.prefix.as_deref()))
+            .chain(self.listed_volumes().into_iter().map(|(device, _)| file_listing_entry(&device)));
+        files.extend(generated.map(|archive_path| PlannedFile {
+            path: String::new(),
+            archive_path,
+            size: None,
+        }));
+        files.sort_by_cached_key(|v| v.archive_path.to_uppercase());
+        files
     }
 
+    /// Copies the files into the archive. The targets are expanded while the worker threads copy
+    /// the files already found, through a queue of at most `queue_size` paths. The files of a user
+    /// are stored below `users\<SID>\`, and the profiles are written to `user_profiles.csv`.
+    ///
+    /// The threads write into `<out_file>.partial`, whose entries are then copied sorted by name
+    /// into `out_file`, so the archive is the same whatever the number of threads.
     pub fn collect(&self) -> ForensicResult<()> {
-        let paths_to_process = self.prepare_paths_to_collect();
-        let mutex = Arc::new(Mutex::new(paths_to_process));
-        let zip_file = std::fs::File::create(&self.params.out_file)?;
+        let resolved = self.resolve_targets();
+        let partial_path = format!("{}.partial", self.params.out_file);
+        let zip_file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&partial_path)?;
         let shared_zip = Arc::new(Mutex::new(zip::ZipWriter::new(zip_file)));
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<QueuedFile>(self.params.queue_size.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
 
-        let mut thread_handlers = Vec::with_capacity(self.params.threads);
+        let threads = self.params.threads.max(1);
+        let mut thread_handlers = Vec::with_capacity(threads);
         let buffer_size = self.params.buffer_size;
-        for i in 0..self.params.threads {
+        for i in 0..threads {
             let shared_zip = shared_zip.clone();
-            let paths_to_process = Arc::clone(&mutex);
+            let paths_to_process = Arc::clone(&receiver);
             thread_handlers.push(
                 std::thread::Builder::new()
                     .name(format!("TriageThrd{}", i))
@@ -75,437 +239,1119 @@ impl TriageCollector {
                         // 1 MB buffer
                         let mut buffer = vec![0; buffer_size];
                         loop {
-                            let path_to_file = match paths_to_process.as_ref().lock() {
-                                Ok(mut v) => match v.pop() {
-                                    Some(v) => v,
-                                    None => return,
-                                },
-                                Err(_) => todo!(),
-                            };
-                            let zip_path_to_file = path_to_file.replace(":\\", "\\");
-                            let splited_path: Vec<String> = path_to_file
-                                .split(std::path::MAIN_SEPARATOR)
-                                .into_iter()
-                                .map(|v| v.to_string())
-                                .collect();
-                            if path_to_file.contains("*") {
-                                for pth in zip_path_to_file.split(std::path::MAIN_SEPARATOR) {
-                                    if pth == "*" {
-                                    } else if pth == "**" {
-                                    } else {
-                                    }
-                                }
-                                continue;
-                            }
-                            let parent_folder = std::path::Path::new(&path_to_file).parent();
-
-                            let mut file = match RawFile::open(&path_to_file) {
+                            let queued = match paths_to_process.lock().unwrap().recv() {
                                 Ok(v) => v,
-                                Err(_) => {
-                                    println!("Error processing {}", path_to_file);
-                                    continue;
-                                }
+                                Err(_) => return,
                             };
-                            if (file.file_size as usize) < buffer.len() {
-                                let readed = match file.read(&mut buffer) {
-                                    Ok(v) => v,
-                                    Err(_) => continue,
-                                };
-                                if file.file_size as usize != readed {
-                                    continue;
-                                }
-                                let mut zip_guard = shared_zip.lock().unwrap();
-                                if let Some(parent) = parent_folder {
-                                    let ancstr = parent.to_str().unwrap().replace(":\\", "\\");
-                                    match zip_guard.add_directory(
-                                        &ancstr,
-                                        FileOptions::default()
-                                            .compression_level(Some(6))
-                                            .compression_method(zip::CompressionMethod::Deflated),
-                                    ) {
-                                        Ok(_) => {
-                                            println!("Creating ancestors {}", &ancstr);
-                                        }
-                                        Err(err) => {
-                                            println!(
-                                                "Error Creating directory {}: {:?}",
-                                                &ancstr, err
-                                            )
-                                        }
-                                    }
-                                }
-                                match zip_guard.start_file(
-                                    &path_to_file.replace(":\\", "\\"),
-                                    FileOptions::default()
-                                        .compression_level(Some(6))
-                                        .compression_method(zip::CompressionMethod::Deflated),
-                                ) {
-                                    Ok(_) => {
-                                        println!("Creating file {}", &path_to_file);
-                                    }
-                                    Err(err) => {
-                                        println!("Error Creating file {}: {:?}", &path_to_file, err)
-                                    }
-                                }
-
-                                let _ = zip_guard.write_all(&buffer[0..readed]);
-                            } else {
-                                let mut zip_guard = shared_zip.lock().unwrap();
-                                if let Some(parent) = parent_folder {
-                                    let ancstr = parent.to_str().unwrap().replace(":\\", "\\");
-                                    match zip_guard.add_directory(
-                                        &ancstr,
-                                        FileOptions::default()
-                                            .compression_level(Some(6))
-                                            .compression_method(zip::CompressionMethod::Deflated),
-                                    ) {
-                                        Ok(_) => {
-                                            println!("Creating ancestors {}", &ancstr);
-                                        }
-                                        Err(err) => {
-                                            println!(
-                                                "Error Creating directory {}: {:?}",
-                                                &ancstr, err
-                                            )
-                                        }
-                                    }
-                                }
-                                match zip_guard.start_file(
-                                    &path_to_file.replace(":\\", "\\"),
-                                    FileOptions::default()
-                                        .compression_level(Some(6))
-                                        .compression_method(zip::CompressionMethod::Deflated),
-                                ) {
-                                    Ok(_) => {
-                                        println!("Creating file {}", &path_to_file);
-                                    }
-                                    Err(err) => {
-                                        println!("Error Creating file {}: {:?}", &path_to_file, err)
-                                    }
-                                }
-                                loop {
-                                    let readed = match file.read(&mut buffer) {
-                                        Ok(v) => v,
-                                        Err(_) => continue,
-                                    };
-                                    if readed == 0 {
-                                        break;
-                                    }
-                                    let _ = zip_guard.write_all(&buffer[0..readed]);
-                                }
+                            if let Err(err) = collect_file(
+                                queued.backend.as_ref(),
+                                &queued.path,
+                                &queued.entry,
+                                queued.owner.as_deref(),
+                                &mut buffer,
+                                &shared_zip,
+                            ) {
+                                println!("Error processing {}: {:?}", queued.path, err);
                             }
-                            println!("Processing: {}, file_size={}", path_to_file, file.file_size);
                         }
                     })
                     .unwrap(),
             );
         }
 
+        self.queue_files(&resolved, &mut |queued| sender.send(queued).is_ok());
+        drop(sender);
+
         for thread in thread_handlers {
             thread.join().unwrap();
         }
 
-        Ok(())
+        for targets in &resolved {
+            if targets.profiles.is_empty() {
+                continue;
+            }
+            let listing_path = user_profiles_entry(targets.prefix.as_deref());
+            let mut zip_guard = shared_zip.lock().unwrap();
+            let written = zip_guard
+                .start_file(
+                    &listing_path,
+                    FileOptions::default()
+                        .compression_level(Some(6))
+                        .compression_method(zip::CompressionMethod::Deflated),
+                )
+                .map_err(|e| ForensicError::Other(format!("{:?}", e)))
+                .and_then(|_| write_user_profiles_csv(&targets.profiles, &mut *zip_guard));
+            if let Err(err) = written {
+                println!("Error writing the user profiles to {}: {:?}", listing_path, err);
+            }
+        }
+
+        for (device, mut fs) in self.listed_volumes() {
+            let mut zip_guard = shared_zip.lock().unwrap();
+            let listing_path = file_listing_entry(&device);
+            if let Err(err) = zip_guard.start_file(
+                &listing_path,
+                FileOptions::default()
+                    .compression_level(Some(6))
+                    .compression_method(zip::CompressionMethod::Deflated),
+            ) {
+                println!("Error Creating file {}: {:?}", &listing_path, err);
+                continue;
+            }
+            if let Err(err) = write_file_listing(fs.as_mut(), &device, &mut *zip_guard) {
+                println!("Error listing files of {}: {:?}", device, err);
+            }
+        }
+
+        let partial = shared_zip
+            .lock()
+            .unwrap()
+            .finish()
+            .map_err(|e| ForensicError::Other(format!("{:?}", e)))?;
+        let sorted = write_sorted_archive(partial, std::fs::File::create(&self.params.out_file)?);
+        if let Err(err) = std::fs::remove_file(&partial_path) {
+            println!("Error removing {}: {:?}", partial_path, err);
+        }
+        sorted
     }
 
-    fn prepare_paths_to_collect(&self) -> Vec<String> {
-        let registry = LiveRegistryReader::new();
-        let mut to_ret = Vec::with_capacity(1_000);
-        let mut registry: Box<dyn RegistryReader> = Box::new(registry);
-        let sys_root = system_root(&mut registry);
-        let sys_drive = system_drive(&mut registry);
-        let prog_data = replace_envvars(&program_data(&mut registry), &sys_drive, &sys_root, "");
-        let users_homes = match list_users_homes_from_reg(&mut registry) {
-            Ok(pths) => {
-                let mut vc = Vec::with_capacity(32);
-                for v in pths {
-                    if contains_env_var(&v) {
-                        vc.push(replace_envvars(&v, &sys_root, &sys_drive, &prog_data));
-                    } else {
-                        vc.push(v);
-                    }
-                }
-                vc
+    /// Local FAT and exFAT volumes, opened as raw file systems, whose files are listed in the
+    /// archive with `removable_media_listing`
+    fn listed_volumes(&self) -> Vec<(String, Box<dyn RawFileSystem>)> {
+        if !self.params.removable_media_listing {
+            return Vec::new();
+        }
+        let mut listed = Vec::new();
+        for device in self.mounted_volumes() {
+            if !device.drive_type.is_local() {
+                continue;
             }
-            Err(_) => vec![],
-        };
+            let device = device.root;
+            let mut volume = match self.backend.open_volume(&device) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if !matches!(
+                read_file_system_type(&mut volume),
+                Ok(Some(FileSystemType::Fat)) | Ok(Some(FileSystemType::ExFat))
+            ) {
+                continue;
+            }
+            if let Ok(fs) = open_raw_file_system(volume) {
+                listed.push((device, fs));
+            }
+        }
+        listed
+    }
 
-        if self.params.usn_jrnl {
-            to_ret.push(replace_envvars(
-                USN_JRNL_PATH,
-                &sys_drive,
-                &sys_root,
-                &prog_data,
-            ));
-            to_ret.push(replace_envvars(
-                USN_JRNL_MAX_PATH,
-                &sys_drive,
-                &sys_root,
-                &prog_data,
-            ));
-        }
-        let mounted_devices = mounted_devices();
-        if self.params.all_disks_mft {
-            for device in &mounted_devices {
-                if device == &sys_drive {
-                    continue;
+    /// Expands the targets and passes each file to `send` as soon as it is found, first from the
+    /// examined system and then, with `shadow_copies`, from every shadow copy of the volumes of the
+    /// targets. The targets are expanded again in each snapshot so files deleted since then are
+    /// found. Stops when `send` returns false.
+    fn queue_files(&self, resolved: &[ResolvedTargets], send: &mut dyn FnMut(QueuedFile) -> bool) {
+        let profiles: Vec<Vec<Arc<UserProfile>>> = resolved
+            .iter()
+            .map(|v| v.profiles.iter().cloned().map(Arc::new).collect())
+            .collect();
+        let mut seen = HashSet::new();
+        if !self.queue_files_from(&self.backend, None, resolved, &profiles, &mut seen, send) || !self.params.shadow_copies
+        {
+            return;
+        }
+        for (id, snapshot) in self.shadow_copy_backends(resolved) {
+            let source = format!(r"vss\{}", id);
+            if !self.queue_files_from(&snapshot, Some(&source), resolved, &profiles, &mut seen, send) {
+                return;
+            }
+        }
+    }
+
+    /// Queues the files of `backend` matched by the targets and not in `seen`. Snapshots are stored
+    /// under their `source` folder and only expand the targets of their own volume.
+    fn queue_files_from(
+        &self,
+        backend: &Arc<dyn CollectionBackend>,
+        source: Option<&str>,
+        resolved: &[ResolvedTargets],
+        profiles: &[Vec<Arc<UserProfile>>],
+        seen: &mut HashSet<String>,
+        send: &mut dyn FnMut(QueuedFile) -> bool,
+    ) -> bool {
+        let volumes = backend.volumes();
+        for (targets, profiles) in resolved.iter().zip(profiles) {
+            let prefix = match (source, targets.prefix.as_deref()) {
+                (Some(source), Some(prefix)) => Some(format!(r"{}\{}", source, prefix)),
+                (Some(v), None) | (None, Some(v)) => Some(v.to_string()),
+                (None, None) => None,
+            };
+            let paths: Vec<String> = match source {
+                Some(_) => targets
+                    .paths
+                    .iter()
+                    .filter(|path| match get_drive_and_disk(path) {
+                        Ok((_, disk)) => volumes.iter().any(|v| v.eq_ignore_ascii_case(&disk)),
+                        Err(_) => false,
+                    })
+                    .cloned()
+                    .collect(),
+                None => targets.paths.clone(),
+            };
+            let expanded = self.expand_targets(backend.as_ref(), paths, &targets.exclusions);
+            for path in unique_paths(backend.as_ref(), source, expanded, seen) {
+                let owner = profile_of(profiles, &path).cloned();
+                let queued = QueuedFile {
+                    entry: archive_entry(prefix.as_deref(), owner.as_deref(), &path),
+                    path,
+                    backend: Arc::clone(backend),
+                    owner,
+                };
+                if !send(queued) {
+                    return false;
                 }
-                let mft_path = format!("{}$MFT", device);
-                to_ret.push(mft_path);
             }
         }
-        if self.params.all_usn_jrnl {
-            for device in &mounted_devices {
-                if device == &sys_drive && self.params.usn_jrnl {
+        true
+    }
+
+    /// Every shadow copy of the volumes referenced by the targets, read as an NTFS volume with the
+    /// drive letter of its volume
+    fn shadow_copy_backends(&self, resolved: &[ResolvedTargets]) -> Vec<(String, Arc<dyn CollectionBackend>)> {
+        let mut disks: Vec<String> = resolved
+            .iter()
+            .flat_map(|v| v.paths.iter())
+            .filter_map(|path| get_drive_and_disk(path).ok())
+            .map(|(_, disk)| disk.to_uppercase())
+            .collect();
+        disks.sort();
+        disks.dedup();
+        let mut snapshots = Vec::new();
+        for disk in disks {
+            let catalog = match self.backend.open_volume(&disk).and_then(|mut v| VssCatalog::parse(&mut v)) {
+                Ok(v) => Arc::new(v),
+                Err(err) => {
+                    println!("Error reading the shadow copies of {}: {:?}", disk, err);
                     continue;
                 }
-                to_ret.push(format!(r"{}\$Extend\$UsnJrnl:$J", device));
-                to_ret.push(format!(r"{}\$Extend\$UsnJrnl:$MAX", device));
+            };
+            for (store, shadow) in catalog.stores.iter().enumerate() {
+                let (backend, volume_catalog, volume) = (Arc::clone(&self.backend), Arc::clone(&catalog), disk.clone());
+                let letter = disk.chars().next().unwrap_or('C');
+                let opened = NtfsBackend::new(letter, move || {
+                    ShadowVolume::new(backend.open_volume(&volume)?, Arc::clone(&volume_catalog), store)
+                });
+                match opened {
+                    Ok(v) => snapshots.push((shadow.info.shadow_copy_id.clone(), Arc::new(v) as Arc<dyn CollectionBackend>)),
+                    Err(err) => println!("Error opening shadow copy {}: {:?}", shadow.info.shadow_copy_id, err),
+                }
             }
         }
+        snapshots
+    }
 
-        for path in &self.params.paths {
-            if is_user_home_env(&path) {
-                for pth in replace_home_vars(path, &users_homes) {
-                    to_ret.push(pth);
-                }
-            } else if contains_env_var(path) {
-                to_ret.push(replace_envvars(path, &sys_drive, &sys_root, &prog_data));
+    /// Expands every target in the examined system, sorted ignoring case
+    #[cfg(test)]
+    fn prepare_paths_to_collect(&self) -> Vec<String> {
+        let resolved = self.resolve_targets();
+        let expanded = resolved
+            .iter()
+            .flat_map(|targets| self.expand_targets(self.backend.as_ref(), targets.paths.clone(), &targets.exclusions));
+        let mut seen = HashSet::new();
+        let mut paths: Vec<String> = unique_paths(self.backend.as_ref(), None, expanded, &mut seen).collect();
+        paths.sort_by_cached_key(|v| self.backend.upcase(v).upcase_str(v));
+        paths
+    }
+
+    /// Replaces the environment variables of the targets and compiles the exclusions. With
+    /// `all_installations` the targets are resolved once for each Windows installation found in the
+    /// volumes, with its own variables and profiles, and stored under its own archive folder.
+    fn resolve_targets(&self) -> Vec<ResolvedTargets> {
+        let mounted_devices: Vec<String> = self.mounted_volumes().into_iter().map(|v| v.root).collect();
+        let (locations, environment, profiles) = self.system_environment(&mounted_devices);
+        let mut volume_targets = self.volume_targets(&environment);
+        let installations = match self.params.all_installations {
+            true => find_windows_installations(self.backend.as_ref(), &mounted_devices),
+            false => vec![],
+        };
+        if installations.is_empty() {
+            let mut resolved = self.resolve_paths(&environment, profiles);
+            resolved.paths.splice(0..0, volume_targets);
+            return vec![resolved];
+        }
+        let mut resolved: Vec<ResolvedTargets> = Vec::with_capacity(installations.len() + 1);
+        for system_root in &installations {
+            let mut targets = if system_root.eq_ignore_ascii_case(&locations.system_root) {
+                self.resolve_paths(&environment, profiles.clone())
             } else {
-                to_ret.push(path.to_string());
-            }
+                let (environment, profiles) = self.installation_environment(system_root);
+                self.resolve_paths(&environment, profiles)
+            };
+            println!("Collecting the Windows installation in {}", system_root);
+            targets.prefix = Some(installation_prefix(system_root));
+            resolved.push(targets);
         }
+        // The files of the system drive are already collected with its installation
+        volume_targets.retain(|v| !resolved.iter().any(|t| t.paths.iter().any(|p| p.eq_ignore_ascii_case(v))));
+        let exclusions = self.resolve_paths(&environment, vec![]).exclusions;
+        resolved.insert(
+            0,
+            ResolvedTargets {
+                paths: volume_targets,
+                exclusions,
+                profiles: vec![],
+                prefix: None,
+            },
+        );
+        resolved
+    }
 
-        to_ret
+    /// Variables and profiles of the running system, or of the one in the backend
+    fn system_environment(&self, mounted_devices: &[String]) -> (SystemLocations, Environment, Vec<UserProfile>) {
+        let mut backend_registry = match &self.registry {
+            Some(_) => None,
+            None => self.backend.registry(),
+        };
+        let mut injected_registry = self.registry.as_ref().map(|v| v.lock().unwrap());
+        let registry = match injected_registry.as_deref_mut() {
+            Some(registry) => Some(registry),
+            None => backend_registry.as_mut(),
+        };
+        match registry {
+            Some(registry) => {
+                let locations =
+                    SystemLocations::from_registry(registry).validated(self.backend.as_ref(), mounted_devices);
+                let environment = Environment::from_locations(registry, &locations);
+                let profiles = read_profiles(registry, &environment, None);
+                (locations, environment, profiles)
+            }
+            None => {
+                let locations = SystemLocations::default().validated(self.backend.as_ref(), mounted_devices);
+                println!(
+                    r"The registry is not available, using the default locations in {}\",
+                    locations.system_drive
+                );
+                let profiles = users_homes_from_listing(self.backend.as_ref(), &locations.system_drive)
+                    .iter()
+                    .map(|home| UserProfile::from_home(home))
+                    .collect();
+                let environment = Environment::with_defaults(&locations.system_drive);
+                (locations, environment, profiles)
+            }
+        }
     }
-}
 
+    /// Variables and profiles of another installation, read from its own hives. The paths it
+    /// recorded in its registry are moved to where it was found: a system that was `C:` in a dual
+    /// boot can be `D:` now, and a `Windows.old` folder has the old `C:` inside.
+    fn installation_environment(&self, system_root: &str) -> (Environment, Vec<UserProfile>) {
+        let installed = SystemLocations::for_installation(system_root);
+        match OfflineRegistry::from_backend(self.backend.as_ref(), system_root) {
+            Ok(registry) => {
+                let mut registry: Box<dyn RegistryReader> = Box::new(registry);
+                let recorded = SystemLocations::from_registry(&mut registry);
+                let mut environment = Environment::from_locations(&mut registry, &recorded);
+                environment.rebase(&recorded.system_drive, &installed.system_drive);
+                environment.set("SystemRoot", system_root);
+                let moved = Some((recorded.system_drive.as_str(), installed.system_drive.as_str()));
+                let profiles = read_profiles(&mut registry, &environment, moved);
+                (environment, profiles)
+            }
+            Err(err) => {
+                println!("Error reading the registry of {}: {:?}", system_root, err);
+                let mut environment = Environment::with_defaults(&installed.system_drive);
+                environment.set("SystemRoot", system_root);
+                let profiles = users_homes_from_listing(self.backend.as_ref(), &installed.system_drive)
+                    .iter()
+                    .map(|home| UserProfile::from_home(home))
+                    .collect();
+                (environment, profiles)
+            }
+        }
+    }
 
-#[derive(Debug)]
-struct EndPathPattern {
-    pattern: String,
-    base_path: PathBuf,
-    fs_iter: Option<ReadDir>,
-    path_pattern: Option<Box<EndPathPattern>>,
-}
+    /// Files of the volumes and not of an installation: the MFT and the USN journals
+    fn volume_targets(&self, environment: &Environment) -> Vec<String> {
+        let mut targets = Vec::new();
+        if self.params.usn_jrnl {
+            targets.extend([USN_JRNL_PATH, USN_JRNL_MAX_PATH]);
+        }
+        if self.params.all_disks_mft {
+            targets.push(NTFS_DRIVES_MFT_PATH);
+        }
+        if self.params.all_usn_jrnl {
+            targets.extend([NTFS_DRIVES_USN_JRNL_PATH, NTFS_DRIVES_USN_JRNL_MAX_PATH]);
+        }
+        targets
+            .into_iter()
+            .flat_map(|path| self.expand_with(path, vec![environment]))
+            .collect()
+    }
 
-impl EndPathPattern {
-    pub fn new(pattern: String) -> Result<Self, std::io::Error> {
-        let (pattern, base_path) = match pattern.rfind("\\") {
-            Some(pos) => (pattern[pos + 1..].to_string(),pattern[..pos].to_string()),
-            None => (format!(".*"), pattern.clone())
+    /// Expands the targets and the exclusions with the variables of a system, the ones with user
+    /// variables once for each profile. The targets with `%PREVIOUSROOT%` or `%PREVIOUSUSERHOME%`
+    /// are expanded with the variables of each installation kept in `Windows.old`.
+    fn resolve_paths(&self, environment: &Environment, profiles: Vec<UserProfile>) -> ResolvedTargets {
+        let mut to_ret = Vec::with_capacity(1_000);
+        let user_environments = self.user_environments(environment, &profiles);
+        let previous = match self.params.paths.iter().any(|v| references_previous_variables(v)) {
+            true => self.previous_environments(environment),
+            false => vec![],
         };
-        let fs_iter = if std::path::Path::new(&base_path).is_dir() {
-            Some(std::fs::read_dir(&base_path)?)
+        let mut exclusions = Vec::new();
+        for path in &self.params.paths {
+            let (path, resolved) = match path.strip_prefix('!') {
+                Some(exclusion) => (exclusion, &mut exclusions),
+                None => (path.as_str(), &mut to_ret),
+            };
+            let environments: Vec<&Environment> = match (references_previous_variables(path), references_user_variables(path)) {
+                (true, true) => previous.iter().flat_map(|(_, users)| users).collect(),
+                (true, false) => previous.iter().map(|(system, _)| system).collect(),
+                (false, true) => user_environments.iter().collect(),
+                (false, false) => vec![environment],
+            };
+            resolved.extend(self.expand_with(path, environments));
+        }
+        let exclusions: Vec<Glob> = exclusions
+            .iter()
+            .filter_map(|v| match Glob::new(v) {
+                Ok(glob) => Some(glob),
+                Err(err) => {
+                    println!("Error compiling exclusion {}: {:?}", v, err);
+                    None
+                }
+            })
+            .collect();
+
+        ResolvedTargets {
+            paths: to_ret,
+            exclusions,
+            profiles,
+            prefix: None,
+        }
+    }
+
+    /// Expands a target with each environment, and once for each volume when it references
+    /// `%ALLDRIVES%`, `%FIXEDDRIVES%` or `%NTFSDRIVES%`
+    fn expand_with(&self, path: &str, environments: Vec<&Environment>) -> Vec<String> {
+        let per_drive: Vec<Environment>;
+        let environments = if references_drive_variables(path) {
+            let names = variable_names(path);
+            let references = |variable: &str| names.iter().any(|v| v.eq_ignore_ascii_case(variable));
+            let (fixed_only, ntfs_only) = (references("FIXEDDRIVES"), references("NTFSDRIVES"));
+            let drives: Vec<String> = self
+                .mounted_volumes()
+                .into_iter()
+                .filter(|volume| {
+                    (!fixed_only || volume.drive_type == DriveType::Fixed)
+                        && (!ntfs_only || volume.may_be_ntfs())
+                        && volume.drive_type.is_local()
+                })
+                .map(|volume| volume.root.trim_end_matches('\\').to_string())
+                .collect();
+            per_drive = environments
+                .iter()
+                .flat_map(|environment| {
+                    drives.iter().map(|drive| {
+                        let mut environment = (*environment).clone();
+                        for variable in DRIVE_VARIABLES {
+                            environment.set(variable, drive);
+                        }
+                        environment
+                    })
+                })
+                .collect();
+            per_drive.iter().collect()
         } else {
-            None
+            environments
         };
-        Ok(EndPathPattern {
-            pattern,
-            base_path : std::path::PathBuf::from(base_path),
-            fs_iter,
-            path_pattern : None
-        })
+        environments
+            .into_iter()
+            .filter_map(|environment| match environment.expand(path) {
+                Ok(v) => Some(v),
+                Err(err) => {
+                    println!("Error resolving {}: {:?}", path, err);
+                    None
+                }
+            })
+            .collect()
     }
-}
 
-impl PathPatternIterator for EndPathPattern {}
+    /// Environment of each profile, once for each home
+    fn user_environments(&self, environment: &Environment, profiles: &[UserProfile]) -> Vec<Environment> {
+        let mut users_homes: Vec<&str> = profiles.iter().map(|v| v.home.as_str()).collect();
+        users_homes.sort_by_key(|v| v.to_uppercase());
+        users_homes.dedup_by_key(|v| v.to_uppercase());
+        users_homes
+            .iter()
+            .map(|home| environment.for_user(home, self.user_hive(home).as_ref()))
+            .collect()
+    }
 
-impl Iterator for EndPathPattern {
-    type Item = String;
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if let Some(pth_pattrn) = &mut self.path_pattern {
-                let nxt = pth_pattrn.next();
-                if let Some(nxt_elmnt) = nxt {
-                    return Some(nxt_elmnt);
+    /// Variables of the installations in `Windows.old` folders of the SystemDrive, and of each of
+    /// their profiles
+    fn previous_environments(&self, environment: &Environment) -> Vec<(Environment, Vec<Environment>)> {
+        let system_drive = format!("{}\\", environment.get("SystemDrive").unwrap_or_else(|| "C:".to_string()));
+        let previous: Vec<String> = find_windows_installations(self.backend.as_ref(), &[system_drive])
+            .into_iter()
+            .filter(|v| is_previous_installation(v))
+            .collect();
+        if previous.is_empty() {
+            println!("No previous installation found for the %PREVIOUSROOT% targets");
+        }
+        previous
+            .iter()
+            .map(|system_root| {
+                let (mut system, profiles) = self.installation_environment(system_root);
+                system.set("PREVIOUSROOT", system_root);
+                let users = self
+                    .user_environments(&system, &profiles)
+                    .into_iter()
+                    .map(|mut user| {
+                        let home = user.get("USERHOME").unwrap_or_default();
+                        user.set("PREVIOUSUSERHOME", &home);
+                        user
+                    })
+                    .collect();
+                (system, users)
+            })
+            .collect()
+    }
+
+    /// Loads the `NTUSER.DAT` of a user to read its variables
+    fn user_hive(&self, home: &str) -> Option<Hive> {
+        let path = join_path(home, "NTUSER.DAT");
+        match load_hive(self.backend.as_ref(), &path) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                println!("Error loading the variables of {} from {}: {:?}", home, path, err);
+                None
+            }
+        }
+    }
+
+    /// Replaces the globs with the files they match, one target at a time, and removes the excluded
+    /// files and the ones rejected by the conditions of their target
+    fn expand_targets<'a>(
+        &'a self,
+        backend: &'a dyn CollectionBackend,
+        targets: Vec<String>,
+        exclusions: &'a [Glob],
+    ) -> impl Iterator<Item = String> + 'a {
+        let now = system_time_to_filetime(std::time::SystemTime::now());
+        targets.into_iter().flat_map(move |target| -> Box<dyn Iterator<Item = String> + 'a> {
+            let (path, conditions) = split_target(&target);
+            let filter = match conditions.map(|v| FileFilter::parse(v, now)).transpose() {
+                Ok(v) => v.unwrap_or_default(),
+                Err(err) => {
+                    println!("Error parsing {}: {:?}", target, err);
+                    return Box::new(std::iter::empty());
+                }
+            };
+            if !has_wildcards(path) {
+                let upcase = backend.upcase(path);
+                if exclusions.iter().any(|v| v.is_match(path, &upcase))
+                    || !(filter.is_empty() || file_matches(backend, path, &filter, &upcase))
+                {
+                    return Box::new(std::iter::empty());
                 }
+                return Box::new(std::iter::once(path.to_string()));
             }
-            self.path_pattern = None;
-            if let Some(readdir) = &mut self.fs_iter {
-                match readdir.next() {
-                    Some(v) => match v {
-                        Ok(dir_entry) => match dir_entry.file_type() {
-                            Ok(v) => {
-                                if v.is_dir() {
-                                    let pth = dir_entry.path().to_str().unwrap_or("").to_string();
-                                    if self.pattern == "**" {
-                                        match EndPathPattern::new(format!("{}\\{}", pth, self.pattern)) {
-                                            Ok(v) => {
-                                                self.path_pattern = Some(Box::new(v));
-                                            }
-                                            Err(err) => {
-                                                println!("Error creating base pattern: {:?} {} {:?}", dir_entry.path(), self.pattern, err);
-                                            }
-                                        };
-                                    }
-                                    continue;
-                                } else if v.is_file() {
-                                    let pth = dir_entry.path().to_str().unwrap_or("").to_string();
-                                    if pth.is_empty() {
-                                        continue;
-                                    }
-                                    match Regex::new(&replace_pattern(&self.pattern)) {
-                                        Ok(v) => {
-                                            if v.find(&pth).is_some() {
-                                                return Some(pth);
-                                            }
-                                        }
-                                        Err(_) => {}
-                                    }
-                                    
-                                }
-                            }
-                            Err(_) => {}
-                        },
-                        Err(_) => {}
-                    },
-                    None => {
-                        break;
-                    }
+            match PathPattern::new(backend, path) {
+                Ok(pattern) => Box::new(
+                    pattern
+                        .excluding(exclusions)
+                        .filtered(filter)
+                        .with_max_depth(self.params.max_depth),
+                ),
+                Err(err) => {
+                    println!("Error expanding {}: {:?}", path, err);
+                    Box::new(std::iter::empty())
                 }
             }
+        })
+    }
+}
+
+/// Removes the files already in `seen`: the same path written with another case or separators, and
+/// hard links when the backend knows the file IDs. The keys of the files of a snapshot start with
+/// its `source` folder.
+fn unique_paths<'a, I>(
+    backend: &'a dyn CollectionBackend,
+    source: Option<&'a str>,
+    paths: I,
+    seen: &'a mut HashSet<String>,
+) -> impl Iterator<Item = String> + 'a
+where
+    I: Iterator<Item = String> + 'a,
+{
+    let prefix = source.map(|v| format!(r"{}\", v.to_uppercase())).unwrap_or_default();
+    paths.map(|v| canonical_path(&v)).filter(move |path| {
+        let key = backend.upcase(path).upcase_str(path);
+        if !seen.insert(format!("{}{}", prefix, key)) {
+            return false;
+        }
+        match backend.file_id(path) {
+            Some(id) => seen.insert(format!("{}{}#{}", prefix, key.split('\\').next().unwrap_or_default(), id)),
+            None => true,
         }
-        None
+    })
+}
+
+/// Checks the conditions of a target without wildcards with the entry of its parent folder
+fn file_matches(backend: &dyn CollectionBackend, path: &str, filter: &FileFilter, upcase: &UpCase) -> bool {
+    let (folder, name) = match split_parent(path) {
+        Some(v) => v,
+        None => return false,
+    };
+    let found = match backend.read_dir(&folder) {
+        Ok(entries) => entries
+            .iter()
+            .any(|v| !v.is_directory && upcase.eq(&v.name, name) && filter.matches(v, upcase)),
+        Err(_) => false,
+    };
+    found && matches_content(backend, path, filter)
+}
+
+/// Reads the header of a file to check the signatures of `filter`
+fn matches_content(backend: &dyn CollectionBackend, path: &str, filter: &FileFilter) -> bool {
+    if !filter.needs_content() {
+        return true;
     }
+    let mut file = match backend.open(path) {
+        Ok(v) => v,
+        Err(err) => {
+            println!("Error reading the header of {}: {:?}", path, err);
+            return false;
+        }
+    };
+    let mut header = [0; SIGNATURE_PROBE_SIZE];
+    let mut readed = 0;
+    while readed < header.len() {
+        match file.reader.read(&mut header[readed..]) {
+            Ok(0) | Err(_) => break,
+            Ok(bytes) => readed += bytes,
+        }
+    }
+    filter.matches_content(&header[..readed])
+}
+
+/// Profiles of the ProfileList with their homes expanded. `moved` replaces the volume recorded in
+/// the homes of an installation with the place where it was found.
+fn read_profiles(
+    registry: &mut Box<dyn RegistryReader>,
+    environment: &Environment,
+    moved: Option<(&str, &str)>,
+) -> Vec<UserProfile> {
+    let profiles = match list_user_profiles(registry) {
+        Ok(v) => v,
+        Err(err) => {
+            println!("Error listing the user profiles: {:?}", err);
+            return vec![];
+        }
+    };
+    profiles
+        .into_iter()
+        .filter_map(|mut profile| {
+            let home = match moved {
+                Some((from, to)) => rebase_path(&profile.home, from, to),
+                None => profile.home.clone(),
+            };
+            match environment.expand(&home) {
+                Ok(v) => {
+                    profile.home = v;
+                    Some(profile)
+                }
+                Err(err) => {
+                    println!("Error resolving the user home {}: {:?}", profile.home, err);
+                    None
+                }
+            }
+        })
+        .collect()
 }
 
-struct PathPattern {
-    pattern: String,
-    base_path: PathBuf,
-    first_pattern : String,
-    last_pattern : String,
-    path_pattern: Option<Box<dyn PathPatternIterator>>,
-    fs_iter: Option<ReadDir>,
+/// Folder of the archive for the files of the installation in `system_root`:
+/// `installations\D_Windows` for `D:\Windows`
+fn installation_prefix(system_root: &str) -> String {
+    format!(
+        r"installations\{}",
+        system_root.trim_end_matches('\\').replace(":\\", "_").replace(['\\', ':'], "_")
+    )
 }
 
-impl PathPattern {
-    pub fn with_base(base_path: PathBuf, pattern: String) -> Result<Self, std::io::Error> {
-        let (first_pattern, last_pattern) = match pattern.find("\\") {
-            Some(p) => (pattern[..p].to_string(), pattern[p+1..].to_string()),
-            None => (pattern.clone(), format!("*"))
-        };
-        
-        let fs_iter = if base_path.is_dir() {
-            Some(std::fs::read_dir(&base_path)?)
-        } else {
-            None
-        };
+/// Profiles found in `<system_drive>\Users`, used when the ProfileList cannot be read
+fn users_homes_from_listing(backend: &dyn CollectionBackend, system_drive: &str) -> Vec<String> {
+    match backend.read_dir(&format!(r"{}\Users", system_drive)) {
+        Ok(entries) => entries
+            .into_iter()
+            .filter(|v| v.is_directory)
+            .map(|v| format!(r"{}\Users\{}", system_drive, v.name))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
 
-        Ok(Self {
-            pattern,
-            base_path,
-            first_pattern,
-            last_pattern,
-            path_pattern: None,
-            fs_iter,
+/// Profile whose home contains `path`. The active profile is preferred to a `.bak` one.
+fn profile_of<'a>(profiles: &'a [Arc<UserProfile>], path: &str) -> Option<&'a Arc<UserProfile>> {
+    let path = path.to_uppercase();
+    profiles
+        .iter()
+        .filter(|v| {
+            let home = v.home.trim_end_matches('\\').to_uppercase();
+            path.strip_prefix(&home).is_some_and(|rest| rest.starts_with('\\'))
         })
+        .min_by_key(|v| v.backup)
+}
+
+/// Path of a file in the archive, `C\...` for `C:\...` below the folder `prefix`. The files of a
+/// user are stored below `users\<SID>`, so profiles that share a home folder name can be told apart.
+fn archive_entry(prefix: Option<&str>, owner: Option<&UserProfile>, path: &str) -> String {
+    let path = path.replace(":\\", "\\");
+    let path = match owner.and_then(|v| v.sid.as_deref()) {
+        Some(sid) => format!(r"users\{}\{}", sid, path),
+        None => path,
+    };
+    match prefix {
+        Some(prefix) => format!(r"{}\{}", prefix, path),
+        None => path,
     }
+}
 
-    pub fn new(pattern: String) -> Result<Self, std::io::Error> {
-        let split_pth: Vec<String> = pattern
-            .split(std::path::MAIN_SEPARATOR)
-            .into_iter()
-            .map(|v| v.to_string())
-            .collect();
-        let mut pos = 0;
-        for elmnt in &split_pth {
-            if elmnt.contains("*") {
+/// Archive path of the profiles of the installation stored below the folder `prefix`
+fn user_profiles_entry(prefix: Option<&str>) -> String {
+    match prefix {
+        Some(prefix) => format!(r"{}\user_profiles.csv", prefix),
+        None => "user_profiles.csv".to_string(),
+    }
+}
+
+/// Archive path of the file listing of the volume `device`
+fn file_listing_entry(device: &str) -> String {
+    format!(r"{}\file_listing.csv", device.replace(":\\", ""))
+}
+
+/// Copies a file into the archive at `entry`. Files smaller than the buffer are read before taking
+/// the archive lock so the other threads are not blocked while reading from disk.
+fn collect_file<W: Write + Seek>(
+    backend: &dyn CollectionBackend,
+    path: &str,
+    entry: &str,
+    owner: Option<&UserProfile>,
+    buffer: &mut [u8],
+    zip: &Mutex<ZipWriter<W>>,
+) -> ForensicResult<()> {
+    let mut file = backend.open(path)?;
+    if (file.size as usize) < buffer.len() {
+        let mut readed = 0;
+        while (readed as u64) < file.size {
+            let bytes = file.reader.read(&mut buffer[readed..])?;
+            if bytes == 0 {
+                break;
+            }
+            readed += bytes;
+        }
+        if readed as u64 != file.size {
+            return Err(ForensicError::Other(format!("Readed {} of {} bytes", readed, file.size)));
+        }
+        let mut zip_guard = zip.lock().unwrap();
+        start_zip_file(&mut zip_guard, entry)?;
+        zip_guard.write_all(&buffer[0..readed])?;
+    } else {
+        let mut zip_guard = zip.lock().unwrap();
+        start_zip_file(&mut zip_guard, entry)?;
+        loop {
+            let readed = file.reader.read(buffer)?;
+            if readed == 0 {
                 break;
             }
-            pos += 1;
+            zip_guard.write_all(&buffer[0..readed])?;
         }
-        let base_path = split_pth[0..pos].join("\\");
-        let pattern = split_pth[pos..].join("\\");
-        Self::with_base(PathBuf::from(base_path), pattern)
     }
+    match owner {
+        Some(user) => println!(
+            "Processing: {}, file_size={}, user={}, sid={}",
+            path,
+            file.size,
+            user.username,
+            user.sid.as_deref().unwrap_or_default()
+        ),
+        None => println!("Processing: {}, file_size={}", path, file.size),
+    }
+    Ok(())
+}
+
+/// Copies the entries of `archive` into `out` sorted by name, without compressing them again, so
+/// the result does not depend on the order in which the worker threads wrote them. Folders added
+/// for several files are kept once.
+fn write_sorted_archive<R: Read + Seek, W: Write + Seek>(archive: R, out: W) -> ForensicResult<()> {
+    let mut archive = zip::ZipArchive::new(archive).map_err(|e| ForensicError::Other(format!("{:?}", e)))?;
+    let mut entries = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        let file = archive
+            .by_index_raw(index)
+            .map_err(|e| ForensicError::Other(format!("{:?}", e)))?;
+        entries.push((file.name().to_string(), index));
+    }
+    entries.sort();
+    entries.dedup_by(|a, b| a.0 == b.0 && a.0.ends_with('/'));
+    let mut zip = ZipWriter::new(out);
+    for (_, index) in entries {
+        let file = archive
+            .by_index_raw(index)
+            .map_err(|e| ForensicError::Other(format!("{:?}", e)))?;
+        zip.raw_copy_file(file)
+            .map_err(|e| ForensicError::Other(format!("{:?}", e)))?;
+    }
+    zip.finish().map_err(|e| ForensicError::Other(format!("{:?}", e)))?;
+    Ok(())
 }
 
-pub trait PathPatternIterator : Iterator<Item = String>{
-    fn next_path(&mut self) -> Option<String> {
-        self.next()
+/// Creates the entry of a file, and of its parent folder, at `C\...` for `C:\...`. The path can
+/// start with the folder of an installation: `installations\D_Windows\D:\...`.
+fn start_zip_file<W: Write + Seek>(zip: &mut ZipWriter<W>, path: &str) -> ForensicResult<()> {
+    if let Some(pos) = path.rfind('\\') {
+        let ancstr = path[..pos].replace(":\\", "\\").replace(':', "");
+        match zip.add_directory(
+            &ancstr,
+            FileOptions::default()
+                .compression_level(Some(6))
+                .compression_method(zip::CompressionMethod::Deflated),
+        ) {
+            Ok(_) => {
+                println!("Creating ancestors {}", &ancstr);
+            }
+            Err(err) => {
+                println!("Error Creating directory {}: {:?}", &ancstr, err)
+            }
+        }
     }
+    zip.start_file(
+        path.replace(":\\", "\\"),
+        FileOptions::default()
+            .compression_level(Some(6))
+            .compression_method(zip::CompressionMethod::Deflated),
+    )
+    .map_err(|e| ForensicError::Other(format!("{:?}", e)))?;
+    println!("Creating file {}", path);
+    Ok(())
 }
 
-impl PathPatternIterator for PathPattern {}
+/// Writes a CSV with every file and directory of a raw file system, deleted entries included.
+/// Deleted directories are listed but not walked because their clusters may have been reused.
+pub fn write_file_listing<W: Write>(fs: &mut dyn RawFileSystem, root: &str, out: &mut W) -> ForensicResult<()> {
+    writeln!(out, "path,size,is_directory,deleted,created,modified,accessed")?;
+    let mut pending = vec![root.trim_end_matches('\\').to_string()];
+    while let Some(directory) = pending.pop() {
+        let entries = match fs.list_dir(&directory) {
+            Ok(v) => v,
+            Err(err) => {
+                println!("Error listing {}: {:?}", directory, err);
+                continue;
+            }
+        };
+        for entry in entries {
+            let path = format!(r"{}\{}", directory, entry.name);
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                csv_field(&path),
+                entry.size,
+                entry.is_directory,
+                entry.deleted,
+                entry.created,
+                entry.modified,
+                entry.accessed
+            )?;
+            if entry.is_directory && !entry.deleted {
+                pending.push(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the result of a dry run as a CSV with the path, the archive path and the size of each file
+pub fn write_planned_files_csv<W: Write>(files: &[PlannedFile], out: &mut W) -> ForensicResult<()> {
+    writeln!(out, "path,archive_path,size")?;
+    for file in files {
+        let size = file.size.map(|v| v.to_string()).unwrap_or_default();
+        writeln!(out, "{},{},{}", csv_field(&file.path), csv_field(&file.archive_path), size)?;
+    }
+    Ok(())
+}
+
+/// Writes the result of a dry run as a JSON array of
+/// `{"path": ..., "archive_path": ..., "size": ...}` objects
+pub fn write_planned_files_json<W: Write>(files: &[PlannedFile], out: &mut W) -> ForensicResult<()> {
+    write!(out, "[")?;
+    for (position, file) in files.iter().enumerate() {
+        if position > 0 {
+            write!(out, ",")?;
+        }
+        let size = file.size.map(|v| v.to_string()).unwrap_or_else(|| "null".to_string());
+        write!(
+            out,
+            "\n  {{\"path\": {}, \"archive_path\": {}, \"size\": {}}}",
+            json_string(&file.path),
+            json_string(&file.archive_path),
+            size
+        )?;
+    }
+    writeln!(out, "\n]")?;
+    Ok(())
+}
 
-impl Iterator for PathPattern {
+/// Writes a CSV with the SID, the account and the home folder of each profile, with the folder
+/// where the files of the profile are stored in the archive
+pub fn write_user_profiles_csv<W: Write>(profiles: &[UserProfile], out: &mut W) -> ForensicResult<()> {
+    writeln!(out, "sid,username,home,archive_path,flags,state,last_load,last_unload,temporary,backup")?;
+    for profile in profiles {
+        let filetime = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            csv_field(profile.sid.as_deref().unwrap_or_default()),
+            csv_field(&profile.username),
+            csv_field(&profile.home),
+            csv_field(&archive_entry(None, Some(profile), &profile.home)),
+            profile.flags,
+            profile.state,
+            filetime(profile.last_load),
+            filetime(profile.last_unload),
+            profile.is_temporary(),
+            profile.backup
+        )?;
+    }
+    Ok(())
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Folders below the start of a pattern walked by default
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// Expands a glob into the files it matches, listing folders through the collection backend.
+/// See the `glob` module for the syntax. Folders are walked with a stack, and the entries of each
+/// folder in name order so the files are always returned in the same order.
+pub struct PathPattern<'a> {
+    backend: &'a dyn CollectionBackend,
+    glob: Glob,
+    /// Case table of the volume of each alternative
+    upcase: Vec<UpCase>,
+    exclusions: &'a [Glob],
+    filter: FileFilter,
+    max_depth: usize,
+    pending: Vec<PendingFolder>,
+    matched: Vec<String>,
+}
+
+/// Folder still to be listed by a `PathPattern`
+struct PendingFolder {
+    path: String,
+    /// Alternative of the glob being walked
+    alternative: usize,
+    /// Segment the entries of the folder must match
+    segment: usize,
+    /// Folders matched so far by the current `**`
+    depth: usize,
+    /// Folders below the start of the pattern
+    level: usize,
+}
+
+impl<'a> PathPattern<'a> {
+    pub fn new(backend: &'a dyn CollectionBackend, pattern: &str) -> ForensicResult<Self> {
+        let glob = Glob::new(pattern)?;
+        let upcase = glob.paths.iter().map(|v| backend.upcase(&v.base)).collect();
+        let pending = glob
+            .paths
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(alternative, path)| PendingFolder {
+                path: path.base.clone(),
+                alternative,
+                segment: 0,
+                depth: 0,
+                level: 0,
+            })
+            .collect();
+        Ok(Self {
+            backend,
+            glob,
+            upcase,
+            exclusions: &[],
+            filter: FileFilter::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            pending,
+            matched: Vec::new(),
+        })
+    }
+
+    /// Skips the files matched by any of the `exclusions`, without walking the folders they exclude
+    pub fn excluding(mut self, exclusions: &'a [Glob]) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    /// Only returns the files accepted by `filter`. Files are opened to check their signatures.
+    pub fn filtered(mut self, filter: FileFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Walks at most `max_depth` folders below the start of the pattern
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    fn is_excluded(&self, entry: &RawDirEntry, path: &str, alternative: usize) -> bool {
+        let upcase = &self.upcase[alternative];
+        !self.filter.matches(entry, upcase)
+            || self.exclusions.iter().any(|v| v.is_match(path, upcase))
+            || !matches_content(self.backend, path, &self.filter)
+    }
+
+    fn is_folder_excluded(&self, path: &str, alternative: usize) -> bool {
+        let upcase = &self.upcase[alternative];
+        self.exclusions.iter().any(|v| v.matches_all_below(path, upcase))
+    }
+}
+
+impl<'a> Iterator for PathPattern<'a> {
     type Item = String;
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(pth_pattrn) = &mut self.path_pattern {
-                let nxt = pth_pattrn.next();
-                if let Some(nxt_elmnt) = nxt {
-                    return Some(nxt_elmnt);
-                }
+            if let Some(path) = self.matched.pop() {
+                return Some(path);
             }
-            self.path_pattern = None;
-            if let Some(readdir) = &mut self.fs_iter {
-                match readdir.next() {
-                    Some(v) => match v {
-                        Ok(dir_entry) => match dir_entry.file_type() {
-                            Ok(v) => {
-                                if v.is_dir() {
-                                    match PathPattern::with_base(dir_entry.path(), self.last_pattern.clone()) {
-                                        Ok(v) => {
-                                            self.path_pattern = Some(Box::new(v));
-                                        }
-                                        Err(err) => {
-                                            println!("Error creating base pattern: {:?} {} {:?}", dir_entry.path(), self.pattern, err);
-                                        }
-                                    };
-                                    continue;
-                                } else if v.is_file() {
-                                    let pth = dir_entry.path().to_str().unwrap_or("").to_string();
-                                    if pth.is_empty() {
-                                        continue;
-                                    }
-                                    match Regex::new(&replace_pattern(&self.last_pattern)) {
-                                        Ok(v) => {
-                                            if v.find(&pth).is_some() {
-                                                return Some(pth);
-                                            }
-                                        }
-                                        Err(_) => {}
-                                    }
-                                    
-                                }
+            let folder = self.pending.pop()?;
+            let segments = &self.glob.paths[folder.alternative].segments;
+            if folder.segment >= segments.len() {
+                continue;
+            }
+            let mut entries = match self.backend.read_dir(&folder.path) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            // Both stacks return the last entry first
+            entries.sort_by(|a, b| b.name.cmp(&a.name));
+            let last = folder.segment + 1 == segments.len();
+            let deeper = folder.level < self.max_depth;
+            match &segments[folder.segment] {
+                Segment::Recursive(max_depth) => {
+                    // `**` also matches zero folders
+                    if !last {
+                        self.pending.push(PendingFolder {
+                            path: folder.path.clone(),
+                            segment: folder.segment + 1,
+                            depth: 0,
+                            ..folder
+                        });
+                    }
+                    let descend = deeper && max_depth.map(|v| folder.depth < v).unwrap_or(true);
+                    for entry in entries {
+                        let path = join_path(&folder.path, &entry.name);
+                        if entry.is_directory {
+                            if descend && !self.is_folder_excluded(&path, folder.alternative) {
+                                self.pending.push(PendingFolder {
+                                    path,
+                                    depth: folder.depth + 1,
+                                    level: folder.level + 1,
+                                    ..folder
+                                });
                             }
-                            Err(_) => {}
-                        },
-                        Err(_) => {}
-                    },
-                    None => {
-                        break;
+                        } else if last && !self.is_excluded(&entry, &path, folder.alternative) {
+                            self.matched.push(path);
+                        }
                     }
                 }
-            }
-        }
-        if self.base_path.ancestors().next().is_none() {
-            return None;
-        }
-        let mut new_str = PathBuf::new();
-        std::mem::swap(&mut self.base_path, &mut new_str);
-        let pth = new_str.to_str().unwrap_or("").to_string();
-        if pth.trim().is_empty() {
-            return None
-        }
-        match Regex::new(&replace_pattern(&self.last_pattern)) {
-            Ok(v) => {
-                if v.find(&pth).is_some() {
-                    return Some(pth);
+                Segment::Name(name) => {
+                    for entry in entries {
+                        if !name.matches(&entry.name, &self.upcase[folder.alternative]) {
+                            continue;
+                        }
+                        let path = join_path(&folder.path, &entry.name);
+                        if !last && entry.is_directory {
+                            if deeper && !self.is_folder_excluded(&path, folder.alternative) {
+                                self.pending.push(PendingFolder {
+                                    path,
+                                    segment: folder.segment + 1,
+                                    depth: 0,
+                                    level: folder.level + 1,
+                                    ..folder
+                                });
+                            }
+                        } else if last && !entry.is_directory && !self.is_excluded(&entry, &path, folder.alternative) {
+                            self.matched.push(path);
+                        }
+                    }
                 }
             }
-            Err(_) => {}
         }
-        None
     }
 }
 
-pub fn replace_pattern(ptrn : &str) -> String {
-    format!("^{}$",ptrn.replace(".", "\\.").replace("*", ".*").replace(".*.*",".*"))
+/// Uses `\\` as separator and removes the empty components: `C:/Windows\\\\System32\\` is
+/// `C:\\Windows\\System32`
+fn canonical_path(path: &str) -> String {
+    let mut canonical = String::with_capacity(path.len());
+    for component in path.split(['\\', '/']).filter(|v| !v.is_empty()) {
+        if !canonical.is_empty() {
+            canonical.push('\\');
+        }
+        canonical.push_str(component);
+    }
+    if canonical.ends_with(':') {
+        canonical.push('\\');
+    }
+    canonical
+}
+
+/// Splits a path into its folder and its name: `C:\\a.txt` into `C:\\` and `a.txt`
+fn split_parent(path: &str) -> Option<(String, &str)> {
+    match path.rsplit_once('\\') {
+        Some((folder, name)) if folder.ends_with(':') => Some((format!("{}\\", folder), name)),
+        Some((folder, name)) => Some((folder.to_string(), name)),
+        None => None,
+    }
+}
+
+fn join_path(directory: &str, name: &str) -> String {
+    if directory.ends_with('\\') {
+        format!("{}{}", directory, name)
+    } else {
+        format!(r"{}\{}", directory, name)
+    }
 }
 
+#[cfg(test)]
+use crate::helpers::test_tree::TestTree;
+
+#[cfg(windows)]
 #[test]
 fn test_collector() {
     let out_file = std::env::temp_dir().join("triage-test.zip").as_os_str().to_string_lossy().into_owned();
@@ -513,29 +1359,1009 @@ fn test_collector() {
         all_disks_mft: false,
         usn_jrnl: false,
         all_usn_jrnl: false,
+        shadow_copies: false,
+        removable_media_listing: false,
         paths: get_default_collection_paths(),
         out_file,
         threads: 4,
         buffer_size: 1_000_000,
+        queue_size: 10_000,
+        max_depth: DEFAULT_MAX_DEPTH,
+        all_installations: false,
     });
     collector.collect().expect("Should generate ZIP file");
 }
 
 #[test]
-fn pattern_should_print_all() {
-    let pattern = EndPathPattern::new(format!(r"C:\Windows\Tasks\**")).unwrap();
-    for element in pattern.into_iter() {
-        println!("{}", element);
+fn should_collect_files_from_shadow_copies() {
+    use std::io::Cursor;
+    let mut volume = crate::ntfs::test_image::build(&[(r"\Windows\test.txt", &[b'N'; 5000])]);
+    let data_offset = {
+        let mut ntfs = crate::ntfs::NtfsVolume::new(Cursor::new(volume.clone())).unwrap();
+        let record = ntfs.find(r"\Windows\test.txt").unwrap();
+        let record = ntfs.record(record).unwrap();
+        match &record.attribute(crate::ntfs::ATTR_DATA, "").unwrap().content {
+            crate::ntfs::AttributeContent::NonResident { runs, .. } => runs[0].lcn.unwrap() * ntfs.cluster_size(),
+            _ => panic!("Test file should be non-resident"),
+        }
+    };
+    crate::vss::tst::add_snapshot(&mut volume, data_offset, &[b'O'; 0x4000]);
+
+    let tree = TestTree::new("vss", &[]);
+    let out_file = tree.path("out.zip");
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            shadow_copies: true,
+            paths: vec![r"C:\Windows\test.txt".to_string()],
+            out_file: out_file.to_string_lossy().into_owned(),
+            ..Default::default()
+        },
+        NtfsBackend::new('c', move || Ok(Cursor::new(volume.clone()))).unwrap(),
+    );
+    collector.collect().unwrap();
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+    let mut content = Vec::new();
+    archive.by_name(r"C\Windows\test.txt").unwrap().read_to_end(&mut content).unwrap();
+    assert_eq!(vec![b'N'; 5000], content);
+    content.clear();
+    archive
+        .by_name(r"vss\22222222-2222-2222-2222-222222222222\C\Windows\test.txt")
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    assert_eq!(vec![b'O'; 5000], content);
+}
+
+#[test]
+fn should_collect_files_deleted_since_a_shadow_copy() {
+    use std::io::Cursor;
+    let snapshot = crate::ntfs::test_image::build(&[
+        (r"\Windows\Logs\kept.log", b"kept"),
+        (r"\Windows\Logs\wiped.log", b"old events"),
+    ]);
+    let mut volume = crate::ntfs::test_image::build(&[(r"\Windows\Logs\kept.log", b"kept")]);
+    // Only the block with the records of the folder and its files changed since the snapshot
+    let changed: Vec<usize> = (0..volume.len())
+        .step_by(0x4000)
+        .filter(|v| snapshot[*v..*v + 0x4000] != volume[*v..*v + 0x4000])
+        .collect();
+    assert_eq!(vec![0x8000], changed);
+    crate::vss::tst::add_snapshot(&mut volume, 0x8000, &snapshot[0x8000..0xC000]);
+
+    let tree = TestTree::new("vss-deleted", &[]);
+    let out_file = tree.path("out.zip");
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            shadow_copies: true,
+            paths: vec![r"C:\Windows\Logs\*.log".to_string()],
+            out_file: out_file.to_string_lossy().into_owned(),
+            ..Default::default()
+        },
+        NtfsBackend::new('c', move || Ok(Cursor::new(volume.clone()))).unwrap(),
+    );
+    collector.collect().unwrap();
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+    let mut names: Vec<String> = archive.file_names().filter(|v| v.ends_with(".log")).map(String::from).collect();
+    names.sort();
+    assert_eq!(
+        vec![
+            r"C\Windows\Logs\kept.log".to_string(),
+            r"vss\22222222-2222-2222-2222-222222222222\C\Windows\Logs\kept.log".to_string(),
+            r"vss\22222222-2222-2222-2222-222222222222\C\Windows\Logs\wiped.log".to_string(),
+        ],
+        names
+    );
+    let planned: Vec<(String, Option<u64>)> = collector.dry_run().into_iter().map(|v| (v.archive_path, v.size)).collect();
+    assert_eq!(
+        vec![
+            (r"C\Windows\Logs\kept.log".to_string(), Some(4)),
+            (r"vss\22222222-2222-2222-2222-222222222222\C\Windows\Logs\kept.log".to_string(), Some(4)),
+            (r"vss\22222222-2222-2222-2222-222222222222\C\Windows\Logs\wiped.log".to_string(), Some(10)),
+        ],
+        planned
+    );
+    let mut content = String::new();
+    archive
+        .by_name(r"vss\22222222-2222-2222-2222-222222222222\C\Windows\Logs\wiped.log")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!("old events", content);
+}
+
+#[test]
+fn should_list_files_of_fat_volumes() {
+    let image = crate::fat::tst::fat16_image();
+    let mut fs = crate::volume::open_raw_file_system(std::io::Cursor::new(image)).unwrap();
+    assert_eq!("FAT16", fs.fs_name());
+    let mut listing = Vec::new();
+    write_file_listing(fs.as_mut(), r"E:\", &mut listing).unwrap();
+    let listing = String::from_utf8(listing).unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(4, lines.len());
+    assert!(lines[1].starts_with(r"E:\DCIM,0,true,false,"));
+    assert!(lines[2].starts_with(r"E:\_ECRET.TXT,11,false,true,"));
+    assert!(lines[3].starts_with(r"E:\DCIM\Holiday 2023.jpg,3000,false,false,"));
+}
+
+#[test]
+fn should_collect_from_mounted_directory() {
+    let tree = TestTree::new("mounted", &[]);
+    tree.write(r"WINDOWS\system32\Config\system", b"SYSTEM hive");
+    tree.write(r"users\Alice\ntuser.dat", &[b'U'; 3000]);
+    let out_file = tree.path("out.zip");
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%SYSTEMROOT%\System32\config\SYSTEM".to_string(),
+                r"%SYSTEMROOT%\System32\config\SAM".to_string(),
+                r"%USERHOME%\NTUSER.DAT".to_string(),
+            ],
+            out_file: out_file.to_string_lossy().into_owned(),
+            threads: 2,
+            buffer_size: 1024,
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    collector.collect().unwrap();
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+    let mut content = Vec::new();
+    archive
+        .by_name(r"C\Windows\System32\config\SYSTEM")
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    assert_eq!(b"SYSTEM hive".to_vec(), content);
+    content.clear();
+    archive
+        .by_name(r"C\Users\Alice\NTUSER.DAT")
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    assert_eq!(vec![b'U'; 3000], content);
+    assert!(archive.by_name(r"C\Windows\System32\config\SAM").is_err());
+}
+
+#[test]
+fn should_expand_default_patterns() {
+    let files = [
+        r"Windows\Tasks\Backup.job",
+        r"Windows\Prefetch\CMD.EXE-0BD30981.pf",
+        r"Windows\System32\winevt\Logs\Security.evtx",
+        r"Windows\System32\Tasks\Microsoft\Windows\Defrag\ScheduledDefrag",
+        r"Windows\System32\config\SYSTEM",
+        r"$Recycle.Bin\S-1-5-21-1000\$IR5A0B1.txt",
+        r"$Recycle.Bin\S-1-5-21-1000\$RR5A0B1.txt",
+        r"$Recycle.Bin\$I123456",
+        r"Users\Alice\AppData\Roaming\Microsoft\Windows\Recent\report.lnk",
+        r"Users\Alice\AppData\Roaming\Mozilla\Firefox\Profiles\x1.default\places.sqlite",
+        r"Users\Bob\AppData\Local\Microsoft\Windows\WebCache\WebCacheV01.dat",
+    ];
+    let tree = TestTree::new("patterns", &files);
+
+    let collector = TriageCollector::from_mount_point(CollectionParameters::default(), tree.root());
+    let paths = collector.prepare_paths_to_collect();
+    for file in files {
+        let path = format!(r"C:\{}", file);
+        if file.contains(r"\$R") {
+            assert!(!paths.contains(&path), "{} should not be collected", path);
+        } else {
+            assert!(paths.contains(&path), "{} should be collected", path);
+        }
     }
+    assert!(paths.iter().all(|v| !v.contains('*')));
+}
+
+#[test]
+fn should_expand_globs_with_alternatives_and_depth() {
+    let tree = TestTree::new(
+        "globs",
+        &[
+            r"Windows\Tasks\Backup.job",
+            r"Windows\Prefetch\CMD.EXE-0BD30981.pf",
+            r"Windows\Prefetch\Layout.ini",
+            r"Windows\System32\Tasks\Updater",
+            r"Windows\System32\Tasks\Microsoft\Windows\Defrag\ScheduledDefrag",
+        ],
+    );
+    let backend = LogicalBackend::new(tree.root());
 
-    
+    let mut paths: Vec<String> = PathPattern::new(&backend, r"C:\Windows\{Tasks\*.job,Prefetch\*.p?}")
+        .unwrap()
+        .collect();
+    paths.sort();
+    assert_eq!(
+        vec![
+            r"C:\Windows\Prefetch\CMD.EXE-0BD30981.pf".to_string(),
+            r"C:\Windows\Tasks\Backup.job".to_string(),
+        ],
+        paths
+    );
+    let paths: Vec<String> = PathPattern::new(&backend, r"C:\Windows\System32\Tasks\**1").unwrap().collect();
+    assert_eq!(vec![r"C:\Windows\System32\Tasks\Updater".to_string()], paths);
+    assert_eq!(2, PathPattern::new(&backend, r"C:\Windows\System32\Tasks\**").unwrap().count());
+}
 
-    let pattern = PathPattern::new(format!(r"C:\Windows\**\*.exe")).unwrap();
-    for element in pattern.into_iter() {
-        println!("{}", element);
+#[test]
+fn should_expand_patterns_on_mixed_case_trees() {
+    let tree = TestTree::new(
+        "case",
+        &[
+            r"windows\SYSTEM32\LogFiles\w3svc1\u_ex240101.log",
+            r"windows\SYSTEM32\winevt\Logs\Security.evtx",
+            r"USERS\alice\ntuser.dat",
+        ],
+    );
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%SYSTEMROOT%\System32\Logfiles\W3SVC1\**".to_string(),
+                r"%SYSTEMROOT%\system32\WINEVT\logs\*.EVTX".to_string(),
+                r"%USERHOME%\NTUser.DAT".to_string(),
+            ],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    let paths = collector.prepare_paths_to_collect();
+    assert_eq!(
+        vec![
+            r"C:\Users\alice\NTUser.DAT".to_string(),
+            r"C:\Windows\System32\Logfiles\W3SVC1\u_ex240101.log".to_string(),
+            r"C:\Windows\system32\WINEVT\logs\Security.evtx".to_string(),
+        ],
+        paths
+    );
+    assert_eq!(4, collector.backend.open(&paths[2]).unwrap().size);
+}
+
+#[test]
+fn should_prune_excluded_folders() {
+    struct ListingRecorder {
+        inner: LogicalBackend,
+        listed: Mutex<Vec<String>>,
+    }
+    impl VolumeSource for ListingRecorder {
+        fn volumes(&self) -> Vec<String> {
+            self.inner.volumes()
+        }
     }
-    let pattern = EndPathPattern::new(format!(r"C:\Windows\**")).unwrap();
-    for element in pattern.into_iter() {
-        println!("{}", element);
+    impl CollectionBackend for ListingRecorder {
+        fn open(&self, path: &str) -> ForensicResult<crate::volume::OpenedFile> {
+            self.inner.open(path)
+        }
+        fn read_dir(&self, path: &str) -> ForensicResult<Vec<crate::volume::RawDirEntry>> {
+            self.listed.lock().unwrap().push(path.to_string());
+            self.inner.read_dir(path)
+        }
     }
+
+    let tree = TestTree::new("exclusions", &[]);
+    let profile = r"Users\Alice\AppData\Roaming\Mozilla\Firefox\Profiles\x1.default";
+    for file in [r"places.sqlite", r"cache2\entries\0A1B2C", r"cache2\index", r"sessionstore.bak"] {
+        tree.write(&format!(r"{}\{}", profile, file), b"data");
+    }
+    let backend = ListingRecorder {
+        inner: LogicalBackend::new(tree.root()),
+        listed: Mutex::new(Vec::new()),
+    };
+    let exclusions = vec![Glob::new(r"**\cache2\**").unwrap(), Glob::new(r"**\*.bak").unwrap()];
+    let paths: Vec<String> = PathPattern::new(&backend, r"C:\Users\*\AppData\Roaming\Mozilla\Firefox\Profiles\**")
+        .unwrap()
+        .excluding(&exclusions)
+        .collect();
+    assert_eq!(vec![format!(r"C:\{}\places.sqlite", profile)], paths);
+    assert!(backend.listed.lock().unwrap().iter().all(|v| !v.contains("cache2")));
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%USERHOME%\AppData\Roaming\Mozilla\Firefox\Profiles\**".to_string(),
+                r"!%USERHOME%\AppData\Roaming\Mozilla\Firefox\Profiles\*\cache2\**".to_string(),
+            ],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    let mut paths = collector.prepare_paths_to_collect();
+    paths.sort();
+    assert_eq!(
+        vec![
+            format!(r"C:\{}\places.sqlite", profile),
+            format!(r"C:\{}\sessionstore.bak", profile),
+        ],
+        paths
+    );
+}
+
+#[test]
+fn should_filter_targets_by_metadata() {
+    let tree = TestTree::new("filters", &[]);
+    for (name, size) in [("run.ps1", 10), ("old.bat", 10), ("big.vbs", 5000), ("notes.txt", 10)] {
+        tree.write(&format!(r"Users\Alice\Desktop\{}", name), &vec![b'x'; size]);
+    }
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60 * 86_400);
+    std::fs::File::options()
+        .write(true)
+        .open(tree.path(r"Users\Alice\Desktop\old.bat"))
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%SYSTEMDRIVE%\Users\**|ext=*.ps1,*.bat,*.vbs;modified>30d;size<1KB".to_string(),
+                r"C:\Users\Alice\Desktop\notes.txt|size>100".to_string(),
+                r"C:\Users\Alice\Desktop\big.vbs|size>=5000".to_string(),
+                r"C:\Users\**|modified>soon".to_string(),
+            ],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    let paths = collector.prepare_paths_to_collect();
+    assert_eq!(
+        vec![
+            r"C:\Users\Alice\Desktop\big.vbs".to_string(),
+            r"C:\Users\Alice\Desktop\run.ps1".to_string(),
+        ],
+        paths
+    );
+}
+
+#[test]
+fn should_filter_targets_by_signature() {
+    let tree = TestTree::new("signatures", &[]);
+    let mut pe = vec![0; 0x100];
+    pe[..2].copy_from_slice(b"MZ");
+    pe[0x3C] = 0x80;
+    pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+    tree.write(r"ProgramData\Updater\update.dat", &pe);
+    tree.write(r"ProgramData\Updater\readme.exe", b"Not an executable");
+    tree.write(r"ProgramData\Updater\huge.bin", &[pe.clone(), vec![0; 4096]].concat());
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%PROGRAMDATA%\**|type=pe;size<4KB".to_string(),
+                r"C:\ProgramData\Updater\readme.exe|type=pe".to_string(),
+            ],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    let paths = collector.prepare_paths_to_collect();
+    assert_eq!(vec![r"C:\ProgramData\Updater\update.dat".to_string()], paths);
+}
+
+#[test]
+fn should_deduplicate_and_sort_expanded_paths() {
+    let tree = TestTree::new(
+        "dedup",
+        &[r"$Recycle.Bin\$I123456", r"$Recycle.Bin\S-1-5-21-1000\$IR5A0B1.txt", "b.txt"],
+    );
+    #[cfg(unix)]
+    std::fs::hard_link(tree.path("b.txt"), tree.path("c.txt")).unwrap();
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"C:\b.txt".to_string(),
+                r"%SYSTEMDRIVE%\$Recycle.Bin\**\$I*".to_string(),
+                r"%SYSTEMDRIVE%\$Recycle.Bin\$I*".to_string(),
+                r"C:/$RECYCLE.BIN\\$i123456".to_string(),
+                r"C:\c.txt".to_string(),
+            ],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    let paths = collector.prepare_paths_to_collect();
+    assert_eq!(
+        vec![
+            r"C:\$Recycle.Bin\$I123456".to_string(),
+            r"C:\$Recycle.Bin\S-1-5-21-1000\$IR5A0B1.txt".to_string(),
+            r"C:\b.txt".to_string(),
+        ],
+        paths
+    );
+    assert_eq!(r"C:\$Extend\$UsnJrnl:$J", canonical_path(r"C:\\$Extend\$UsnJrnl:$J"));
+    assert_eq!(r"D:\", canonical_path(r"D:\"));
+}
+
+#[test]
+fn should_write_the_same_archive_with_several_threads() {
+    let tree = TestTree::new("reproducible", &[]);
+    for folder in ["Logs", r"Logs\old", "Temp"] {
+        for file in 0..30 {
+            tree.write(&format!(r"c\{}\file{:02}.log", folder, file), &vec![b'L'; file * 100]);
+        }
+    }
+    let entries = |threads: usize, name: &str| {
+        let out_file = tree.path(name);
+        let params = CollectionParameters {
+            paths: vec![r"C:\Logs\**".to_string(), r"C:\*\*.log".to_string(), r"C:\Logs\file0*.log".to_string()],
+            out_file: out_file.to_string_lossy().into_owned(),
+            threads,
+            queue_size: 2,
+            ..Default::default()
+        };
+        TriageCollector::from_mount_point(params, tree.path("c")).collect().unwrap();
+        assert!(!tree.path(&format!("{}.partial", name)).exists());
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+        (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect::<Vec<String>>()
+    };
+    let first = entries(4, "first.zip");
+    assert_eq!(first, entries(4, "second.zip"));
+    assert_eq!(first, entries(1, "single.zip"));
+    assert_eq!(90, first.iter().filter(|v| v.ends_with(".log")).count());
+    let mut sorted = first.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted, first);
+}
+
+#[test]
+fn should_list_planned_files_without_collecting() {
+    let tree = TestTree::new("dry-run", &["notes \"1\".txt"]);
+    tree.write(r"Windows\System32\config\SYSTEM", &[0; 3000]);
+    tree.write(r"Windows\System32\config\SAM", &[0; 100]);
+    let out_file = tree.path("out.zip");
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%SYSTEMROOT%\System32\config\*".to_string(),
+                r"C:\notes*".to_string(),
+                r"C:\$Extend\$UsnJrnl:$J".to_string(),
+            ],
+            out_file: out_file.to_string_lossy().into_owned(),
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    let files = collector.dry_run();
+    assert!(!out_file.exists());
+    assert_eq!(
+        vec![
+            PlannedFile {
+                path: r"C:\$Extend\$UsnJrnl:$J".to_string(),
+                archive_path: r"C\$Extend\$UsnJrnl:$J".to_string(),
+                size: None
+            },
+            PlannedFile {
+                path: "C:\\notes \"1\".txt".to_string(),
+                archive_path: "C\\notes \"1\".txt".to_string(),
+                size: Some(4)
+            },
+            PlannedFile {
+                path: r"C:\Windows\System32\config\SAM".to_string(),
+                archive_path: r"C\Windows\System32\config\SAM".to_string(),
+                size: Some(100)
+            },
+            PlannedFile {
+                path: r"C:\Windows\System32\config\SYSTEM".to_string(),
+                archive_path: r"C\Windows\System32\config\SYSTEM".to_string(),
+                size: Some(3000)
+            },
+        ],
+        files
+    );
+
+    let mut csv = Vec::new();
+    write_planned_files_csv(&files[..2], &mut csv).unwrap();
+    assert_eq!(
+        "path,archive_path,size\nC:\\$Extend\\$UsnJrnl:$J,C\\$Extend\\$UsnJrnl:$J,\n\"C:\\notes \"\"1\"\".txt\",\"C\\notes \"\"1\"\".txt\",4\n",
+        String::from_utf8(csv).unwrap()
+    );
+    let mut json = Vec::new();
+    write_planned_files_json(&files[..2], &mut json).unwrap();
+    assert_eq!(
+        "[\n  {\"path\": \"C:\\\\$Extend\\\\$UsnJrnl:$J\", \"archive_path\": \"C\\\\$Extend\\\\$UsnJrnl:$J\", \"size\": null},\n  {\"path\": \"C:\\\\notes \\\"1\\\".txt\", \"archive_path\": \"C\\\\notes \\\"1\\\".txt\", \"size\": 4}\n]\n",
+        String::from_utf8(json).unwrap()
+    );
+}
+
+#[test]
+fn should_plan_the_listing_of_removable_media() {
+    struct UsbBackend;
+    impl VolumeSource for UsbBackend {
+        fn volumes(&self) -> Vec<String> {
+            vec![r"C:\".to_string(), r"E:\".to_string()]
+        }
+    }
+    impl CollectionBackend for UsbBackend {
+        fn open(&self, path: &str) -> ForensicResult<crate::volume::OpenedFile> {
+            Err(ForensicError::missing_string(path.to_string()))
+        }
+        fn read_dir(&self, _path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+            Ok(Vec::new())
+        }
+        fn open_volume(&self, path: &str) -> ForensicResult<Box<dyn crate::volume::VolumeReader>> {
+            match path {
+                r"E:\" => Ok(Box::new(std::io::Cursor::new(crate::fat::tst::fat16_image()))),
+                _ => Err(ForensicError::missing_string(path.to_string())),
+            }
+        }
+    }
+
+    let tree = TestTree::new("removable", &[]);
+    let out_file = tree.path("out.zip");
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            paths: Vec::new(),
+            removable_media_listing: true,
+            out_file: out_file.to_string_lossy().into_owned(),
+            ..Default::default()
+        },
+        UsbBackend,
+    );
+    assert_eq!(
+        vec![PlannedFile {
+            path: String::new(),
+            archive_path: r"E\file_listing.csv".to_string(),
+            size: None
+        }],
+        collector.dry_run()
+    );
+    collector.collect().unwrap();
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+    assert!(archive.by_name(r"E\file_listing.csv").is_ok());
+}
+
+#[test]
+fn should_expand_patterns_from_ntfs_indexes() {
+    let image = crate::ntfs::test_image::build(&[
+        (r"\System Volume Information\Syscache.hve", b"regf"),
+        (r"\Users\Alice\AppData\Local\Temp\a.ps1", b"Write-Host"),
+        (r"\Users\Bob\AppData\Local\Temp\b.ps1", b"Write-Host"),
+        (r"\Users\Bob\AppData\Local\Temp\b.txt", b"text"),
+    ]);
+    let backend = crate::backend::NtfsBackend::new('C', move || Ok(std::io::Cursor::new(image.clone()))).unwrap();
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            paths: vec![
+                r"C:\System Volume Information\*.hve".to_string(),
+                r"%USERHOME%\AppData\Local\Temp\*.ps1".to_string(),
+            ],
+            ..Default::default()
+        },
+        backend,
+    );
+    assert_eq!(
+        vec![
+            r"C:\System Volume Information\Syscache.hve".to_string(),
+            r"C:\Users\Alice\AppData\Local\Temp\a.ps1".to_string(),
+            r"C:\Users\Bob\AppData\Local\Temp\b.ps1".to_string(),
+        ],
+        collector.prepare_paths_to_collect()
+    );
+}
+
+#[test]
+fn should_stream_expanded_paths_to_workers() {
+    let tree = TestTree::new("streaming", &[]);
+    let mut folder = "Shares".to_string();
+    for level in 0..5 {
+        folder = format!(r"{}\level{}", folder, level);
+        for file in 0..20 {
+            tree.write(&format!(r"{}\file{:02}.txt", folder, file), format!("{}-{}", level, file).as_bytes());
+        }
+    }
+    let out_file = tree.path("out.zip");
+
+    let params = CollectionParameters {
+        paths: vec![r"C:\Shares\**".to_string()],
+        out_file: out_file.to_string_lossy().into_owned(),
+        threads: 3,
+        queue_size: 1,
+        ..Default::default()
+    };
+    TriageCollector::from_mount_point(params.clone(), tree.root()).collect().unwrap();
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+    let mut content = String::new();
+    archive
+        .by_name(r"C\Shares\level0\level1\level2\level3\level4\file19.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!("4-19", content);
+    assert_eq!(100, archive.file_names().filter(|v| v.ends_with(".txt")).count());
+
+    let backend = LogicalBackend::new(tree.root());
+    let paths: Vec<String> = PathPattern::new(&backend, r"C:\Shares\**").unwrap().with_max_depth(2).collect();
+    assert_eq!(40, paths.len());
+    assert_eq!(r"C:\Shares\level0\file00.txt", paths[0]);
+    assert_eq!(r"C:\Shares\level0\level1\file19.txt", paths[39]);
+    let collector = TriageCollector::from_mount_point(CollectionParameters { max_depth: 3, ..params }, tree.root());
+    assert_eq!(60, collector.prepare_paths_to_collect().len());
+}
+
+#[test]
+fn should_expand_user_variables_from_each_profile() {
+    let tree = TestTree::new(
+        "user-vars",
+        &[
+            r"Users\Alice\LocalData\Microsoft\Windows\WebCache\WebCacheV01.dat",
+            r"Users\Alice\AppData\Local\Microsoft\Windows\WebCache\Stale.dat",
+            r"Users\Bob\AppData\Local\Microsoft\Windows\WebCache\WebCacheV01.dat",
+        ],
+    );
+    let hive = crate::regf::tst::build(&[(
+        r"Software\Microsoft\Windows\CurrentVersion\Explorer\User Shell Folders",
+        "Local AppData",
+        forensic_rs::prelude::RegValue::ExpandSZ(r"%USERPROFILE%\LocalData".into()),
+    )]);
+    tree.write(r"Users\Alice\NTUSER.DAT", &hive);
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%LOCALAPPDATA%\Microsoft\Windows\WebCache\*.dat".to_string(),
+                r"%USERHOME%\NTUSER.DAT".to_string(),
+                r"%UNDEFINED%\file.txt".to_string(),
+            ],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    assert_eq!(
+        vec![
+            r"C:\Users\Alice\LocalData\Microsoft\Windows\WebCache\WebCacheV01.dat".to_string(),
+            r"C:\Users\Alice\NTUSER.DAT".to_string(),
+            r"C:\Users\Bob\AppData\Local\Microsoft\Windows\WebCache\WebCacheV01.dat".to_string(),
+            r"C:\Users\Bob\NTUSER.DAT".to_string(),
+        ],
+        collector.prepare_paths_to_collect()
+    );
+}
+
+#[test]
+fn should_record_the_profile_of_user_artifacts() {
+    use crate::environment::tst::TestRegistry;
+    use forensic_rs::prelude::{RegValue, RegistryReader};
+
+    struct RegistryBackend {
+        inner: LogicalBackend,
+        registry: TestRegistry,
+    }
+    impl VolumeSource for RegistryBackend {
+        fn volumes(&self) -> Vec<String> {
+            self.inner.volumes()
+        }
+    }
+    impl CollectionBackend for RegistryBackend {
+        fn open(&self, path: &str) -> ForensicResult<crate::volume::OpenedFile> {
+            self.inner.open(path)
+        }
+        fn read_dir(&self, path: &str) -> ForensicResult<Vec<RawDirEntry>> {
+            self.inner.read_dir(path)
+        }
+        fn registry(&self) -> Option<Box<dyn RegistryReader>> {
+            Some(Box::new(self.registry.clone()))
+        }
+    }
+
+    let tree = TestTree::new("profiles", &[]);
+    tree.write(r"Users\alice\NTUSER.DAT", b"regf");
+    let out_file = tree.path("out.zip").to_string_lossy().into_owned();
+
+    let profile_list = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList";
+    let alice = format!(r"{}\S-1-5-21-1-2-3-1001", profile_list);
+    let broken = format!(r"{}\S-1-5-21-1-2-3-1002", profile_list);
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            paths: vec![r"%USERHOME%\NTUSER.DAT".to_string()],
+            out_file: out_file.clone(),
+            ..Default::default()
+        },
+        RegistryBackend {
+            inner: LogicalBackend::new(tree.root()),
+            registry: TestRegistry::new(&[
+                (&alice, "ProfileImagePath", RegValue::ExpandSZ(r"%SystemDrive%\Users\alice".into())),
+                (&alice, "State", RegValue::DWord(0)),
+                (&broken, "State", RegValue::DWord(0)),
+            ]),
+        },
+    );
+    collector.collect().unwrap();
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+    assert!(archive.by_name(r"users\S-1-5-21-1-2-3-1001\C\Users\alice\NTUSER.DAT").is_ok());
+    let mut csv = String::new();
+    archive.by_name("user_profiles.csv").unwrap().read_to_string(&mut csv).unwrap();
+    assert_eq!(
+        "sid,username,home,archive_path,flags,state,last_load,last_unload,temporary,backup\n\
+         S-1-5-21-1-2-3-1001,alice,C:\\Users\\alice,users\\S-1-5-21-1-2-3-1001\\C\\Users\\alice,0,0,,,false,false\n",
+        csv
+    );
+}
+
+#[test]
+fn should_resolve_variables_from_the_hives_of_the_volume() {
+    use forensic_rs::prelude::RegValue;
+
+    let tree = TestTree::new("offline-registry", &[r"Logs\app.log", r"Old\app.log", r"Profiles\carol\NTUSER.DAT"]);
+    let environment = r"Control\Session Manager\Environment";
+    let system = crate::regf::tst::build(&[
+        ("Select", "Current", RegValue::DWord(2)),
+        (
+            &format!(r"ControlSet001\{}", environment),
+            "AppLogs",
+            RegValue::ExpandSZ(r"%SystemDrive%\Old".into()),
+        ),
+        (
+            &format!(r"ControlSet002\{}", environment),
+            "AppLogs",
+            RegValue::ExpandSZ(r"%SystemDrive%\Logs".into()),
+        ),
+    ]);
+    let software = crate::regf::tst::build(&[
+        (r"Microsoft\Windows NT\CurrentVersion", "SystemRoot", RegValue::SZ(r"C:\Windows".into())),
+        (
+            r"Microsoft\Windows NT\CurrentVersion\ProfileList\S-1-5-21-1-2-3-1003",
+            "ProfileImagePath",
+            RegValue::ExpandSZ(r"%SystemDrive%\Profiles\carol".into()),
+        ),
+    ]);
+    tree.write(r"Windows\System32\config\SYSTEM", &system);
+    tree.write(r"Windows\System32\config\SOFTWARE", &software);
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![r"%AppLogs%\*.log".to_string(), r"%USERHOME%\NTUSER.DAT".to_string()],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    assert_eq!(
+        vec![r"C:\Logs\app.log".to_string(), r"C:\Profiles\carol\NTUSER.DAT".to_string()],
+        collector.prepare_paths_to_collect()
+    );
+}
+
+#[test]
+fn should_use_the_given_registry_and_volumes() {
+    use crate::environment::tst::TestRegistry;
+    use forensic_rs::prelude::RegValue;
+
+    let tree = TestTree::new(
+        "injected",
+        &[
+            r"d\Windows\System32\config\SYSTEM",
+            r"d\Users\alice\AppData\Roaming\app\settings.json",
+            r"d\Users\bob\AppData\Roaming\app\state.json",
+        ],
+    );
+    let (drive_c, drive_d) = (tree.path("c"), tree.path("d"));
+    std::fs::create_dir_all(&drive_c).unwrap();
+
+    let profile_list = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\ProfileList";
+    let alice = format!(r"{}\S-1-5-21-1-2-3-1001", profile_list);
+    let bob = format!(r"{}\S-1-5-21-1-2-3-1002", profile_list);
+    let registry = TestRegistry::new(&[
+        (r"SOFTWARE\Microsoft\Windows NT\CurrentVersion", "SystemRoot", RegValue::SZ(r"D:\Windows".into())),
+        (&alice, "ProfileImagePath", RegValue::ExpandSZ(r"%SystemDrive%\Users\alice".into())),
+        (&bob, "ProfileImagePath", RegValue::SZ(r"D:\Users\bob".into())),
+    ]);
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            all_disks_mft: true,
+            paths: vec![
+                r"%SystemRoot%\System32\config\SYSTEM".to_string(),
+                r"%APPDATA%\app\*.json".to_string(),
+            ],
+            ..Default::default()
+        },
+        LogicalBackend::new(&drive_c).with_volume('D', &drive_d),
+    )
+    .with_registry(Box::new(registry))
+    .with_volumes(|| vec![r"C:\".to_string(), r"D:\".to_string(), r"E:\".to_string()]);
+    assert_eq!(
+        vec![
+            r"C:\$MFT".to_string(),
+            r"D:\$MFT".to_string(),
+            r"D:\Users\alice\AppData\Roaming\app\settings.json".to_string(),
+            r"D:\Users\bob\AppData\Roaming\app\state.json".to_string(),
+            r"D:\Windows\System32\config\SYSTEM".to_string(),
+            r"E:\$MFT".to_string(),
+        ],
+        collector.prepare_paths_to_collect()
+    );
+}
+
+#[test]
+fn should_collect_every_windows_installation() {
+    use forensic_rs::prelude::RegValue;
+
+    let software = |system_root: &str, profile: &str| {
+        crate::regf::tst::build(&[
+            (r"Microsoft\Windows NT\CurrentVersion", "SystemRoot", RegValue::SZ(system_root.into())),
+            (
+                r"Microsoft\Windows NT\CurrentVersion\ProfileList\S-1-5-21-1-2-3-1001",
+                "ProfileImagePath",
+                RegValue::ExpandSZ(profile.into()),
+            ),
+        ])
+    };
+    let system = crate::regf::tst::build(&[("Select", "Current", RegValue::DWord(1))]);
+    let tree = TestTree::new(
+        "installations",
+        &[
+            r"c\Users\alice\NTUSER.DAT",
+            r"c\Windows.old\Users\olduser\NTUSER.DAT",
+            r"d\Users\dave\NTUSER.DAT",
+            r"d\Program Files\readme.txt",
+        ],
+    );
+    for windows in [r"c\Windows", r"c\Windows.old\Windows", r"d\Windows"] {
+        tree.write(&format!(r"{}\System32\config\SYSTEM", windows), &system);
+    }
+    tree.write(r"c\Windows\System32\config\SOFTWARE", &software(r"C:\WINDOWS", r"%SystemDrive%\Users\alice"));
+    tree.write(r"c\Windows.old\Windows\System32\config\SOFTWARE", &software(r"C:\Windows", r"C:\Users\olduser"));
+    tree.write(r"d\Windows\System32\config\SOFTWARE", &software(r"C:\Windows", r"%SystemDrive%\Users\dave"));
+    let (drive_c, drive_d) = (tree.path("c"), tree.path("d"));
+    let out_file = tree.path("out.zip").to_string_lossy().into_owned();
+
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            all_installations: true,
+            paths: vec![
+                r"%SystemRoot%\System32\config\SOFTWARE".to_string(),
+                r"%USERHOME%\NTUSER.DAT".to_string(),
+                r"%ProgramFiles%\readme.txt".to_string(),
+            ],
+            out_file: out_file.clone(),
+            ..Default::default()
+        },
+        LogicalBackend::new(&drive_c).with_volume('D', &drive_d),
+    );
+    collector.collect().unwrap();
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&out_file).unwrap()).unwrap();
+    let mut files: Vec<String> = archive
+        .file_names()
+        .filter(|v| !v.ends_with('/') && !v.ends_with('\\'))
+        .map(|v| v.to_string())
+        .collect();
+    files.sort();
+    assert_eq!(
+        vec![
+            r"installations\C_Windows.old_Windows\C\Windows.old\Windows\System32\config\SOFTWARE",
+            r"installations\C_Windows.old_Windows\user_profiles.csv",
+            r"installations\C_Windows.old_Windows\users\S-1-5-21-1-2-3-1001\C\Windows.old\Users\olduser\NTUSER.DAT",
+            r"installations\C_Windows\C\WINDOWS\System32\config\SOFTWARE",
+            r"installations\C_Windows\user_profiles.csv",
+            r"installations\C_Windows\users\S-1-5-21-1-2-3-1001\C\Users\alice\NTUSER.DAT",
+            r"installations\D_Windows\D\Program Files\readme.txt",
+            r"installations\D_Windows\D\Windows\System32\config\SOFTWARE",
+            r"installations\D_Windows\user_profiles.csv",
+            r"installations\D_Windows\users\S-1-5-21-1-2-3-1001\D\Users\dave\NTUSER.DAT",
+        ],
+        files
+    );
+    // Targets without wildcards are planned even when missing, like `C:\Program Files\readme.txt`
+    let mut planned: Vec<String> = collector
+        .dry_run()
+        .into_iter()
+        .filter(|v| v.size.is_some() || v.path.is_empty())
+        .map(|v| v.archive_path)
+        .collect();
+    planned.sort();
+    assert_eq!(files, planned);
+    let mut csv = String::new();
+    archive
+        .by_name(r"installations\D_Windows\user_profiles.csv")
+        .unwrap()
+        .read_to_string(&mut csv)
+        .unwrap();
+    assert!(csv.contains(r"S-1-5-21-1-2-3-1001,dave,D:\Users\dave,users\S-1-5-21-1-2-3-1001\D\Users\dave,"));
+}
+
+#[test]
+fn should_map_previous_root_targets_onto_windows_old() {
+    use forensic_rs::prelude::RegValue;
+
+    let tree = TestTree::new("previous-root", &[r"Users\alice\NTUSER.DAT", r"Windows.old\Users\bob\NTUSER.DAT"]);
+    let software = |profile: &str| {
+        crate::regf::tst::build(&[
+            (r"Microsoft\Windows NT\CurrentVersion", "SystemRoot", RegValue::SZ(r"C:\Windows".into())),
+            (
+                r"Microsoft\Windows NT\CurrentVersion\ProfileList\S-1-5-21-1-2-3-1001",
+                "ProfileImagePath",
+                RegValue::ExpandSZ(profile.into()),
+            ),
+        ])
+    };
+    let system = crate::regf::tst::build(&[("Select", "Current", RegValue::DWord(1))]);
+    tree.write(r"Windows\System32\config\SYSTEM", &system);
+    tree.write(r"Windows\System32\config\SOFTWARE", &software(r"%SystemDrive%\Users\alice"));
+    tree.write(r"Windows.old\Windows\System32\config\SYSTEM", &system);
+    tree.write(r"Windows.old\Windows\System32\config\SOFTWARE", &software(r"C:\Users\bob"));
+    tree.write(r"Windows.old\Windows\System32\winevt\Logs\System.evtx", b"ElfFile");
+    tree.write(r"Windows\System32\winevt\Logs\System.evtx", b"ElfFile");
+
+    let collector = TriageCollector::from_mount_point(
+        CollectionParameters {
+            paths: vec![
+                r"%PREVIOUSROOT%\System32\winevt\Logs\*.evtx".to_string(),
+                r"%PREVIOUSUSERHOME%\NTUSER.DAT".to_string(),
+                r"%USERHOME%\NTUSER.DAT".to_string(),
+            ],
+            ..Default::default()
+        },
+        tree.root(),
+    );
+    assert_eq!(
+        vec![
+            r"C:\Users\alice\NTUSER.DAT".to_string(),
+            r"C:\Windows.old\Users\bob\NTUSER.DAT".to_string(),
+            r"C:\Windows.old\Windows\System32\winevt\Logs\System.evtx".to_string(),
+        ],
+        collector.prepare_paths_to_collect()
+    );
+}
+
+#[test]
+fn should_expand_drive_variables_by_drive_type() {
+    struct Drives;
+    impl VolumeSource for Drives {
+        fn volumes(&self) -> Vec<String> {
+            self.mounted_volumes().into_iter().map(|v| v.root).collect()
+        }
+        fn mounted_volumes(&self) -> Vec<MountedVolume> {
+            vec![
+                MountedVolume::new(r"C:\"),
+                MountedVolume::new(r"D:\").with_drive_type(DriveType::Removable).with_file_system("NTFS"),
+                MountedVolume::new(r"E:\").with_drive_type(DriveType::Optical).with_file_system("CDFS"),
+                MountedVolume::new(r"F:\").with_drive_type(DriveType::Removable).with_file_system("FAT32"),
+                MountedVolume::new(r"Z:\").with_drive_type(DriveType::Network),
+            ]
+        }
+    }
+
+    let tree = TestTree::new(
+        "all-drives",
+        &[
+            r"c\$Recycle.Bin\S-1-5-21-1001\$IABC.txt",
+            r"d\$Recycle.Bin\$IDEF.txt",
+            r"f\$Recycle.Bin\$IGHI.txt",
+        ],
+    );
+    let (drive_c, drive_d, drive_f) = (tree.path("c"), tree.path("d"), tree.path("f"));
+
+    let collector = TriageCollector::with_backend(
+        CollectionParameters {
+            usn_jrnl: true,
+            all_usn_jrnl: true,
+            paths: vec![
+                r"%ALLDRIVES%\$Recycle.Bin\**\$I*".to_string(),
+                r"%FIXEDDRIVES%\pagefile.sys".to_string(),
+            ],
+            ..Default::default()
+        },
+        LogicalBackend::new(&drive_c).with_volume('D', &drive_d).with_volume('F', &drive_f),
+    )
+    .with_volumes(Drives);
+    assert_eq!(
+        vec![
+            r"C:\$Extend\$UsnJrnl:$J".to_string(),
+            r"C:\$Extend\$UsnJrnl:$MAX".to_string(),
+            r"C:\$Recycle.Bin\S-1-5-21-1001\$IABC.txt".to_string(),
+            r"C:\pagefile.sys".to_string(),
+            r"D:\$Extend\$UsnJrnl:$J".to_string(),
+            r"D:\$Extend\$UsnJrnl:$MAX".to_string(),
+            r"D:\$Recycle.Bin\$IDEF.txt".to_string(),
+            r"F:\$Recycle.Bin\$IGHI.txt".to_string(),
+        ],
+        collector.prepare_paths_to_collect()
+    );
 }
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+/// Number of UTF-16 code units mapped by the NTFS `$UpCase` file
+const UPCASE_ENTRIES: usize = 0x10000;
+
+/// Uppercase mapping used to compare names the way Windows does. NTFS volumes store their own
+/// table in `$UpCase`. Without it, characters are mapped with the Unicode simple uppercase mapping,
+/// leaving the ones whose uppercase takes more than one character (`ß`) unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct UpCase {
+    table: Option<Arc<Vec<u16>>>,
+}
+
+impl UpCase {
+    /// Loads the content of the NTFS `$UpCase` file
+    pub fn from_ntfs_table(data: &[u8]) -> Option<Self> {
+        if data.len() < UPCASE_ENTRIES * 2 {
+            return None;
+        }
+        let table = data[..UPCASE_ENTRIES * 2]
+            .chunks_exact(2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]))
+            .collect();
+        Some(Self {
+            table: Some(Arc::new(table)),
+        })
+    }
+
+    pub fn upcase(&self, c: char) -> char {
+        if let Some(table) = &self.table {
+            let code = c as u32;
+            if code < UPCASE_ENTRIES as u32 {
+                return char::from_u32(table[code as usize] as u32).unwrap_or(c);
+            }
+            return c;
+        }
+        let mut upper = c.to_uppercase();
+        match (upper.next(), upper.next()) {
+            (Some(v), None) => v,
+            _ => c,
+        }
+    }
+
+    pub fn upcase_str(&self, value: &str) -> String {
+        value.chars().map(|c| self.upcase(c)).collect()
+    }
+
+    /// Compares two names ignoring case
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        let mut a = a.chars();
+        let mut b = b.chars();
+        loop {
+            match (a.next(), b.next()) {
+                (None, None) => return true,
+                (Some(x), Some(y)) if x == y || self.upcase(x) == self.upcase(y) => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+
+    #[test]
+    fn should_compare_names_ignoring_case() {
+        let upcase = UpCase::default();
+        assert!(upcase.eq("NTUser.DAT", "NTUSER.DAT"));
+        assert!(upcase.eq("LogFiles", "Logfiles"));
+        assert!(upcase.eq("ÁRBOL.txt", "árbol.TXT"));
+        assert!(!upcase.eq("straße", "STRASSE"));
+        assert!(!upcase.eq("SYSTEM", "SYSTEM.LOG1"));
+
+        // A table that only maps 'a' keeps 'b' distinct from 'B'
+        let mut data: Vec<u8> = (0..UPCASE_ENTRIES as u32).flat_map(|v| (v as u16).to_le_bytes()).collect();
+        data[b'a' as usize * 2] = b'A';
+        let upcase = UpCase::from_ntfs_table(&data).unwrap();
+        assert!(upcase.eq("a", "A"));
+        assert!(!upcase.eq("b", "B"));
+        assert!(UpCase::from_ntfs_table(&data[..100]).is_none());
+    }
+}
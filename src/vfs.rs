@@ -0,0 +1,554 @@
+//! forensic-rs `VirtualFileSystem` implementations, so the artifact parsers of ForensicRS can read
+//! files through this crate.
+
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use forensic_rs::{
+    prelude::{ForensicError, ForensicResult},
+    traits::vfs::{VDirEntry, VFileType, VMetadata, VirtualFile, VirtualFileSystem},
+};
+
+use zip::ZipArchive;
+
+use crate::{
+    helpers::{dos_datetime_to_filetime, filetime_to_unix_seconds},
+    ntfs::{split_stream_name, NtfsStream, NtfsVolume, ATTR_DATA},
+    upcase::UpCase,
+};
+
+/// File system that reads the files of an NTFS volume from its clusters, without the file system
+/// driver, so files locked by the running system can be read. The drive letter of the paths is
+/// ignored and alternate data streams are accessed with `file:stream`.
+pub struct NtfsFileSystem<R: Read + Seek> {
+    volume: Arc<Mutex<NtfsVolume<R>>>,
+    live: bool,
+}
+
+impl<R: Read + Seek + 'static> NtfsFileSystem<R> {
+    pub fn new(reader: R) -> ForensicResult<Self> {
+        Ok(Self {
+            volume: Arc::new(Mutex::new(NtfsVolume::new(reader)?)),
+            live: false,
+        })
+    }
+
+    fn locate(&self, path: &Path) -> ForensicResult<(VMetadata, Option<NtfsStream>)> {
+        let path = path.to_string_lossy();
+        let (path, stream_name) = split_stream_name(&path);
+        let mut volume = lock_volume(&self.volume)?;
+        let number = volume.find(path)?;
+        let record = volume.record(number)?;
+        let times = record.times();
+        let mut metadata = VMetadata {
+            created: filetime_to_unix_seconds(times.created),
+            accessed: filetime_to_unix_seconds(times.accessed),
+            modified: filetime_to_unix_seconds(times.modified),
+            file_type: VFileType::Directory,
+            size: 0,
+        };
+        if record.is_directory() && stream_name.is_empty() {
+            return Ok((metadata, None));
+        }
+        let stream = volume.stream(&record, ATTR_DATA, stream_name)?;
+        metadata.file_type = VFileType::File;
+        metadata.size = stream.size();
+        Ok((metadata, Some(stream)))
+    }
+
+    fn locate_file(&self, path: &Path) -> ForensicResult<(VMetadata, NtfsStream)> {
+        match self.locate(path)? {
+            (metadata, Some(stream)) => Ok((metadata, stream)),
+            (_, None) => Err(ForensicError::Other(format!("{} is a directory", path.to_string_lossy()))),
+        }
+    }
+}
+
+/// NTFS file system of a mounted volume of the running system. Requires elevated privileges.
+#[cfg(windows)]
+impl NtfsFileSystem<crate::volume::AlignedReader<std::fs::File>> {
+    pub fn live(drive: &str) -> ForensicResult<Self> {
+        let mut fs = Self::new(crate::volume::open_live_volume(drive)?)?;
+        fs.live = true;
+        Ok(fs)
+    }
+}
+
+fn lock_volume<R: Read + Seek>(
+    volume: &Mutex<NtfsVolume<R>>,
+) -> ForensicResult<std::sync::MutexGuard<'_, NtfsVolume<R>>> {
+    volume
+        .lock()
+        .map_err(|_| ForensicError::Other("The NTFS volume lock is poisoned".to_string()))
+}
+
+impl<R: Read + Seek + 'static> VirtualFileSystem for NtfsFileSystem<R> {
+    fn from_file(&self, file: Box<dyn VirtualFile>) -> ForensicResult<Box<dyn VirtualFileSystem>> {
+        Ok(Box::new(NtfsFileSystem::new(file)?))
+    }
+
+    fn from_fs(&self, _fs: Box<dyn VirtualFileSystem>) -> ForensicResult<Box<dyn VirtualFileSystem>> {
+        Err(ForensicError::Other(
+            "An NTFS file system can only be opened from a volume file".to_string(),
+        ))
+    }
+
+    fn read_to_string(&mut self, path: &Path) -> ForensicResult<String> {
+        String::from_utf8(self.read_all(path)?).map_err(|_| ForensicError::bad_format_str("The file is not UTF-8"))
+    }
+
+    fn read_all(&mut self, path: &Path) -> ForensicResult<Vec<u8>> {
+        let (_, stream) = self.locate_file(path)?;
+        lock_volume(&self.volume)?.read_stream_to_vec(&stream, u64::MAX)
+    }
+
+    fn read(&mut self, path: &Path, pos: u64, buf: &mut [u8]) -> ForensicResult<usize> {
+        let (_, stream) = self.locate_file(path)?;
+        lock_volume(&self.volume)?.read_stream(&stream, pos, buf)
+    }
+
+    fn metadata(&mut self, path: &Path) -> ForensicResult<VMetadata> {
+        Ok(self.locate(path)?.0)
+    }
+
+    fn read_dir(&mut self, path: &Path) -> ForensicResult<Vec<VDirEntry>> {
+        let path = path.to_string_lossy();
+        let mut volume = lock_volume(&self.volume)?;
+        let directory = volume.find(&path)?;
+        Ok(volume
+            .read_dir(directory)?
+            .into_iter()
+            .map(|v| {
+                if v.is_directory {
+                    VDirEntry::Directory(v.name)
+                } else {
+                    VDirEntry::File(v.name)
+                }
+            })
+            .collect())
+    }
+
+    fn is_live(&self) -> bool {
+        self.live
+    }
+
+    fn open(&mut self, path: &Path) -> ForensicResult<Box<dyn VirtualFile>> {
+        let (metadata, stream) = self.locate_file(path)?;
+        Ok(Box::new(NtfsVirtualFile {
+            volume: self.volume.clone(),
+            stream,
+            metadata,
+            position: 0,
+        }))
+    }
+
+    fn duplicate(&self) -> Box<dyn VirtualFileSystem> {
+        Box::new(NtfsFileSystem {
+            volume: self.volume.clone(),
+            live: self.live,
+        })
+    }
+}
+
+/// File opened from an `NtfsFileSystem`. It shares the volume with the file system it comes from.
+pub struct NtfsVirtualFile<R: Read + Seek> {
+    volume: Arc<Mutex<NtfsVolume<R>>>,
+    stream: NtfsStream,
+    metadata: VMetadata,
+    position: u64,
+}
+
+impl<R: Read + Seek> Read for NtfsVirtualFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut volume = lock_volume(&self.volume).map_err(|e| std::io::Error::other(e.to_string()))?;
+        let readed = volume
+            .read_stream(&self.stream, self.position, buf)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        self.position += readed as u64;
+        Ok(readed)
+    }
+}
+
+impl<R: Read + Seek> Seek for NtfsVirtualFile<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(v) => v as i64,
+            SeekFrom::End(v) => self.stream.size() as i64 + v,
+            SeekFrom::Current(v) => self.position as i64 + v,
+        };
+        if position < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+impl<R: Read + Seek> VirtualFile for NtfsVirtualFile<R> {
+    fn metadata(&self) -> ForensicResult<VMetadata> {
+        Ok(copy_metadata(&self.metadata))
+    }
+}
+
+/// `VMetadata` is not `Clone`
+fn copy_metadata(metadata: &VMetadata) -> VMetadata {
+    VMetadata {
+        created: metadata.created,
+        accessed: metadata.accessed,
+        modified: metadata.modified,
+        file_type: match metadata.file_type {
+            VFileType::File => VFileType::File,
+            VFileType::Directory => VFileType::Directory,
+            VFileType::Symlink => VFileType::Symlink,
+        },
+        size: metadata.size,
+    }
+}
+
+/// Entry of a triage archive, indexed by its Windows path
+#[derive(Debug, Clone)]
+struct ZipEntry {
+    /// Windows path with the case of the archive
+    path: String,
+    /// Index in the archive, `None` for folders without their own entry
+    index: Option<usize>,
+    directory: bool,
+    size: u64,
+    /// Seconds since the UNIX epoch
+    modified: Option<usize>,
+}
+
+/// File system over the archive generated by the `TriageCollector`. Stored paths like
+/// `C\Windows\System32\config\SYSTEM` are accessed with their original Windows path
+/// `C:\Windows\System32\config\SYSTEM`, ignoring case. Other entries, like the shadow copies in
+/// `vss\...` or the user files in `users\<SID>\...`, are accessed with their stored path. Files are
+/// decompressed in memory when opened, and the last one is kept so reading a file chunk by chunk
+/// decompresses it once.
+pub struct ZipFileSystem<R: Read + Seek> {
+    archive: Arc<Mutex<ZipArchive<R>>>,
+    entries: Arc<BTreeMap<String, ZipEntry>>,
+    /// Index and content of the last entry decompressed
+    last_entry: Mutex<Option<(usize, Arc<[u8]>)>>,
+}
+
+impl ZipFileSystem<std::fs::File> {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> ForensicResult<Self> {
+        Self::new(std::fs::File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek + 'static> ZipFileSystem<R> {
+    pub fn new(reader: R) -> ForensicResult<Self> {
+        let mut archive = ZipArchive::new(reader).map_err(|e| ForensicError::bad_format_string(format!("{:?}", e)))?;
+        let mut entries = BTreeMap::new();
+        for index in 0..archive.len() {
+            let file = archive
+                .by_index_raw(index)
+                .map_err(|e| ForensicError::bad_format_string(format!("{:?}", e)))?;
+            let path = archive_to_windows_path(file.name());
+            if path.is_empty() {
+                continue;
+            }
+            let modified = file.last_modified();
+            let modified = filetime_to_unix_seconds(dos_datetime_to_filetime(modified.datepart(), modified.timepart(), 0));
+            let mut ancestor = 0;
+            while let Some(pos) = path[ancestor..].find('\\') {
+                ancestor += pos + 1;
+                let parent = path[..ancestor - 1].to_string();
+                entries.entry(path_key(&parent)).or_insert(ZipEntry {
+                    path: parent,
+                    index: None,
+                    directory: true,
+                    size: 0,
+                    modified: None,
+                });
+            }
+            let entry = ZipEntry {
+                index: Some(index),
+                directory: file.is_dir(),
+                size: if file.is_dir() { 0 } else { file.size() },
+                modified,
+                path,
+            };
+            entries.insert(path_key(&entry.path), entry);
+        }
+        Ok(Self {
+            archive: Arc::new(Mutex::new(archive)),
+            entries: Arc::new(entries),
+            last_entry: Mutex::new(None),
+        })
+    }
+
+    fn entry(&self, path: &Path) -> ForensicResult<&ZipEntry> {
+        let key = path_key(&path.to_string_lossy());
+        match self.entries.get(&key) {
+            Some(v) => Ok(v),
+            None => Err(ForensicError::missing_string(format!(
+                "Cannot find {} in the archive",
+                path.to_string_lossy()
+            ))),
+        }
+    }
+
+    /// Decompressed content of a file, reusing the last entry decompressed
+    fn entry_data(&self, path: &Path) -> ForensicResult<(&ZipEntry, Arc<[u8]>)> {
+        let entry = self.entry(path)?;
+        let index = match (entry.directory, entry.index) {
+            (false, Some(v)) => v,
+            _ => return Err(ForensicError::Other(format!("{} is a directory", path.to_string_lossy()))),
+        };
+        let mut last_entry = self
+            .last_entry
+            .lock()
+            .map_err(|_| ForensicError::Other("The archive lock is poisoned".to_string()))?;
+        if let Some((last_index, data)) = last_entry.as_ref() {
+            if *last_index == index {
+                return Ok((entry, data.clone()));
+            }
+        }
+        let mut archive = self
+            .archive
+            .lock()
+            .map_err(|_| ForensicError::Other("The archive lock is poisoned".to_string()))?;
+        let mut file = archive
+            .by_index(index)
+            .map_err(|e| ForensicError::bad_format_string(format!("{:?}", e)))?;
+        let mut data = Vec::with_capacity(entry.size as usize);
+        file.read_to_end(&mut data)?;
+        let data: Arc<[u8]> = data.into();
+        *last_entry = Some((index, data.clone()));
+        Ok((entry, data))
+    }
+
+    fn open_entry(&self, path: &Path) -> ForensicResult<ZipVirtualFile> {
+        let (entry, data) = self.entry_data(path)?;
+        Ok(ZipVirtualFile {
+            data: Cursor::new(data),
+            metadata: zip_metadata(entry),
+        })
+    }
+}
+
+/// `C\Windows\...` is stored for `C:\Windows\...`
+fn archive_to_windows_path(name: &str) -> String {
+    let name = name.replace('/', "\\");
+    let name = name.trim_end_matches('\\');
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), None) if letter.is_ascii_alphabetic() => format!("{}:", letter),
+        (Some(letter), Some('\\')) if letter.is_ascii_alphabetic() => format!("{}:{}", letter, &name[1..]),
+        _ => name.to_string(),
+    }
+}
+
+fn path_key(path: &str) -> String {
+    UpCase::default().upcase_str(path.replace('/', "\\").trim_matches('\\'))
+}
+
+fn zip_metadata(entry: &ZipEntry) -> VMetadata {
+    VMetadata {
+        created: None,
+        accessed: None,
+        modified: entry.modified,
+        file_type: if entry.directory {
+            VFileType::Directory
+        } else {
+            VFileType::File
+        },
+        size: entry.size,
+    }
+}
+
+impl<R: Read + Seek + 'static> VirtualFileSystem for ZipFileSystem<R> {
+    fn from_file(&self, file: Box<dyn VirtualFile>) -> ForensicResult<Box<dyn VirtualFileSystem>> {
+        Ok(Box::new(ZipFileSystem::new(file)?))
+    }
+
+    fn from_fs(&self, _fs: Box<dyn VirtualFileSystem>) -> ForensicResult<Box<dyn VirtualFileSystem>> {
+        Err(ForensicError::Other(
+            "A triage archive can only be opened from a file".to_string(),
+        ))
+    }
+
+    fn read_to_string(&mut self, path: &Path) -> ForensicResult<String> {
+        String::from_utf8(self.read_all(path)?).map_err(|_| ForensicError::bad_format_str("The file is not UTF-8"))
+    }
+
+    fn read_all(&mut self, path: &Path) -> ForensicResult<Vec<u8>> {
+        Ok(self.entry_data(path)?.1.to_vec())
+    }
+
+    fn read(&mut self, path: &Path, pos: u64, buf: &mut [u8]) -> ForensicResult<usize> {
+        let (_, data) = self.entry_data(path)?;
+        let start = (pos as usize).min(data.len());
+        let readed = buf.len().min(data.len() - start);
+        buf[..readed].copy_from_slice(&data[start..start + readed]);
+        Ok(readed)
+    }
+
+    fn metadata(&mut self, path: &Path) -> ForensicResult<VMetadata> {
+        Ok(zip_metadata(self.entry(path)?))
+    }
+
+    fn read_dir(&mut self, path: &Path) -> ForensicResult<Vec<VDirEntry>> {
+        let key = path_key(&path.to_string_lossy());
+        let prefix = if key.is_empty() {
+            key
+        } else {
+            if !self.entry(path)?.directory {
+                return Err(ForensicError::Other(format!("{} is not a directory", path.to_string_lossy())));
+            }
+            format!("{}\\", key)
+        };
+        Ok(self
+            .entries
+            .range(prefix.clone()..)
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .filter(|(k, _)| !k[prefix.len()..].contains('\\'))
+            .map(|(_, v)| {
+                let name = v.path.rsplit('\\').next().unwrap_or_default().to_string();
+                if v.directory {
+                    VDirEntry::Directory(name)
+                } else {
+                    VDirEntry::File(name)
+                }
+            })
+            .collect())
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn open(&mut self, path: &Path) -> ForensicResult<Box<dyn VirtualFile>> {
+        Ok(Box::new(self.open_entry(path)?))
+    }
+
+    fn duplicate(&self) -> Box<dyn VirtualFileSystem> {
+        Box::new(ZipFileSystem {
+            archive: self.archive.clone(),
+            entries: self.entries.clone(),
+            last_entry: Mutex::new(None),
+        })
+    }
+}
+
+/// File decompressed from a triage archive
+pub struct ZipVirtualFile {
+    data: Cursor<Arc<[u8]>>,
+    metadata: VMetadata,
+}
+
+impl Read for ZipVirtualFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl Seek for ZipVirtualFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+impl VirtualFile for ZipVirtualFile {
+    fn metadata(&self) -> ForensicResult<VMetadata> {
+        Ok(copy_metadata(&self.metadata))
+    }
+}
+
+#[cfg(test)]
+mod tst {
+    use super::*;
+
+    #[test]
+    fn should_read_ntfs_volume_as_virtual_file_system() {
+        let image = crate::ntfs::test_image::build(&[
+            (r"\Windows\System32\config\SYSTEM", &[b'S'; 5000]),
+            (r"\Windows\notepad.exe", b"MZ"),
+        ]);
+        let mut fs = NtfsFileSystem::new(Cursor::new(image)).unwrap();
+
+        assert!(fs.metadata(Path::new(r"C:\Windows\System32\config\SYSTEM")).unwrap().is_file());
+        assert!(fs.metadata(Path::new(r"C:\Windows\System32")).unwrap().is_dir());
+        assert!(fs.metadata(Path::new(r"C:\Windows\System32\config\SAM")).is_err());
+        let metadata = fs.metadata(Path::new(r"C:\windows\system32\CONFIG\system")).unwrap();
+        assert_eq!(5000, metadata.size);
+        assert!(matches!(metadata.file_type, VFileType::File));
+
+        let mut names: Vec<String> = fs
+            .read_dir(Path::new(r"C:\Windows"))
+            .unwrap()
+            .into_iter()
+            .map(|v| match v {
+                VDirEntry::Directory(name) => format!("{}\\", name),
+                VDirEntry::File(name) | VDirEntry::Symlink(name) => name,
+            })
+            .collect();
+        names.sort();
+        assert_eq!(vec![r"System32\".to_string(), "notepad.exe".to_string()], names);
+
+        let mut file = fs.duplicate().open(Path::new(r"C:\Windows\System32\config\SYSTEM")).unwrap();
+        let mut content = Vec::new();
+        file.seek(SeekFrom::Start(1000)).unwrap();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(vec![b'S'; 4000], content);
+        assert_eq!(b"MZ".to_vec(), fs.read_all(Path::new(r"C:\Windows\notepad.exe")).unwrap());
+    }
+
+    #[test]
+    fn should_read_triage_archive_as_virtual_file_system() {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        zip.add_directory(r"C\Windows\System32\config", options).unwrap();
+        zip.start_file(r"C\Windows\System32\config\SYSTEM", options).unwrap();
+        zip.write_all(&[b'S'; 3000]).unwrap();
+        zip.start_file(r"C\Users\Alice\NTUSER.DAT", options).unwrap();
+        zip.write_all(b"regf").unwrap();
+        zip.start_file(r"vss\22222222-2222-2222-2222-222222222222\C\Windows\test.txt", options).unwrap();
+        zip.write_all(b"old").unwrap();
+        let zip = zip.finish().unwrap();
+
+        let mut fs = ZipFileSystem::new(zip).unwrap();
+        assert!(fs.metadata(Path::new(r"C:\Windows\System32\config\SYSTEM")).unwrap().is_file());
+        assert!(fs.metadata(Path::new(r"c:\windows\system32")).unwrap().is_dir());
+        assert!(fs.metadata(Path::new(r"C:\Users")).unwrap().is_dir());
+        assert_eq!(3000, fs.metadata(Path::new(r"C:\Windows\System32\config\system")).unwrap().size);
+        let mut chunk = [0u8; 1024];
+        assert_eq!(1024, fs.read(Path::new(r"C:\Windows\System32\config\SYSTEM"), 0, &mut chunk).unwrap());
+        assert_eq!(952, fs.read(Path::new(r"C:\Windows\System32\config\SYSTEM"), 2048, &mut chunk).unwrap());
+        assert_eq!(0, fs.read(Path::new(r"C:\Windows\System32\config\SYSTEM"), 4000, &mut chunk).unwrap());
+
+        let mut file = fs.open(Path::new(r"C:\Windows\System32\config\SYSTEM")).unwrap();
+        file.seek(SeekFrom::Start(1000)).unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(vec![b'S'; 2000], content);
+        assert_eq!(
+            "old",
+            fs.duplicate()
+                .read_to_string(Path::new(r"vss\22222222-2222-2222-2222-222222222222\C\Windows\test.txt"))
+                .unwrap()
+        );
+
+        let names: Vec<String> = fs
+            .read_dir(Path::new(r"C:\"))
+            .unwrap()
+            .into_iter()
+            .map(|v| match v {
+                VDirEntry::Directory(name) => format!("{}\\", name),
+                VDirEntry::File(name) | VDirEntry::Symlink(name) => name,
+            })
+            .collect();
+        assert_eq!(vec![r"Users\".to_string(), r"Windows\".to_string()], names);
+        assert!(fs.open(Path::new(r"C:\Windows\System32\config\SAM")).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    ops::{Deref, DerefMut},
+};
+
+use forensic_rs::prelude::{ForensicError, ForensicResult};
+
+use crate::{exfat::ExFatVolume, fat::FatVolume, ntfs::NtfsVolume};
+
+/// Size of the chunks read from the underlying device
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Raw volume devices only accept reads aligned to the sector size. This reader translates
+/// arbitrary reads into aligned chunk reads so the file system parsers can work over a device.
+pub struct AlignedReader<R: Read + Seek> {
+    inner: R,
+    sector_size: u64,
+    position: u64,
+    buffer: Vec<u8>,
+    buffer_offset: u64,
+    buffer_len: usize,
+}
+
+impl<R: Read + Seek> AlignedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_sector_size(inner, 512)
+    }
+
+    pub fn with_sector_size(inner: R, sector_size: u64) -> Self {
+        let chunk = (CHUNK_SIZE as u64).max(sector_size) / sector_size * sector_size;
+        Self {
+            inner,
+            sector_size,
+            position: 0,
+            buffer: vec![0u8; chunk as usize],
+            buffer_offset: 0,
+            buffer_len: 0,
+        }
+    }
+
+    fn fill_buffer(&mut self) -> std::io::Result<()> {
+        let aligned = self.position - self.position % self.sector_size;
+        self.inner.seek(SeekFrom::Start(aligned))?;
+        let mut filled = 0;
+        while filled < self.buffer.len() {
+            let readed = self.inner.read(&mut self.buffer[filled..])?;
+            if readed == 0 {
+                break;
+            }
+            filled += readed;
+        }
+        self.buffer_offset = aligned;
+        self.buffer_len = filled;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for AlignedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let cached = self.position >= self.buffer_offset
+            && self.position < self.buffer_offset + self.buffer_len as u64;
+        if !cached {
+            self.fill_buffer()?;
+        }
+        let start = (self.position - self.buffer_offset) as usize;
+        if start >= self.buffer_len {
+            return Ok(0);
+        }
+        let len = (self.buffer_len - start).min(buf.len());
+        buf[..len].copy_from_slice(&self.buffer[start..start + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for AlignedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(v) => v,
+            SeekFrom::End(v) => self.inner.seek(SeekFrom::End(v))?,
+            SeekFrom::Current(v) => match self.position.checked_add_signed(v) {
+                Some(v) => v,
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Seek before the start of the volume",
+                    ))
+                }
+            },
+        };
+        Ok(self.position)
+    }
+}
+
+/// Opens the raw device of a mounted volume (`C:\...` opens `\\.\C:`). Requires elevated privileges.
+#[cfg(windows)]
+pub fn open_live_volume(path: &str) -> ForensicResult<AlignedReader<std::fs::File>> {
+    let (drive_path, _) = crate::helpers::get_drive_and_disk(path)?;
+    let device = std::fs::File::open(drive_path)?;
+    Ok(AlignedReader::new(device))
+}
+
+/// Opens a raw image of a volume, such as a `dd` image of a partition
+pub fn open_volume_image<P: AsRef<std::path::Path>>(path: P) -> ForensicResult<AlignedReader<std::fs::File>> {
+    let file = std::fs::File::open(path)?;
+    Ok(AlignedReader::new(file))
+}
+
+/// File or directory found while walking a file system
+#[derive(Debug, Clone, Default)]
+pub struct RawDirEntry {
+    pub name: String,
+    pub is_directory: bool,
+    /// The entry belongs to a deleted file, its content may have been overwritten
+    pub deleted: bool,
+    pub size: u64,
+    /// FILETIME values, 0 when unknown
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+/// Read only file system parsed directly from a raw volume
+pub trait RawFileSystem {
+    /// Name of the file system: NTFS, FAT12, FAT16, FAT32 or exFAT
+    fn fs_name(&self) -> &'static str;
+    /// Lists a directory. Deleted entries are included when the file system keeps them.
+    fn list_dir(&mut self, path: &str) -> ForensicResult<Vec<RawDirEntry>>;
+    /// Opens the content of a file
+    fn open_file(&mut self, path: &str) -> ForensicResult<Box<dyn Read + '_>>;
+    /// Opens the content of a file, handing the file system over to the reader
+    fn into_file(self: Box<Self>, path: &str) -> ForensicResult<OpenedFile>;
+}
+
+/// Content of a file that does not borrow the file system it was read from
+pub struct OpenedFile {
+    pub size: u64,
+    pub reader: Box<dyn Read>,
+}
+
+/// Raw volume that can be handed to the file system parsers
+pub trait VolumeReader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> VolumeReader for T {}
+
+/// Volume used by a file reader, either borrowed or owned by the reader
+pub enum VolumeRef<'a, V> {
+    Borrowed(&'a mut V),
+    Owned(V),
+}
+
+impl<'a, V> Deref for VolumeRef<'a, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self {
+            VolumeRef::Borrowed(v) => v,
+            VolumeRef::Owned(v) => v,
+        }
+    }
+}
+
+impl<'a, V> DerefMut for VolumeRef<'a, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        match self {
+            VolumeRef::Borrowed(v) => v,
+            VolumeRef::Owned(v) => v,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystemType {
+    Ntfs,
+    Fat,
+    ExFat,
+}
+
+/// Identifies the file system of a volume from its boot sector
+pub fn detect_file_system(boot_sector: &[u8]) -> Option<FileSystemType> {
+    if boot_sector.len() < 512 {
+        return None;
+    }
+    if &boot_sector[3..11] == b"NTFS    " {
+        return Some(FileSystemType::Ntfs);
+    }
+    if &boot_sector[3..11] == b"EXFAT   " {
+        return Some(FileSystemType::ExFat);
+    }
+    if boot_sector[510..512] == [0x55, 0xAA] && crate::fat::FatBootSector::parse(boot_sector).is_ok() {
+        return Some(FileSystemType::Fat);
+    }
+    None
+}
+
+/// Identifies the file system of a raw volume reading its boot sector
+pub fn read_file_system_type<R: Read + Seek + ?Sized>(reader: &mut R) -> ForensicResult<Option<FileSystemType>> {
+    let mut boot_sector = vec![0u8; 512];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut boot_sector)?;
+    Ok(detect_file_system(&boot_sector))
+}
+
+/// Opens the file system of a raw volume, choosing the parser from the boot sector
+pub fn open_raw_file_system<R: Read + Seek + 'static>(mut reader: R) -> ForensicResult<Box<dyn RawFileSystem>> {
+    match read_file_system_type(&mut reader)? {
+        Some(FileSystemType::Ntfs) => Ok(Box::new(NtfsVolume::new(reader)?)),
+        Some(FileSystemType::Fat) => Ok(Box::new(FatVolume::new(reader)?)),
+        Some(FileSystemType::ExFat) => Ok(Box::new(ExFatVolume::new(reader)?)),
+        None => Err(ForensicError::bad_format_str("Unknown file system")),
+    }
+}
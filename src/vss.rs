@@ -0,0 +1,436 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+use forensic_rs::prelude::{ForensicError, ForensicResult};
+
+use crate::helpers::{format_guid, le_u16, le_u32, le_u64, utf16_string};
+
+/// {3808876b-c176-4e48-b7ae-04046e6cc752} as stored on disk
+pub const VSS_IDENTIFIER: [u8; 16] = [
+    0x6b, 0x87, 0x08, 0x38, 0x76, 0xc1, 0x48, 0x4e, 0xb7, 0xae, 0x04, 0x04, 0x6e, 0x6c, 0xc7, 0x52,
+];
+/// The VSS volume header is located after the NTFS boot code
+pub const VSS_HEADER_OFFSET: u64 = 0x1e00;
+/// Size of catalog, store and data blocks
+pub const VSS_BLOCK_SIZE: u64 = 0x4000;
+const BLOCK_HEADER_SIZE: usize = 128;
+const CATALOG_ENTRY_SIZE: usize = 128;
+const BLOCK_DESCRIPTOR_SIZE: usize = 32;
+
+const RECORD_VOLUME_HEADER: u32 = 1;
+const RECORD_CATALOG: u32 = 2;
+const RECORD_BLOCK_LIST: u32 = 3;
+const RECORD_STORE_HEADER: u32 = 4;
+
+const CATALOG_ENTRY_SNAPSHOT: u64 = 2;
+const CATALOG_ENTRY_STORE: u64 = 3;
+
+const BLOCK_FORWARDER: u32 = 0x01;
+const BLOCK_OVERLAY: u32 = 0x02;
+const BLOCK_NOT_USED: u32 = 0x04;
+
+/// Information of a shadow copy found in the catalog
+#[derive(Debug, Clone, Default)]
+pub struct ShadowCopyInfo {
+    pub store_id: String,
+    pub shadow_copy_id: String,
+    pub shadow_copy_set_id: String,
+    /// FILETIME of the snapshot creation
+    pub creation_time: u64,
+    pub volume_size: u64,
+    pub operating_machine: String,
+    pub service_machine: String,
+}
+
+#[derive(Debug, Clone)]
+struct BlockDescriptor {
+    relative_offset: u64,
+    store_offset: u64,
+    flags: u32,
+    bitmap: u32,
+}
+
+/// A snapshot store: the blocks that were overwritten after the snapshot was taken
+#[derive(Debug, Clone)]
+pub struct VssStore {
+    pub info: ShadowCopyInfo,
+    blocks: HashMap<u64, BlockDescriptor>,
+    overlays: HashMap<u64, BlockDescriptor>,
+}
+
+/// Catalog of the Volume Shadow Copies of an NTFS volume. Stores are ordered from oldest to newest.
+#[derive(Debug, Clone, Default)]
+pub struct VssCatalog {
+    pub stores: Vec<VssStore>,
+}
+
+#[derive(Default)]
+struct CatalogEntry {
+    info: ShadowCopyInfo,
+    block_list_offset: u64,
+    store_header_offset: u64,
+}
+
+impl VssCatalog {
+    /// Parses the VSS volume header, the catalog and the block lists of every store.
+    /// A volume without shadow copies returns an empty catalog.
+    pub fn parse<R: Read + Seek>(reader: &mut R) -> ForensicResult<Self> {
+        let mut header = vec![0u8; 512];
+        read_at(reader, VSS_HEADER_OFFSET, &mut header)?;
+        if header[0..16] != VSS_IDENTIFIER || le_u32(&header, 20) != RECORD_VOLUME_HEADER {
+            return Ok(Self::default());
+        }
+        let catalog_offset = le_u64(&header, 48);
+        if catalog_offset == 0 {
+            return Ok(Self::default());
+        }
+        let mut entries: Vec<CatalogEntry> = Vec::with_capacity(8);
+        let mut block = vec![0u8; VSS_BLOCK_SIZE as usize];
+        let mut next = catalog_offset;
+        let mut visited = Vec::with_capacity(4);
+        while next != 0 && !visited.contains(&next) {
+            visited.push(next);
+            read_at(reader, next, &mut block)?;
+            check_block_header(&block, RECORD_CATALOG)?;
+            next = le_u64(&block, 40);
+            for entry in block[BLOCK_HEADER_SIZE..].chunks_exact(CATALOG_ENTRY_SIZE) {
+                match le_u64(entry, 0) {
+                    CATALOG_ENTRY_SNAPSHOT => {
+                        let store_id = format_guid(&entry[16..32]);
+                        let info = ShadowCopyInfo {
+                            store_id: store_id.clone(),
+                            volume_size: le_u64(entry, 8),
+                            creation_time: le_u64(entry, 48),
+                            ..Default::default()
+                        };
+                        match entries.iter_mut().find(|v| v.info.store_id == store_id) {
+                            Some(v) => {
+                                v.info.volume_size = info.volume_size;
+                                v.info.creation_time = info.creation_time;
+                            }
+                            None => entries.push(CatalogEntry {
+                                info,
+                                ..Default::default()
+                            }),
+                        }
+                    }
+                    CATALOG_ENTRY_STORE => {
+                        let store_id = format_guid(&entry[16..32]);
+                        let pos = match entries.iter().position(|v| v.info.store_id == store_id) {
+                            Some(v) => v,
+                            None => {
+                                entries.push(CatalogEntry {
+                                    info: ShadowCopyInfo {
+                                        store_id,
+                                        ..Default::default()
+                                    },
+                                    ..Default::default()
+                                });
+                                entries.len() - 1
+                            }
+                        };
+                        entries[pos].block_list_offset = le_u64(entry, 8);
+                        entries[pos].store_header_offset = le_u64(entry, 32);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        entries.sort_by_key(|v| v.info.creation_time);
+        let mut stores = Vec::with_capacity(entries.len());
+        for mut entry in entries {
+            if entry.block_list_offset == 0 {
+                continue;
+            }
+            if entry.store_header_offset != 0 {
+                read_store_header(reader, entry.store_header_offset, &mut entry.info)?;
+            }
+            if entry.info.shadow_copy_id.is_empty() {
+                entry.info.shadow_copy_id = entry.info.store_id.clone();
+            }
+            let (blocks, overlays) = read_block_list(reader, entry.block_list_offset)?;
+            stores.push(VssStore {
+                info: entry.info,
+                blocks,
+                overlays,
+            });
+        }
+        Ok(Self { stores })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stores.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stores.len()
+    }
+}
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, buf: &mut [u8]) -> ForensicResult<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(buf)?;
+    Ok(())
+}
+
+fn check_block_header(block: &[u8], record_type: u32) -> ForensicResult<()> {
+    if block[0..16] != VSS_IDENTIFIER || le_u32(block, 20) != record_type {
+        return Err(ForensicError::bad_format_string(format!(
+            "Invalid VSS block, expected record type {}",
+            record_type
+        )));
+    }
+    Ok(())
+}
+
+fn read_store_header<R: Read + Seek>(reader: &mut R, offset: u64, info: &mut ShadowCopyInfo) -> ForensicResult<()> {
+    let mut block = vec![0u8; VSS_BLOCK_SIZE as usize];
+    read_at(reader, offset, &mut block)?;
+    check_block_header(&block, RECORD_STORE_HEADER)?;
+    let store = &block[BLOCK_HEADER_SIZE..];
+    info.shadow_copy_id = format_guid(&store[16..32]);
+    info.shadow_copy_set_id = format_guid(&store[32..48]);
+    let machine_size = le_u16(store, 64) as usize;
+    info.operating_machine = utf16_string(store, 66, machine_size / 2);
+    let service_offset = 66 + machine_size;
+    let service_size = le_u16(store, service_offset) as usize;
+    info.service_machine = utf16_string(store, service_offset + 2, service_size / 2);
+    Ok(())
+}
+
+type BlockMap = HashMap<u64, BlockDescriptor>;
+
+fn read_block_list<R: Read + Seek>(reader: &mut R, offset: u64) -> ForensicResult<(BlockMap, BlockMap)> {
+    let mut blocks = HashMap::with_capacity(1024);
+    let mut overlays = HashMap::new();
+    let mut block = vec![0u8; VSS_BLOCK_SIZE as usize];
+    let mut next = offset;
+    let mut visited = Vec::with_capacity(64);
+    while next != 0 && !visited.contains(&next) {
+        visited.push(next);
+        read_at(reader, next, &mut block)?;
+        check_block_header(&block, RECORD_BLOCK_LIST)?;
+        next = le_u64(&block, 40);
+        for descriptor in block[BLOCK_HEADER_SIZE..].chunks_exact(BLOCK_DESCRIPTOR_SIZE) {
+            if descriptor.iter().all(|v| *v == 0) {
+                continue;
+            }
+            let original_offset = le_u64(descriptor, 0);
+            let entry = BlockDescriptor {
+                relative_offset: le_u64(descriptor, 8),
+                store_offset: le_u64(descriptor, 16),
+                flags: le_u32(descriptor, 24),
+                bitmap: le_u32(descriptor, 28),
+            };
+            if entry.flags & BLOCK_NOT_USED != 0 {
+                continue;
+            }
+            if entry.flags & BLOCK_OVERLAY != 0 {
+                overlays.insert(original_offset, entry);
+            } else {
+                blocks.insert(original_offset, entry);
+            }
+        }
+    }
+    Ok((blocks, overlays))
+}
+
+/// Read only view of the volume as it was when a shadow copy was created
+pub struct ShadowVolume<R: Read + Seek> {
+    reader: R,
+    catalog: Arc<VssCatalog>,
+    store: usize,
+    position: u64,
+    block: Vec<u8>,
+    block_offset: Option<u64>,
+}
+
+impl<R: Read + Seek> ShadowVolume<R> {
+    /// `reader` must access the same volume the catalog was parsed from
+    pub fn new(reader: R, catalog: Arc<VssCatalog>, store: usize) -> ForensicResult<Self> {
+        if store >= catalog.stores.len() {
+            return Err(ForensicError::missing_string(format!(
+                "Shadow copy store {} does not exist",
+                store
+            )));
+        }
+        Ok(Self {
+            reader,
+            catalog,
+            store,
+            position: 0,
+            block: vec![0u8; VSS_BLOCK_SIZE as usize],
+            block_offset: None,
+        })
+    }
+
+    pub fn info(&self) -> &ShadowCopyInfo {
+        &self.catalog.stores[self.store].info
+    }
+
+    pub fn size(&self) -> u64 {
+        self.info().volume_size
+    }
+
+    /// Reads a block as seen by the store: if the store does not have a copy, newer stores
+    /// and finally the current volume are checked.
+    fn read_block(&mut self, store: usize, offset: u64, buf: &mut [u8]) -> ForensicResult<()> {
+        let catalog = self.catalog.clone();
+        let current = match catalog.stores.get(store) {
+            Some(v) => v,
+            None => return read_at(&mut self.reader, offset, buf),
+        };
+        match current.blocks.get(&offset) {
+            Some(descriptor) if descriptor.flags & BLOCK_FORWARDER != 0 => {
+                self.read_block(store + 1, descriptor.relative_offset, buf)?
+            }
+            Some(descriptor) => read_at(&mut self.reader, descriptor.store_offset, buf)?,
+            None => self.read_block(store + 1, offset, buf)?,
+        }
+        if let Some(overlay) = current.overlays.get(&offset) {
+            let mut sector = [0u8; 512];
+            for i in 0..32 {
+                if overlay.bitmap & (1 << i) == 0 {
+                    continue;
+                }
+                read_at(&mut self.reader, overlay.store_offset + i * 512, &mut sector)?;
+                let start = (i * 512) as usize;
+                buf[start..start + 512].copy_from_slice(&sector);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for ShadowVolume<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.size();
+        if self.position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_offset = self.position - self.position % VSS_BLOCK_SIZE;
+        if self.block_offset != Some(block_offset) {
+            let mut block = std::mem::take(&mut self.block);
+            let readed = self.read_block(self.store, block_offset, &mut block);
+            self.block = block;
+            if let Err(e) = readed {
+                self.block_offset = None;
+                return Err(std::io::Error::other(e.to_string()));
+            }
+            self.block_offset = Some(block_offset);
+        }
+        let start = (self.position - block_offset) as usize;
+        let len = (self.block.len() - start)
+            .min(buf.len())
+            .min((size - self.position) as usize);
+        buf[..len].copy_from_slice(&self.block[start..start + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for ShadowVolume<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(v) => v as i64,
+            SeekFrom::End(v) => self.size() as i64 + v,
+            SeekFrom::Current(v) => self.position as i64 + v,
+        };
+        if position < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the shadow copy",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tst {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn block_header(record_type: u32, next: u64) -> Vec<u8> {
+        let mut header = vec![0u8; VSS_BLOCK_SIZE as usize];
+        header[0..16].copy_from_slice(&VSS_IDENTIFIER);
+        header[16..20].copy_from_slice(&1u32.to_le_bytes());
+        header[20..24].copy_from_slice(&record_type.to_le_bytes());
+        header[40..48].copy_from_slice(&next.to_le_bytes());
+        header
+    }
+
+    /// Appends a VSS catalog with a single snapshot to `volume`. The snapshot holds `old_block`
+    /// as the content that the block at `original_offset` had before being overwritten.
+    pub(crate) fn add_snapshot(volume: &mut Vec<u8>, original_offset: u64, old_block: &[u8]) {
+        let base = (volume.len() as u64).div_ceil(VSS_BLOCK_SIZE) * VSS_BLOCK_SIZE;
+        let catalog_offset = base;
+        let store_header_offset = base + VSS_BLOCK_SIZE;
+        let block_list_offset = base + 2 * VSS_BLOCK_SIZE;
+        let data_offset = base + 3 * VSS_BLOCK_SIZE;
+        volume.resize((base + 4 * VSS_BLOCK_SIZE) as usize, 0);
+        let volume_size = volume.len() as u64;
+        let store_id = [0x11u8; 16];
+
+        let mut header = vec![0u8; 512];
+        header[0..16].copy_from_slice(&VSS_IDENTIFIER);
+        header[16..20].copy_from_slice(&1u32.to_le_bytes());
+        header[20..24].copy_from_slice(&RECORD_VOLUME_HEADER.to_le_bytes());
+        header[48..56].copy_from_slice(&catalog_offset.to_le_bytes());
+        volume[VSS_HEADER_OFFSET as usize..VSS_HEADER_OFFSET as usize + 512].copy_from_slice(&header);
+
+        let mut catalog = block_header(RECORD_CATALOG, 0);
+        let snapshot = &mut catalog[128..256];
+        snapshot[0..8].copy_from_slice(&CATALOG_ENTRY_SNAPSHOT.to_le_bytes());
+        snapshot[8..16].copy_from_slice(&volume_size.to_le_bytes());
+        snapshot[16..32].copy_from_slice(&store_id);
+        snapshot[48..56].copy_from_slice(&0x01D9_0000_0000_0000u64.to_le_bytes());
+        let store = &mut catalog[256..384];
+        store[0..8].copy_from_slice(&CATALOG_ENTRY_STORE.to_le_bytes());
+        store[8..16].copy_from_slice(&block_list_offset.to_le_bytes());
+        store[16..32].copy_from_slice(&store_id);
+        store[32..40].copy_from_slice(&store_header_offset.to_le_bytes());
+        volume[catalog_offset as usize..store_header_offset as usize].copy_from_slice(&catalog);
+
+        let mut store_header = block_header(RECORD_STORE_HEADER, 0);
+        store_header[128 + 16..128 + 32].copy_from_slice(&[0x22u8; 16]);
+        volume[store_header_offset as usize..block_list_offset as usize].copy_from_slice(&store_header);
+
+        let mut block_list = block_header(RECORD_BLOCK_LIST, 0);
+        let descriptor = &mut block_list[128..160];
+        descriptor[0..8].copy_from_slice(&original_offset.to_le_bytes());
+        descriptor[16..24].copy_from_slice(&data_offset.to_le_bytes());
+        volume[block_list_offset as usize..data_offset as usize].copy_from_slice(&block_list);
+
+        volume[data_offset as usize..data_offset as usize + old_block.len()].copy_from_slice(old_block);
+    }
+
+    #[test]
+    fn volume_without_shadow_copies_has_empty_catalog() {
+        let mut volume = Cursor::new(vec![0u8; 64 * 1024]);
+        assert!(VssCatalog::parse(&mut volume).unwrap().is_empty());
+    }
+
+    #[test]
+    fn shadow_volume_should_return_old_blocks() {
+        let mut volume = vec![0xAAu8; 4 * VSS_BLOCK_SIZE as usize];
+        add_snapshot(&mut volume, VSS_BLOCK_SIZE, &vec![0x55u8; VSS_BLOCK_SIZE as usize]);
+        let mut reader = Cursor::new(volume.clone());
+        let catalog = Arc::new(VssCatalog::parse(&mut reader).unwrap());
+        assert_eq!(1, catalog.len());
+        assert_eq!("22222222-2222-2222-2222-222222222222", catalog.stores[0].info.shadow_copy_id);
+
+        let mut shadow = ShadowVolume::new(Cursor::new(volume), catalog, 0).unwrap();
+        let mut buf = vec![0u8; 1024];
+        shadow.seek(SeekFrom::Start(VSS_BLOCK_SIZE - 512)).unwrap();
+        shadow.read_exact(&mut buf).unwrap();
+        assert!(buf[..512].iter().all(|v| *v == 0xAA));
+        assert!(buf[512..].iter().all(|v| *v == 0x55));
+    }
+}